@@ -0,0 +1,77 @@
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// An axis-aligned bounding box.
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(a: Vec3, b: Vec3) -> Aabb {
+        Aabb {
+            min: a.min(&b),
+            max: a.max(&b),
+        }
+    }
+
+    /// A box containing nothing, which any union replaces.
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: vec3!(f32::INFINITY),
+            max: vec3!(f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(&other.min),
+            max: self.max.max(&other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn diagonal(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let d = self.diagonal();
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn contains(&self, p: &Vec3) -> bool {
+        (0..3).all(|i| p.axis(i) >= self.min.axis(i) && p.axis(i) <= self.max.axis(i))
+    }
+
+    /// Whether the ray passes through the box between `t_min` and `t_max`,
+    /// using the slab test.
+    pub fn hit(&self, ray: &Ray, mut t_min: f32, mut t_max: f32) -> bool {
+        for i in 0..3 {
+            let inv = 1. / ray.direction().axis(i);
+            let mut t0 = (self.min.axis(i) - ray.origin().axis(i)) * inv;
+            let mut t1 = (self.max.axis(i) - ray.origin().axis(i)) * inv;
+            if inv < 0. {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return false;
+            }
+        }
+
+        true
+    }
+}
@@ -0,0 +1,154 @@
+use crate::material::luminance;
+use crate::vec3::Vec3;
+
+/// The z-score of a 95% confidence interval.
+const Z_95: f32 = 1.96;
+
+/// Luminance below this is treated as this when judging relative noise, so
+/// that black pixels don't need endless samples.
+const MIN_LUMINANCE: f32 = 0.01;
+
+/// The running mean and variance of the luminance of a pixel's samples,
+/// updated one sample at a time (Welford 1962).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Welford {
+    count: u32,
+    luminance: f32,
+    /// The sum of squared differences from the mean luminance.
+    m2: f32,
+}
+
+impl Default for Welford {
+    fn default() -> Welford {
+        Welford {
+            count: 0,
+            luminance: 0.,
+            m2: 0.,
+        }
+    }
+}
+
+impl Welford {
+    pub fn add(&mut self, sample: Vec3) {
+        self.count += 1;
+        let n = self.count as f32;
+        let l = luminance(&sample);
+        let delta = l - self.luminance;
+        self.luminance += delta / n;
+        self.m2 += delta * (l - self.luminance);
+    }
+
+    pub fn to_bytes(self) -> [u8; 12] {
+        let mut bytes = [0; 12];
+        bytes[0..4].copy_from_slice(&self.count.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.luminance.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.m2.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: [u8; 12]) -> Welford {
+        let word = |i: usize| [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]];
+        Welford {
+            count: u32::from_le_bytes(word(0)),
+            luminance: f32::from_le_bytes(word(4)),
+            m2: f32::from_le_bytes(word(8)),
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// The sample variance of the luminance.
+    pub fn variance(&self) -> f32 {
+        if self.count > 1 {
+            self.m2 / (self.count - 1) as f32
+        } else {
+            0.
+        }
+    }
+
+    /// The standard error of the mean luminance, relative to the mean, or
+    /// infinite until there are two samples to estimate it from.
+    pub fn relative_noise(&self) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+
+        (self.variance() / self.count as f32).sqrt() / self.luminance.max(MIN_LUMINANCE)
+    }
+
+    /// Half the width of the 95% confidence interval of the mean luminance,
+    /// relative to the mean.
+    pub fn relative_error(&self) -> f32 {
+        Z_95 * self.relative_noise()
+    }
+}
+
+/// Decides when a pixel has had enough samples.
+pub struct AdaptiveSampling {
+    min_samples: u32,
+    max_samples: u32,
+    threshold: f32,
+}
+
+impl AdaptiveSampling {
+    /// Samples every pixel between `min_samples` and `max_samples` times,
+    /// stopping once the 95% confidence interval of its luminance is within
+    /// `threshold` of the mean, relatively.
+    pub fn new(min_samples: u32, max_samples: u32, threshold: f32) -> AdaptiveSampling {
+        AdaptiveSampling {
+            min_samples: min_samples.max(2),
+            max_samples: max_samples.max(min_samples),
+            threshold,
+        }
+    }
+
+    /// Samples every pixel exactly `samples` times.
+    pub fn fixed(samples: u32) -> AdaptiveSampling {
+        AdaptiveSampling {
+            min_samples: samples,
+            max_samples: samples,
+            threshold: 0.,
+        }
+    }
+
+    pub fn min_samples(&self) -> u32 {
+        self.min_samples
+    }
+
+    pub fn max_samples(&self) -> u32 {
+        self.max_samples
+    }
+
+    pub fn is_done(&self, pixel: &Welford) -> bool {
+        pixel.count() >= self.max_samples
+            || (pixel.count() >= self.min_samples && pixel.relative_error() <= self.threshold)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_welford_matches_two_pass() {
+        let samples: Vec<Vec3> = (0..100).map(|i| vec3!(((i * 37) % 11) as f32 / 3.)).collect();
+        let mut pixel = Welford::default();
+        samples.iter().for_each(|sample| pixel.add(*sample));
+
+        let n = samples.len() as f32;
+        let mean = samples.iter().map(luminance).sum::<f32>() / n;
+        let variance = samples.iter().map(|s| (luminance(s) - mean).powi(2)).sum::<f32>() / (n - 1.);
+
+        assert!((pixel.luminance - mean).abs() < 1e-4);
+        assert!((pixel.variance() - variance).abs() < 1e-3 * variance);
+
+        let sampling = AdaptiveSampling::new(4, 1000, 0.05);
+        assert!(!sampling.is_done(&pixel));
+
+        let mut flat = Welford::default();
+        (0..4).for_each(|_| flat.add(vec3!(0.5)));
+        assert!(sampling.is_done(&flat));
+    }
+}
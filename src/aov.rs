@@ -0,0 +1,258 @@
+use std::io;
+
+use crate::image::Image;
+use crate::material::luminance;
+use crate::vec3::Vec3;
+
+/// The most light groups written. Lights past the last group share it.
+const MAX_LIGHT_GROUPS: usize = 16;
+
+/// Layers holding IDs, which are kept from one sample rather than averaged.
+const ID_LAYERS: [&str; 2] = ["object", "material"];
+
+/// What a camera sample found besides its color, for compositing. The
+/// surface layers describe the first surface the ray hits, and stay zero if
+/// it misses.
+pub struct Aovs {
+    pub albedo: Vec3,
+    /// The shading normal, in world space.
+    pub normal: Vec3,
+    /// The distance from the camera.
+    pub depth: f32,
+    pub position: Vec3,
+    pub object: Option<usize>,
+    /// The index of the material, numbered when the scene was built.
+    pub material: Option<usize>,
+    /// Light that reached the camera after at most one bounce.
+    pub direct: Vec3,
+    pub indirect: Vec3,
+    /// The light from each of the scene's lights.
+    pub lights: Vec<Vec3>,
+}
+
+impl Aovs {
+    pub fn new(lights: usize) -> Aovs {
+        Aovs {
+            albedo: vec3!(0.),
+            normal: vec3!(0.),
+            depth: 0.,
+            position: vec3!(0.),
+            object: None,
+            material: None,
+            direct: vec3!(0.),
+            indirect: vec3!(0.),
+            lights: vec![vec3!(0.); lights],
+        }
+    }
+
+    /// Empties the AOVs for the next sample.
+    pub fn clear(&mut self) {
+        let mut lights = std::mem::take(&mut self.lights);
+        lights.iter_mut().for_each(|light| *light = vec3!(0.));
+        *self = Aovs {
+            lights,
+            ..Aovs::new(0)
+        };
+    }
+}
+
+/// Averages the AOVs of every sample through each pixel, keeping a layer
+/// for each of them. Object and material IDs aren't filtered: each pixel
+/// keeps the raw ID its first sample found, or -1 if it missed, so that
+/// they can be matched exactly.
+pub struct AovFilm {
+    width: usize,
+    height: usize,
+    layers: Vec<(String, Image)>,
+    samples: Vec<u32>,
+    /// The sums of the luminance of the image and its square, to estimate
+    /// how noisy each pixel is.
+    moments: Vec<[f32; 2]>,
+}
+
+impl AovFilm {
+    pub fn new(width: usize, height: usize, lights: usize) -> AovFilm {
+        let mut names: Vec<String> = [
+            "albedo", "normal", "depth", "position", "object", "material", "direct", "indirect",
+        ]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+
+        let groups = lights.min(MAX_LIGHT_GROUPS);
+        for group in 0..groups {
+            names.push(if group + 1 == MAX_LIGHT_GROUPS && lights > MAX_LIGHT_GROUPS {
+                String::from("light_others")
+            } else {
+                format!("light_{}", group)
+            });
+        }
+
+        AovFilm {
+            width,
+            height,
+            layers: names.into_iter().map(|name| (name, Image::new(width, height))).collect(),
+            samples: vec![0; width * height],
+            moments: vec![[0.; 2]; width * height],
+        }
+    }
+
+    /// Adds a sample through the pixel `x` across and `y` down from the top
+    /// left.
+    pub fn add(&mut self, x: usize, y: usize, aovs: &Aovs) {
+        let id = |id: Option<usize>| vec3!(id.map_or(-1., |id| id as f32));
+
+        let mut values = vec![
+            aovs.albedo,
+            aovs.normal,
+            vec3!(aovs.depth),
+            aovs.position,
+            id(aovs.object),
+            id(aovs.material),
+            aovs.direct,
+            aovs.indirect,
+        ];
+
+        let groups = self.layers.len() - values.len();
+        let mut lights = vec![vec3!(0.); groups];
+        for (i, light) in aovs.lights.iter().enumerate() {
+            lights[i.min(groups - 1)] += *light;
+        }
+        values.extend(lights);
+
+        let index = y * self.width + x;
+        for ((name, layer), value) in self.layers.iter_mut().zip(values) {
+            if !ID_LAYERS.contains(&name.as_str()) {
+                layer.set(x, y, layer.get(x, y) + value);
+            } else if self.samples[index] == 0 {
+                layer.set(x, y, value);
+            }
+        }
+        let l = luminance(&(aovs.direct + aovs.indirect));
+        self.samples[index] += 1;
+        self.moments[index][0] += l;
+        self.moments[index][1] += l * l;
+    }
+
+    /// The variance of each pixel's luminance, divided by its number of
+    /// samples to give the variance of the pixel itself.
+    pub fn variance(&self) -> Image {
+        let mut image = Image::new(self.width, self.height);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = y * self.width + x;
+                let n = self.samples[index] as f32;
+                if n > 1. {
+                    let [sum, squares] = self.moments[index];
+                    let variance = (squares - sum * sum / n).max(0.) / (n - 1.);
+                    image.set(x, y, vec3!(variance / n));
+                }
+            }
+        }
+
+        image
+    }
+
+    /// The average of the layer called `name` over each pixel's samples, or
+    /// for IDs, the first sample's.
+    pub fn layer(&self, name: &str) -> Option<Image> {
+        let (_, layer) = self.layers.iter().find(|(layer, _)| layer == name)?;
+        let filtered = !ID_LAYERS.contains(&name);
+        let mut image = Image::new(self.width, self.height);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let samples = if filtered { self.samples[y * self.width + x].max(1) } else { 1 };
+                image.set(x, y, layer.get(x, y) / samples as f32);
+            }
+        }
+
+        Some(image)
+    }
+
+    /// Writes every layer to `<prefix>_<layer>.exr`, passing it through
+    /// `frame` first.
+    pub fn write(&self, prefix: &str, frame: impl Fn(Image) -> Image) -> io::Result<()> {
+        for (name, _) in self.layers.iter() {
+            if let Some(image) = self.layer(name) {
+                frame(image).write_exr(format!("{}_{}.exr", prefix, name))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::rc::Rc;
+
+    use crate::integrator::{Integrator, PathTracer};
+    use crate::light::{Light, PointLight};
+    use crate::light_sampler::UniformLightSampler;
+    use crate::material::Lambertian;
+    use crate::objects::{HittableList, Quad};
+    use crate::ray::Ray;
+    use crate::scene::{add_area_light, Scene};
+    use crate::sky::GradientSky;
+
+    #[test]
+    fn test_light_paths_add_up_to_image() {
+        let mut objects = HittableList::new();
+        let mut lights: Vec<Box<dyn Light>> = vec![];
+        let ground = Rc::new(Lambertian::new(vec3!(0.5)));
+        objects.add(Box::new(Quad::new(vec3!(-2., 0., -2.), vec3!(0., 0., 4.), vec3!(4., 0., 0.), ground)));
+        add_area_light(&mut objects, &mut lights, vec3!(4.), |material| {
+            Quad::new(vec3!(-0.5, 2., -0.5), vec3!(1., 0., 0.), vec3!(0., 0., 1.), material)
+        });
+        lights.push(Box::new(PointLight::new(vec3!(1., 1., 0.), vec3!(2.))));
+        lights.push(Box::new(GradientSky));
+        let sampler = Box::new(UniformLightSampler::new(&lights));
+        let scene = Scene::new(Box::new(objects), lights, sampler);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut aovs = Aovs::new(scene.lights.len());
+        let mut totals = vec![vec3!(0.); scene.lights.len()];
+        for spectral in [false, true].iter() {
+            let tracer = PathTracer::new(*spectral);
+            for i in 0..200 {
+                // Rays at the ground, the area light and the sky.
+                let target = vec3!(i as f32 / 50. - 2., (i % 3) as f32, -1.);
+                let ray = Ray::new(vec3!(0., 1., 3.), target - vec3!(0., 1., 3.));
+                aovs.clear();
+                let color = tracer.color_with_aovs(ray, &scene, &mut rng, &mut aovs);
+
+                let tolerance = 1e-4 * (1. + color.length());
+                assert!((aovs.direct + aovs.indirect - color).length() < tolerance);
+                let groups = aovs.lights.iter().fold(vec3!(0.), |sum, light| sum + *light);
+                assert!((groups - color).length() < tolerance);
+                totals.iter_mut().zip(aovs.lights.iter()).for_each(|(total, light)| *total += *light);
+            }
+        }
+        assert!(totals.iter().all(|total| total.x > 0.));
+    }
+
+    #[test]
+    fn test_ids_are_not_filtered() {
+        let mut film = AovFilm::new(2, 1, 1);
+        let mut aovs = Aovs::new(1);
+        aovs.albedo = vec3!(1.);
+        aovs.object = Some(7);
+        aovs.material = Some(3);
+        film.add(0, 0, &aovs);
+        aovs.clear();
+        aovs.object = Some(2);
+        film.add(0, 0, &aovs);
+        aovs.clear();
+        film.add(1, 0, &aovs);
+
+        assert_eq!(film.layer("albedo").unwrap().get(0, 0), vec3!(0.5));
+        assert_eq!(film.layer("object").unwrap().get(0, 0), vec3!(7.));
+        assert_eq!(film.layer("material").unwrap().get(0, 0), vec3!(3.));
+        assert_eq!(film.layer("object").unwrap().get(1, 0), vec3!(-1.));
+    }
+}
@@ -0,0 +1,714 @@
+use rand::{Rng, RngCore};
+use std::f32::consts::PI;
+
+use crate::aabb::Aabb;
+use crate::camera::Camera;
+use crate::integrator::{Integrator, Splat};
+use crate::light_sampler::{LightSampler, PowerLightSampler};
+use crate::objects::HitRecord;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::{Channels, SampledWavelengths, Spectrum};
+use crate::vec3::Vec3;
+
+/// The most bounces a path made by joining two subpaths can have.
+const MAX_DEPTH: usize = 10;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Camera,
+    Light,
+    Surface,
+}
+
+/// A point on a camera or light subpath.
+struct Vertex {
+    kind: Kind,
+    p: Vec3,
+    /// The geometric normal, or zero for points that aren't on a surface.
+    n: Vec3,
+    record: Option<HitRecord>,
+    /// The ray that arrived at the vertex, heading the way the subpath goes.
+    ray_in: Ray,
+    /// The throughput of the subpath up to the vertex, divided by the
+    /// density of sampling it.
+    beta: Spectrum,
+    /// The area densities of sampling the vertex from the one before it, and
+    /// from the one after it if the subpath were traced the other way.
+    pdf_fwd: f32,
+    pdf_rev: f32,
+    /// Whether the subpath scattered specularly here, so that it can't be
+    /// joined to another.
+    delta: bool,
+    /// The light the vertex lies on.
+    light: Option<usize>,
+    /// Whether the vertex stands for light at infinity, with `p` a point
+    /// along the direction toward it.
+    infinite: bool,
+}
+
+impl Vertex {
+    /// The area density at `next` of sampling the direction toward it from
+    /// here with the solid angle density `pdf`.
+    fn convert_density(&self, pdf: f32, next: &Vertex) -> f32 {
+        if next.infinite {
+            return pdf;
+        }
+
+        let w = next.p - self.p;
+        let distance2 = w.squared_length();
+        if distance2 <= 0. {
+            return 0.;
+        }
+
+        let mut pdf = pdf / distance2;
+        if next.n != vec3!(0.) {
+            pdf *= next.n.dot(&(w / distance2.sqrt())).abs();
+        }
+        pdf
+    }
+
+    /// The BSDF times the cosine for light scattering here between the
+    /// previous vertex and the unit direction `wi`.
+    fn eval(&self, wi: &Vec3) -> Vec3 {
+        match &self.record {
+            Some(record) => record.material.eval(&self.ray_in, record, wi),
+            None => vec3!(0.),
+        }
+    }
+}
+
+/// Bidirectional path tracing (Veach 1997), which traces one subpath from
+/// the camera and one from a light and joins every prefix of one to every
+/// prefix of the other, weighting the paths with the balance heuristic.
+/// Light that reaches the camera only through specular surfaces, like
+/// caustics seen on a diffuse floor, is found far more often than by tracing
+/// from the camera alone.
+///
+/// Light subpaths are also joined straight to the lens of a camera that
+/// allows it, and that light is splatted wherever it lands on the image.
+/// Caustics seen directly, which the camera subpath can't join to a light
+/// at all, are only found this way.
+pub struct Bdpt {
+    /// Light subpaths start from lights picked by power, the same way from
+    /// everywhere in the scene.
+    sampler: PowerLightSampler,
+    bounds: Aabb,
+    radius: f32,
+    spectral: bool,
+}
+
+impl Bdpt {
+    pub fn new(scene: &Scene, spectral: bool) -> Bdpt {
+        let bounds = scene.world.bounding_box();
+
+        Bdpt {
+            sampler: PowerLightSampler::new(&scene.lights),
+            radius: (0.5 * bounds.diagonal().length()).max(1e-3),
+            bounds,
+            spectral,
+        }
+    }
+
+    fn camera_subpath(
+        &self,
+        scene: &Scene,
+        camera: Option<&dyn Camera>,
+        ray: Ray,
+        channels: &mut Channels,
+        rng: &mut dyn RngCore,
+    ) -> Vec<Vertex> {
+        // A camera that light can't reach is marked as specular, so that no
+        // path is weighted as if it could have been joined to the lens.
+        let pdf = camera.and_then(|camera| camera.pdf_direction(&ray));
+        let mut path = vec![Vertex {
+            kind: Kind::Camera,
+            p: *ray.origin(),
+            n: vec3!(0.),
+            record: None,
+            ray_in: ray,
+            beta: Spectrum::splat(1.),
+            pdf_fwd: 1.,
+            pdf_rev: 0.,
+            delta: pdf.is_none(),
+            light: None,
+            infinite: false,
+        }];
+
+        let pdf = pdf.unwrap_or(1.);
+        self.random_walk(scene, ray, Spectrum::splat(1.), pdf, &mut path, MAX_DEPTH + 2, true, channels, rng);
+        path
+    }
+
+    fn light_subpath(&self, scene: &Scene, channels: &mut Channels, rng: &mut dyn RngCore) -> Vec<Vertex> {
+        let mut path = vec![];
+
+        let (index, pmf) = match self.sampler.pick(&vec3!(0.), rng.gen()) {
+            Some(picked) => picked,
+            None => return path,
+        };
+        let light = &scene.lights[index];
+        let sample = match light.sample_emission(&self.bounds, rng) {
+            Some(sample) if sample.pdf_position > 0. && sample.pdf_direction > 0. => sample,
+            _ => return path,
+        };
+
+        let ray = sample.ray.with_wavelength(channels.hero());
+        let direction = ray.direction().unit_vector();
+        let infinite = light.is_distant();
+
+        path.push(Vertex {
+            kind: Kind::Light,
+            p: *ray.origin(),
+            n: sample.normal,
+            record: None,
+            ray_in: ray,
+            beta: channels.spectrum_of(&sample.radiance),
+            pdf_fwd: pmf * sample.pdf_position,
+            pdf_rev: 0.,
+            delta: false,
+            light: Some(index),
+            infinite,
+        });
+
+        let cosine = sample.normal.dot(&direction).abs();
+        let beta = channels.spectrum_of(&(sample.radiance * cosine / (pmf * sample.pdf_position * sample.pdf_direction)));
+        self.random_walk(scene, ray, beta, sample.pdf_direction, &mut path, MAX_DEPTH + 1, false, channels, rng);
+
+        // Rays from lights at infinity start on a disk, which is the density
+        // of the first hit, and the light itself is picked by direction.
+        if infinite {
+            if let Some(first) = path.get_mut(1) {
+                first.pdf_fwd = sample.pdf_position;
+                if first.n != vec3!(0.) {
+                    first.pdf_fwd *= first.n.dot(&direction).abs();
+                }
+            }
+            path[0].pdf_fwd = self.infinite_density(scene, &direction);
+        }
+
+        path
+    }
+
+    /// Extends `path` by scattering `ray` through the scene, until it has
+    /// `max_vertices` or the ray is absorbed. Camera subpaths end with a
+    /// vertex at infinity when they escape.
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(
+        &self,
+        scene: &Scene,
+        mut ray: Ray,
+        mut beta: Spectrum,
+        pdf: f32,
+        path: &mut Vec<Vertex>,
+        max_vertices: usize,
+        camera: bool,
+        channels: &mut Channels,
+        rng: &mut dyn RngCore,
+    ) {
+        let mut pdf_fwd = pdf;
+
+        while path.len() < max_vertices && !beta.is_black() {
+            let record = match scene.hit(&ray, 0.001, f32::MAX) {
+                Some(record) => record,
+                None => {
+                    if camera {
+                        let direction = ray.direction().unit_vector();
+                        path.push(Vertex {
+                            kind: Kind::Light,
+                            p: *ray.origin() + direction,
+                            n: -direction,
+                            record: None,
+                            ray_in: ray,
+                            beta,
+                            pdf_fwd,
+                            pdf_rev: 0.,
+                            delta: false,
+                            light: None,
+                            infinite: true,
+                        });
+                    }
+                    break;
+                }
+            };
+
+            let opacity = record.material.opacity(&record);
+            if opacity < 1. && rng.gen::<f32>() >= opacity {
+                ray = Ray::new(record.p, *ray.direction()).with_wavelength(ray.wavelength());
+                continue;
+            }
+
+            let mut vertex = Vertex {
+                kind: Kind::Surface,
+                p: record.p,
+                n: record.normal.unit_vector(),
+                light: record.light,
+                record: None,
+                ray_in: ray,
+                beta,
+                pdf_fwd: 0.,
+                pdf_rev: 0.,
+                delta: false,
+                infinite: false,
+            };
+            vertex.pdf_fwd = path[path.len() - 1].convert_density(pdf_fwd, &vertex);
+
+            let scatter = if path.len() + 1 < max_vertices {
+                record.material.scatter(&ray, &record, rng)
+            } else {
+                None
+            };
+            let dispersive = record.material.dispersive();
+            vertex.record = Some(record);
+            path.push(vertex);
+
+            let scatter = match scatter {
+                Some(scatter) => scatter,
+                None => break,
+            };
+
+            let n = path.len();
+            let record = path[n - 1].record.as_ref().unwrap();
+            let direction = scatter.ray.direction().unit_vector();
+            let pdf_rev = if scatter.specular {
+                pdf_fwd = 0.;
+                0.
+            } else {
+                pdf_fwd = record.material.pdf(&ray, record, &direction);
+                let reversed = Ray::new(record.p + direction, -direction).with_wavelength(ray.wavelength());
+                record.material.pdf(&reversed, record, &-ray.direction().unit_vector())
+            };
+
+            if !scatter.specular && pdf_fwd <= 0. {
+                break;
+            }
+
+            if dispersive {
+                channels.terminate_secondary();
+            }
+
+            beta *= channels.spectrum_of(&scatter.attenuation);
+            ray = scatter.ray.with_wavelength(channels.hero());
+            path[n - 1].delta = scatter.specular;
+            path[n - 2].pdf_rev = path[n - 1].convert_density(pdf_rev, &path[n - 2]);
+        }
+    }
+
+    /// The light seen along `ray`, splatting what is joined straight to the
+    /// lens of `camera` to where it lands. Without a camera, paths always
+    /// have a camera vertex past the lens.
+    fn trace(
+        &self,
+        ray: Ray,
+        scene: &Scene,
+        camera: Option<&dyn Camera>,
+        rng: &mut dyn RngCore,
+        splats: &mut Vec<Splat>,
+    ) -> Vec3 {
+        let mut channels = if self.spectral {
+            Channels::Spectral(SampledWavelengths::sample_uniform(rng.gen()))
+        } else {
+            Channels::Rgb
+        };
+
+        let ray = ray.with_wavelength(channels.hero());
+        let camera_path = self.camera_subpath(scene, camera, ray, &mut channels, rng);
+        let light_path = self.light_subpath(scene, &mut channels, rng);
+        let mut radiance = Spectrum::splat(0.);
+
+        // A single light vertex is always sampled afresh from the camera
+        // vertex, so it is tried even if the light subpath failed. Lights
+        // seen straight from the lens are left to the camera subpath.
+        let first = if camera.is_some() { 1 } else { 2 };
+        for t in first..=camera_path.len() {
+            for s in 0..=light_path.len().max(1) {
+                if (t == 1 && s < 2) || s + t - 2 > MAX_DEPTH {
+                    continue;
+                }
+
+                let (contribution, image) = self.connect(scene, camera, &light_path, &camera_path, s, t, &channels, rng);
+                match image {
+                    Some((u, v)) => splats.push(Splat {
+                        s: u,
+                        t: v,
+                        color: channels.to_rgb(&contribution),
+                    }),
+                    None => radiance += contribution,
+                }
+            }
+        }
+
+        channels.to_rgb(&radiance)
+    }
+
+    /// The contribution of the path made of the first `s` vertices of the
+    /// light subpath and the first `t` of the camera subpath, and where on
+    /// the image it lands if it was joined straight to the lens.
+    #[allow(clippy::too_many_arguments)]
+    fn connect(
+        &self,
+        scene: &Scene,
+        camera: Option<&dyn Camera>,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        s: usize,
+        t: usize,
+        channels: &Channels,
+        rng: &mut dyn RngCore,
+    ) -> (Spectrum, Option<(f32, f32)>) {
+        let black = (Spectrum::splat(0.), None);
+        let pt = &camera_path[t - 1];
+        let mut sampled = None;
+        let mut image = None;
+
+        let contribution = if s == 0 {
+            // The camera subpath found a light by itself.
+            let emitted = match &pt.record {
+                Some(record) => record.material.emitted(&pt.ray_in, record),
+                None if pt.infinite => scene.environment(&pt.ray_in.direction().unit_vector()),
+                None => vec3!(0.),
+            };
+            pt.beta * channels.spectrum_of(&emitted)
+        } else if t == 1 {
+            // Join the end of the light subpath to a point on the lens.
+            let qs = &light_path[s - 1];
+            let sample = match camera.and_then(|camera| camera.sample_lens(&qs.p, rng)) {
+                Some(sample) if sample.pdf > 0. => sample,
+                _ => return black,
+            };
+
+            let to_lens = sample.p - qs.p;
+            let distance = to_lens.length();
+            let wi = to_lens / distance;
+            let f = qs.eval(&wi);
+            if f == vec3!(0.) {
+                return black;
+            }
+
+            let importance = sample.importance / sample.pdf;
+            sampled = Some(Vertex {
+                kind: Kind::Camera,
+                p: sample.p,
+                n: sample.normal,
+                record: None,
+                ray_in: Ray::new(sample.p, -wi),
+                beta: Spectrum::splat(importance),
+                pdf_fwd: 0.,
+                pdf_rev: 0.,
+                delta: false,
+                light: None,
+                infinite: false,
+            });
+            image = Some((sample.s, sample.t));
+
+            let transmittance = scene.transmittance(&qs.p, &wi, distance);
+            qs.beta * channels.spectrum_of(&(f * importance * transmittance))
+        } else if pt.kind != Kind::Surface {
+            return black;
+        } else if s == 1 {
+            // Sample a point on a light from the camera vertex.
+            let (index, pmf) = match self.sampler.pick(&pt.p, rng.gen()) {
+                Some(picked) => picked,
+                None => return black,
+            };
+            let light = &scene.lights[index];
+            let sample = match light.sample(&pt.p, rng) {
+                Some(sample) if sample.pdf > 0. => sample,
+                _ => return black,
+            };
+
+            let f = pt.eval(&sample.wi);
+            if f == vec3!(0.) {
+                return black;
+            }
+
+            let distance = if sample.distance.is_finite() {
+                sample.distance
+            } else {
+                2. * self.radius
+            };
+            let radiance = sample.radiance / (sample.pdf * pmf);
+            let mut vertex = Vertex {
+                kind: Kind::Light,
+                p: pt.p + distance * sample.wi,
+                n: sample.normal,
+                record: None,
+                ray_in: Ray::new(pt.p + distance * sample.wi, -sample.wi),
+                beta: channels.spectrum_of(&radiance),
+                pdf_fwd: 0.,
+                pdf_rev: 0.,
+                delta: false,
+                light: Some(index),
+                infinite: light.is_distant(),
+            };
+            vertex.pdf_fwd = self.pdf_light_origin(scene, &vertex, pt);
+            sampled = Some(vertex);
+
+            let transmittance = scene.transmittance(&pt.p, &sample.wi, sample.distance);
+            pt.beta * channels.spectrum_of(&(f * radiance * transmittance))
+        } else {
+            // Join the ends of the two subpaths.
+            let qs = &light_path[s - 1];
+            let to_light = qs.p - pt.p;
+            let distance = to_light.length();
+            let wi = to_light / distance;
+
+            let f = pt.eval(&wi) * qs.eval(&-wi);
+            if f == vec3!(0.) {
+                return black;
+            }
+
+            let transmittance = scene.transmittance(&pt.p, &wi, distance);
+            qs.beta * pt.beta * channels.spectrum_of(&(f * transmittance / (distance * distance)))
+        };
+
+        if contribution.is_black() {
+            return black;
+        }
+
+        let weight = self.mis_weight(scene, camera, light_path, camera_path, sampled.as_ref(), s, t);
+        (contribution * Spectrum::splat(weight), image)
+    }
+
+    /// The balance heuristic weight of the strategy joining `s` light
+    /// vertices to `t` camera vertices, against every other way of making
+    /// the same path. `sampled` is the vertex `connect` sampled afresh for a
+    /// single light or camera vertex.
+    #[allow(clippy::too_many_arguments)]
+    fn mis_weight(
+        &self,
+        scene: &Scene,
+        camera: Option<&dyn Camera>,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        sampled: Option<&Vertex>,
+        s: usize,
+        t: usize,
+    ) -> f32 {
+        if s + t == 2 {
+            return 1.;
+        }
+
+        let camera_vertex = |i: usize| if t == 1 { sampled.unwrap() } else { &camera_path[i] };
+        let light_vertex = |i: usize| if s == 1 { sampled.unwrap() } else { &light_path[i] };
+        let pt = camera_vertex(t - 1);
+        let pt_minus = if t > 1 { Some(&camera_path[t - 2]) } else { None };
+
+        // Emitters that no light stands for can only be found by the camera
+        // subpath.
+        if s == 0 && pt.light.is_none() && !pt.infinite {
+            return 1.;
+        }
+
+        // The densities of each vertex, updated for the new connection.
+        let densities = |v: &Vertex| (v.pdf_fwd, v.pdf_rev, v.delta);
+        let mut on_camera: Vec<(f32, f32, bool)> = (0..t).map(|i| densities(camera_vertex(i))).collect();
+        let mut on_light: Vec<(f32, f32, bool)> = (0..s).map(|i| densities(light_vertex(i))).collect();
+
+        if s > 0 {
+            let qs = light_vertex(s - 1);
+            let qs_minus = if s > 1 { Some(light_vertex(s - 2)) } else { None };
+
+            on_camera[t - 1].1 = self.pdf(scene, camera, qs, qs_minus, pt);
+            if let Some(pt_minus) = pt_minus {
+                on_camera[t - 2].1 = self.pdf(scene, camera, pt, Some(qs), pt_minus);
+            }
+            on_light[s - 1] = (on_light[s - 1].0, self.pdf(scene, camera, pt, pt_minus, qs), false);
+            if let Some(qs_minus) = qs_minus {
+                on_light[s - 2].1 = self.pdf(scene, camera, qs, Some(pt), qs_minus);
+            }
+        } else {
+            // Strategies without light vertices reach past the lens.
+            let pt_minus = pt_minus.unwrap();
+            on_camera[t - 1].1 = self.pdf_light_origin(scene, pt, pt_minus);
+            on_camera[t - 2].1 = self.pdf_light(scene, pt, pt_minus);
+        }
+        on_camera[t - 1].2 = false;
+
+        let remap = |pdf: f32| if pdf != 0. { pdf } else { 1. };
+        let mut sum = 0.;
+
+        // Strategies with fewer camera vertices, down to the lens alone.
+        let mut ratio = 1.;
+        for i in (1..t).rev() {
+            ratio *= remap(on_camera[i].1) / remap(on_camera[i].0);
+            if !on_camera[i].2 && !on_camera[i - 1].2 {
+                sum += ratio;
+            }
+        }
+
+        // Strategies with fewer light vertices, down to none.
+        ratio = 1.;
+        for i in (0..s).rev() {
+            ratio *= remap(on_light[i].1) / remap(on_light[i].0);
+            let delta_before = if i > 0 {
+                on_light[i - 1].2
+            } else {
+                light_vertex(0).light.is_some_and(|index| scene.lights[index].is_delta())
+            };
+            if !on_light[i].2 && !delta_before {
+                sum += ratio;
+            }
+        }
+
+        1. / (1. + sum)
+    }
+
+    /// The area density at `next` of sampling it from `v`, having arrived
+    /// from `prev`.
+    fn pdf(&self, scene: &Scene, camera: Option<&dyn Camera>, v: &Vertex, prev: Option<&Vertex>, next: &Vertex) -> f32 {
+        match v.kind {
+            Kind::Light => return self.pdf_light(scene, v, next),
+            Kind::Camera => {
+                let ray = Ray::new(v.p, next.p - v.p);
+                let pdf = camera.and_then(|camera| camera.pdf_direction(&ray));
+                return pdf.map_or(0., |pdf| v.convert_density(pdf, next));
+            }
+            Kind::Surface => {}
+        }
+
+        let (record, prev) = match (&v.record, prev) {
+            (Some(record), Some(prev)) => (record, prev),
+            _ => return 0.,
+        };
+
+        let ray_in = Ray::new(prev.p, v.p - prev.p).with_wavelength(v.ray_in.wavelength());
+        let wi = (next.p - v.p).unit_vector();
+        v.convert_density(record.material.pdf(&ray_in, record, &wi), next)
+    }
+
+    /// The area density at `next` of a light subpath leaving the light at
+    /// `v` toward it.
+    fn pdf_light(&self, scene: &Scene, v: &Vertex, next: &Vertex) -> f32 {
+        let w = next.p - v.p;
+        let distance2 = w.squared_length();
+        let w = w / distance2.sqrt();
+
+        let mut pdf = if v.infinite {
+            1. / (PI * self.radius * self.radius)
+        } else {
+            match v.light {
+                Some(index) => scene.lights[index].pdf_emission(&self.bounds, &v.p, &v.n, &w).1 / distance2,
+                None => return 0.,
+            }
+        };
+
+        if next.n != vec3!(0.) {
+            pdf *= next.n.dot(&w).abs();
+        }
+        pdf
+    }
+
+    /// The density of a light subpath starting at `v`, on its way to `next`.
+    fn pdf_light_origin(&self, scene: &Scene, v: &Vertex, next: &Vertex) -> f32 {
+        let w = (next.p - v.p).unit_vector();
+        if v.infinite {
+            return self.infinite_density(scene, &w);
+        }
+
+        match v.light {
+            Some(index) => {
+                let pmf = self.sampler.pmf(&v.p, index);
+                pmf * scene.lights[index].pdf_emission(&self.bounds, &v.p, &v.n, &w).0
+            }
+            None => 0.,
+        }
+    }
+
+    /// The solid angle density of light subpaths starting from the lights at
+    /// infinity heading in the unit direction `w`.
+    fn infinite_density(&self, scene: &Scene, w: &Vec3) -> f32 {
+        let center = self.bounds.center();
+
+        (0..scene.lights.len())
+            .filter(|&i| scene.lights[i].is_infinite())
+            .map(|i| self.sampler.pmf(&center, i) * scene.lights[i].pdf(&center, &-w))
+            .sum()
+    }
+}
+
+impl Integrator for Bdpt {
+    fn color(&self, ray: Ray, scene: &Scene, rng: &mut dyn RngCore) -> Vec3 {
+        self.trace(ray, scene, None, rng, &mut Vec::new())
+    }
+
+    fn color_with_splats(
+        &self,
+        ray: Ray,
+        scene: &Scene,
+        camera: &dyn Camera,
+        rng: &mut dyn RngCore,
+        splats: &mut Vec<Splat>,
+    ) -> Vec3 {
+        self.trace(ray, scene, Some(camera), rng, splats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bvh::Bvh;
+    use crate::camera::PerspectiveCamera;
+    use crate::integrator::PathTracer;
+    use crate::light::Light;
+    use crate::light_sampler::UniformLightSampler;
+    use crate::material::{Lambertian, Material};
+    use crate::objects::{HittableList, Quad, Sphere};
+    use crate::scene::add_area_light;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_matches_path_tracing() {
+        let mut list = HittableList::new();
+        let mut lights: Vec<Box<dyn Light>> = vec![];
+        let white: Rc<dyn Material> = Rc::new(Lambertian::new(vec3!(0.7)));
+        list.add(Box::new(Quad::new(vec3!(-3., 0., -3.), vec3!(6., 0., 0.), vec3!(0., 0., 6.), Rc::clone(&white))));
+        list.add(Box::new(Sphere::new(vec3!(0., 0.7, 0.), 0.7, Rc::new(Lambertian::new(vec3!(0.6, 0.3, 0.2))))));
+        add_area_light(&mut list, &mut lights, vec3!(8.), |material| {
+            Quad::new(vec3!(-0.5, 2.5, -0.5), vec3!(1., 0., 0.), vec3!(0., 0., 1.), material)
+        });
+        let sampler = Box::new(UniformLightSampler::new(&lights));
+        let scene = Scene::new(Box::new(Bvh::new(list)), lights, sampler);
+
+        let camera = PerspectiveCamera::new(vec3!(0., 1.5, 5.), vec3!(0., 0.5, 0.), vec3!(0., 1., 0.), 50., 1.5, 0.1, 5.);
+        let bdpt = Bdpt::new(&scene, false);
+        let path_tracer = PathTracer::new(false);
+
+        // The mean and its standard error of the image each renders. Light
+        // splatted anywhere on the image counts toward its mean as much as
+        // the sample it came from.
+        let samples = 200_000;
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut mean = |color: &mut dyn FnMut(Ray, &mut StdRng) -> f32| {
+            let (mut sum, mut sum2) = (0., 0.);
+            for _ in 0..samples {
+                let ray = camera.get_ray(rng.gen(), rng.gen(), &mut rng).unwrap();
+                let value = f64::from(color(ray, &mut rng));
+                sum += value;
+                sum2 += value * value;
+            }
+            let mean = sum / f64::from(samples);
+            (mean, ((sum2 / f64::from(samples) - mean * mean) / f64::from(samples)).sqrt())
+        };
+        let luminance = |color: Vec3| (color.x + color.y + color.z) / 3.;
+
+        let mut splatted = 0.;
+        let mut splats = vec![];
+        let expected = mean(&mut |ray, rng| luminance(path_tracer.color(ray, &scene, rng)));
+        let with_lens = mean(&mut |ray, rng| {
+            let color = bdpt.color_with_splats(ray, &scene, &camera, rng, &mut splats);
+            let splat: Vec3 = splats.drain(..).fold(vec3!(0.), |sum, splat| sum + splat.color);
+            splatted += luminance(splat);
+            luminance(color + splat)
+        });
+        let without_lens = mean(&mut |ray, rng| luminance(bdpt.color(ray, &scene, rng)));
+
+        assert!(splatted > 0.);
+        for (mean, error) in [with_lens, without_lens].iter() {
+            let noise = 4. * (error * error + expected.1 * expected.1).sqrt();
+            assert!((mean - expected.0).abs() < noise, "{} ± {} against {:?}", mean, error, expected);
+        }
+    }
+}
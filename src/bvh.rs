@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::aabb::Aabb;
+use crate::objects::{HitRecord, Hittable, HittableList};
+use crate::ray::Ray;
+
+/// The most objects a leaf holds before it is split.
+const LEAF_SIZE: usize = 4;
+
+/// A bounding volume hierarchy, so that rays only test the objects whose
+/// boxes they pass through.
+pub enum Bvh {
+    /// Objects along with their positions in the list the BVH was built
+    /// from and the indices of their materials, which hits are tagged with.
+    Leaf {
+        bounds: Aabb,
+        objects: Vec<(usize, usize, Box<dyn Hittable>)>,
+    },
+    Node {
+        bounds: Aabb,
+        left: Box<Bvh>,
+        right: Box<Bvh>,
+    },
+}
+
+impl Bvh {
+    /// Numbers the materials in the order they first appear in `list`, so
+    /// that they keep their numbers from one render to the next. Objects
+    /// without a single material get a number of their own.
+    pub fn new(list: HittableList) -> Bvh {
+        let mut materials = HashMap::new();
+        let objects = list
+            .into_objects()
+            .into_iter()
+            .enumerate()
+            .map(|(id, object)| {
+                let next = materials.len();
+                let material = match object.material() {
+                    Some(material) => *materials.entry(Rc::as_ptr(material) as *const ()).or_insert(next),
+                    None => *materials.entry(&*object as *const dyn Hittable as *const ()).or_insert(next),
+                };
+                (id, material, object)
+            })
+            .collect();
+
+        Bvh::build(objects)
+    }
+
+    /// Splits the objects in half at the median of their centers along the
+    /// axis where the centers are most spread out.
+    fn build(mut objects: Vec<(usize, usize, Box<dyn Hittable>)>) -> Bvh {
+        let bounds = objects
+            .iter()
+            .fold(Aabb::empty(), |bounds, (_, _, object)| bounds.union(&object.bounding_box()));
+
+        if objects.len() <= LEAF_SIZE {
+            return Bvh::Leaf { bounds, objects };
+        }
+
+        let centers = objects.iter().fold(Aabb::empty(), |centers, (_, _, object)| {
+            let center = object.bounding_box().center();
+            centers.union(&Aabb::new(center, center))
+        });
+        let axis = centers.longest_axis();
+
+        let mid = objects.len() / 2;
+        objects.select_nth_unstable_by(mid, |a, b| {
+            let a = a.2.bounding_box().center().axis(axis);
+            let b = b.2.bounding_box().center().axis(axis);
+            a.total_cmp(&b)
+        });
+        let right = objects.split_off(mid);
+
+        Bvh::Node {
+            bounds,
+            left: Box::new(Bvh::build(objects)),
+            right: Box::new(Bvh::build(right)),
+        }
+    }
+}
+
+impl Bvh {
+    /// Finds the closest hit like `hit`, adding a test for every bounding
+    /// box and object along the way to `cost`.
+    fn hit_counting(&self, ray: &Ray, t_min: f32, t_max: f32, cost: &mut usize) -> Option<HitRecord> {
+        *cost += 1;
+
+        match self {
+            Bvh::Leaf { bounds, objects } => {
+                if !bounds.hit(ray, t_min, t_max) {
+                    return None;
+                }
+
+                let mut record = None;
+                let mut closest_so_far = t_max;
+
+                for (id, material, object) in objects.iter() {
+                    *cost += 1;
+                    if let Some(mut r) = object.hit(ray, t_min, closest_so_far) {
+                        closest_so_far = r.t;
+                        r.object = *id;
+                        r.material_id = *material;
+                        record = Some(r);
+                    }
+                }
+
+                record
+            }
+            Bvh::Node { bounds, left, right } => {
+                if !bounds.hit(ray, t_min, t_max) {
+                    return None;
+                }
+
+                let left = left.hit_counting(ray, t_min, t_max, cost);
+                let closest_so_far = left.as_ref().map_or(t_max, |r| r.t);
+                right.hit_counting(ray, t_min, closest_so_far, cost).or(left)
+            }
+        }
+    }
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.hit_counting(ray, t_min, t_max, &mut 0)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match self {
+            Bvh::Leaf { bounds, .. } | Bvh::Node { bounds, .. } => *bounds,
+        }
+    }
+
+    fn hit_cost(&self, ray: &Ray, t_min: f32, t_max: f32) -> usize {
+        let mut cost = 0;
+        self.hit_counting(ray, t_min, t_max, &mut cost);
+        cost
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::material::{Lambertian, Material};
+    use crate::objects::Sphere;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn test_hits_are_tagged_in_list_order() {
+        let red: Rc<dyn Material> = Rc::new(Lambertian::new(vec3!(1., 0., 0.)));
+        let blue: Rc<dyn Material> = Rc::new(Lambertian::new(vec3!(0., 0., 1.)));
+
+        // Spheres along x, more than fit in a leaf, with the materials
+        // first used out of order along it.
+        let mut list = HittableList::new();
+        for (i, material) in [&blue, &red, &blue, &red, &red, &blue].iter().enumerate() {
+            list.add(Box::new(Sphere::new(vec3!(3. * i as f32, 0., 0.), 1., Rc::clone(material))));
+        }
+        let bvh = Bvh::new(list);
+
+        for (i, expected) in [0, 1, 0, 1, 1, 0].iter().enumerate() {
+            let ray = Ray::new(vec3!(3. * i as f32, 0., 5.), vec3!(0., 0., -1.));
+            let record = bvh.hit(&ray, 0.001, f32::MAX).unwrap();
+            assert_eq!((record.object, record.material_id), (i, *expected));
+        }
+    }
+}
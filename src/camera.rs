@@ -2,81 +2,443 @@ use crate::ray::Ray;
 use crate::vec3::Vec3;
 
 use std::f32::consts::PI;
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use rand::{Rng, RngCore};
 
-pub struct Camera {
+/// Turns a point on the image into the ray the camera sees it along. `s`
+/// runs from 0 at the left to 1 at the right, and `t` from 0 at the bottom
+/// to 1 at the top.
+pub trait Camera {
+    /// The ray through `(s, t)`, or `None` if the camera sees nothing there.
+    fn get_ray(&self, s: f32, t: f32, rng: &mut dyn RngCore) -> Option<Ray>;
+
+    /// Where the camera is, or the middle of its view if its rays don't all
+    /// start at one point.
+    fn origin(&self) -> Vec3;
+
+    /// Samples a point on the lens that sees `p`, for tracing light to the
+    /// camera. `None` if the camera doesn't see `p`, or can't be reached by
+    /// light this way at all.
+    fn sample_lens(&self, _p: &Vec3, _rng: &mut dyn RngCore) -> Option<LensSample> {
+        None
+    }
+
+    /// The solid angle density of the camera sending `ray`, which starts on
+    /// its lens, or `None` if the camera can't be reached by light.
+    fn pdf_direction(&self, _ray: &Ray) -> Option<f32> {
+        None
+    }
+}
+
+/// A point on a camera's lens that sees a point in the scene.
+pub struct LensSample {
+    pub p: Vec3,
+    /// The way the camera looks, which the lens faces.
+    pub normal: Vec3,
+    /// Where on the image the point is seen.
+    pub s: f32,
+    pub t: f32,
+    /// How much light arriving along the line counts toward the image, so
+    /// that it integrates to one over the image and lens.
+    pub importance: f32,
+    /// The solid angle density, at the point, of sampling the lens point.
+    pub pdf: f32,
+}
+
+/// A point in the unit disk, by the concentric mapping of Shirley and Chiu
+/// (1997). It always takes two numbers, so the lens keeps the sampler
+/// dimensions meant for it.
+fn random_in_unit_disk(rng: &mut dyn RngCore) -> Vec3 {
+    let a = 2. * rng.gen::<f32>() - 1.;
+    let b = 2. * rng.gen::<f32>() - 1.;
+    if a == 0. && b == 0. {
+        return vec3!(0.);
+    }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4. * (b / a))
+    } else {
+        (b, PI / 2. - PI / 4. * (a / b))
+    };
+
+    vec3!(r * theta.cos(), r * theta.sin(), 0.)
+}
+
+/// The camera's right, up and backward directions.
+fn basis(origin: Vec3, look_at: Vec3, vup: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = (origin - look_at).unit_vector();
+    let u = vup.cross(&w).unit_vector();
+    let v = w.cross(&u);
+
+    (u, v, w)
+}
+
+/// The camera's right, up and backward directions with up along `vup`, so
+/// that the horizon stays level however far up or down it looks.
+fn level_basis(origin: Vec3, look_at: Vec3, vup: Vec3) -> (Vec3, Vec3, Vec3) {
+    let v = vup.unit_vector();
+    let u = v.cross(&(origin - look_at)).unit_vector();
+    let w = u.cross(&v);
+
+    (u, v, w)
+}
+
+/// A perspective camera with a thin lens, which blurs what is away from the
+/// focus distance.
+pub struct PerspectiveCamera {
     lower_left_corner: Vec3,
     horizontal: Vec3,
     vertical: Vec3,
     origin: Vec3,
     u: Vec3,
     v: Vec3,
-    lens_radius: f32
-}
-
-fn random_in_unit_disk(rng: &mut ThreadRng) -> Vec3 {
-    let mut p;
-
-    loop {
-        let x: f32 = rng.gen();
-        let y: f32 = rng.gen();
-        p = 2. * vec3!(x, y, 0.) - vec3!(1.,1.,0.);
-
-        if p.dot(&p) < 1. {
-            break;
-        }
-    }
-
-    p
+    w: Vec3,
+    lens_radius: f32,
+    focus_dist: f32,
+    /// The area of the image one unit in front of the lens.
+    area: f32,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     pub fn new(
         origin: Vec3,
         look_at: Vec3,
         vup: Vec3,
-        vfov: f32, 
+        vfov: f32,
         aspect: f32,
         aperture: f32,
         focus_dist: f32
-    ) -> Camera {
+    ) -> PerspectiveCamera {
 
         let theta = vfov * PI / 180.;
         let half_height = (theta / 2.).tan();
         let half_width = aspect * half_height;
 
-        let w = (&origin - &look_at).unit_vector();
-        let u = vup.cross(&w).unit_vector();
-        let v = w.cross(&u);
+        let (u, v, w) = basis(origin, look_at, vup);
 
-        let lower_left_corner = 
-            &origin
-            - half_width * focus_dist * &u
-            - half_height * focus_dist  * &v
-            - focus_dist * &w;
+        let lower_left_corner =
+            origin
+            - half_width * focus_dist * u
+            - half_height * focus_dist  * v
+            - focus_dist * w;
 
-        let horizontal = 2. * half_width * focus_dist * &u;
-        let vertical = 2. * half_height * focus_dist * &v;
+        let horizontal = 2. * half_width * focus_dist * u;
+        let vertical = 2. * half_height * focus_dist * v;
 
-        Camera {
+        PerspectiveCamera {
             lower_left_corner,
             horizontal,
             vertical,
             origin,
             u,
             v,
-            lens_radius: aperture / 2.
+            w,
+            lens_radius: aperture / 2.,
+            focus_dist,
+            area: 4. * half_width * half_height,
+        }
+    }
+
+    /// The area of the lens, or one for a pinhole.
+    fn lens_area(&self) -> f32 {
+        if self.lens_radius > 0. {
+            PI * self.lens_radius * self.lens_radius
+        } else {
+            1.
         }
     }
 
-    pub fn get_ray(&self, s: f32, t: f32, rng: &mut ThreadRng) -> Ray {
+    /// Where on the image a ray from `lens` along `direction` is seen, and
+    /// the cosine between it and the way the camera looks.
+    fn image_point(&self, lens: &Vec3, direction: &Vec3) -> Option<(f32, f32, f32)> {
+        let direction = direction.unit_vector();
+        let cosine = -direction.dot(&self.w);
+        if cosine <= 0. {
+            return None;
+        }
+
+        // Every ray through a point in focus meets there.
+        let focus = *lens + self.focus_dist / cosine * direction - self.lower_left_corner;
+        let s = focus.dot(&self.horizontal) / self.horizontal.squared_length();
+        let t = focus.dot(&self.vertical) / self.vertical.squared_length();
+        if (0. ..=1.).contains(&s) && (0. ..=1.).contains(&t) {
+            Some((s, t, cosine))
+        } else {
+            None
+        }
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, s: f32, t: f32, rng: &mut dyn RngCore) -> Option<Ray> {
         let rd = self.lens_radius * random_in_unit_disk(rng);
         let offset = self.u * rd.x() + self.v * rd.y();
 
-        Ray::new(
+        Some(Ray::new(
             self.origin + offset,
-            &self.lower_left_corner + s * &self.horizontal + t * &self.vertical - &self.origin - offset,
-        )
+            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+        ))
+    }
+
+    fn origin(&self) -> Vec3 {
+        self.origin
+    }
+
+    fn sample_lens(&self, p: &Vec3, rng: &mut dyn RngCore) -> Option<LensSample> {
+        let rd = self.lens_radius * random_in_unit_disk(rng);
+        let lens = self.origin + self.u * rd.x() + self.v * rd.y();
+        let (s, t, cosine) = self.image_point(&lens, &(*p - lens))?;
+
+        // As in pbrt, the importance falls with the cosine to the fourth, as
+        // rays further out spread over less of the image.
+        Some(LensSample {
+            p: lens,
+            normal: -self.w,
+            s,
+            t,
+            importance: 1. / (self.area * self.lens_area() * cosine.powi(4)),
+            pdf: (*p - lens).squared_length() / (cosine * self.lens_area()),
+        })
+    }
+
+    fn pdf_direction(&self, ray: &Ray) -> Option<f32> {
+        let pdf = match self.image_point(ray.origin(), ray.direction()) {
+            Some((_, _, cosine)) => 1. / (self.area * cosine.powi(3)),
+            None => 0.,
+        };
+        Some(pdf)
+    }
+}
+
+/// Parallel rays from a rectangle facing `look_at`, so that things keep
+/// their size however far away they are, as in an elevation.
+pub struct OrthographicCamera {
+    origin: Vec3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    direction: Vec3,
+}
+
+impl OrthographicCamera {
+    /// A camera whose view is `height` units high, centered on `origin`.
+    pub fn new(origin: Vec3, look_at: Vec3, vup: Vec3, height: f32, aspect: f32) -> OrthographicCamera {
+        let (u, v, w) = basis(origin, look_at, vup);
+
+        OrthographicCamera {
+            origin,
+            horizontal: aspect * height * u,
+            vertical: height * v,
+            direction: -w,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, s: f32, t: f32, _rng: &mut dyn RngCore) -> Option<Ray> {
+        let origin = self.origin + (s - 0.5) * self.horizontal + (t - 0.5) * self.vertical;
+        Some(Ray::new(origin, self.direction))
+    }
+
+    fn origin(&self) -> Vec3 {
+        self.origin
+    }
+}
+
+/// An equidistant fisheye, which maps the angle from the middle of the view
+/// straight to the distance from the middle of the image. It sees a circle
+/// as high as the image, `fov` degrees across, and nothing outside it.
+pub struct FisheyeCamera {
+    origin: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    half_fov: f32,
+    aspect: f32,
+}
+
+impl FisheyeCamera {
+    pub fn new(origin: Vec3, look_at: Vec3, vup: Vec3, fov: f32, aspect: f32) -> FisheyeCamera {
+        let (u, v, w) = basis(origin, look_at, vup);
+
+        FisheyeCamera {
+            origin,
+            u,
+            v,
+            w,
+            half_fov: fov * PI / 360.,
+            aspect,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, s: f32, t: f32, _rng: &mut dyn RngCore) -> Option<Ray> {
+        let (x, y) = ((2. * s - 1.) * self.aspect, 2. * t - 1.);
+        let r = (x * x + y * y).sqrt();
+        if r > 1. {
+            return None;
+        }
+
+        let theta = r * self.half_fov;
+        let phi = y.atan2(x);
+        let direction = theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w;
+
+        Some(Ray::new(self.origin, direction))
+    }
+
+    fn origin(&self) -> Vec3 {
+        self.origin
+    }
+}
+
+/// Sees every direction, mapping longitude across the image and latitude up
+/// it, as in a 360° panorama. The middle of the image faces `look_at`, with
+/// the horizon level across it.
+pub struct EquirectangularCamera {
+    origin: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl EquirectangularCamera {
+    pub fn new(origin: Vec3, look_at: Vec3, vup: Vec3) -> EquirectangularCamera {
+        let (u, v, w) = level_basis(origin, look_at, vup);
+        EquirectangularCamera { origin, u, v, w }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, s: f32, t: f32, _rng: &mut dyn RngCore) -> Option<Ray> {
+        let longitude = 2. * PI * (s - 0.5);
+        let latitude = PI * (t - 0.5);
+        let direction = latitude.cos() * (longitude.sin() * self.u - longitude.cos() * self.w)
+            + latitude.sin() * self.v;
+
+        Some(Ray::new(self.origin, direction))
+    }
+
+    fn origin(&self) -> Vec3 {
+        self.origin
+    }
+}
+
+/// Sees every direction as the six 90° faces of a cube, laid out three
+/// across and two down: right, left and up along the top, and down, front
+/// and back along the bottom. Front faces `look_at`, with the horizon level
+/// across it.
+pub struct CubeMapCamera {
+    origin: Vec3,
+    /// The forward, right and up directions of each face, in layout order.
+    faces: [(Vec3, Vec3, Vec3); 6],
+}
+
+impl CubeMapCamera {
+    pub fn new(origin: Vec3, look_at: Vec3, vup: Vec3) -> CubeMapCamera {
+        let (u, v, w) = level_basis(origin, look_at, vup);
+
+        CubeMapCamera {
+            origin,
+            faces: [
+                (u, w, v),
+                (-u, -w, v),
+                (v, u, w),
+                (-v, u, -w),
+                (-w, u, v),
+                (w, -u, v),
+            ],
+        }
+    }
+}
+
+impl Camera for CubeMapCamera {
+    fn get_ray(&self, s: f32, t: f32, _rng: &mut dyn RngCore) -> Option<Ray> {
+        let (x, y) = (3. * s.clamp(0., 1.), 2. * (1. - t.clamp(0., 1.)));
+        let (column, row) = ((x as usize).min(2), (y as usize).min(1));
+        let (forward, right, up) = self.faces[3 * row + column];
+
+        // Where on the face, from -1 to 1 across and up.
+        let a = 2. * (x - column as f32) - 1.;
+        let b = 1. - 2. * (y - row as f32);
+
+        Some(Ray::new(self.origin, forward + a * right + b * up))
+    }
+
+    fn origin(&self) -> Vec3 {
+        self.origin
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::objects::random_unit_vector;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_cameras_look_where_expected() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let (origin, look_at, vup) = (vec3!(0., 1., 0.), vec3!(0., 0., -4.), vec3!(0., 1., 0.));
+        let direction = |camera: &dyn Camera, s: f32, t: f32, rng: &mut StdRng| {
+            camera.get_ray(s, t, rng).map(|ray| ray.direction().unit_vector())
+        };
+        let close = |a: Option<Vec3>, b: Vec3| a.is_some_and(|a| (a - b).length() < 1e-4);
+
+        // The lens always takes two numbers, so the sampler dimensions after
+        // it stay where they were meant to be.
+        let lens = PerspectiveCamera::new(origin, look_at, vup, 20., 1.5, 2., 4.);
+        for _ in 0..100 {
+            let mut skipped = rng.clone();
+            lens.get_ray(0.5, 0.5, &mut rng);
+            skipped.next_u32();
+            skipped.next_u32();
+            assert_eq!(rng.next_u32(), skipped.next_u32());
+        }
+
+        // A point in focus is seen where the lens sends rays through it,
+        // from anywhere on the lens, and the camera sends rays over the
+        // image with a total density of one.
+        let in_focus = lens.lower_left_corner + 0.3 * lens.horizontal + 0.6 * lens.vertical;
+        for _ in 0..10 {
+            let sample = lens.sample_lens(&in_focus, &mut rng).unwrap();
+            assert!((sample.s - 0.3).abs() < 1e-4 && (sample.t - 0.6).abs() < 1e-4);
+        }
+        let wide = PerspectiveCamera::new(origin, look_at, vup, 90., 1.5, 0., 4.);
+        let total: f32 = (0..100_000)
+            .map(|_| wide.pdf_direction(&Ray::new(origin, random_unit_vector(&mut rng))).unwrap())
+            .sum();
+        assert!((4. * PI * total / 100_000. - 1.).abs() < 0.05);
+
+        // Orthographic rays are parallel, spread over the view.
+        let orthographic = OrthographicCamera::new(origin, look_at, vup, 2., 1.5);
+        let corner = orthographic.get_ray(1., 1., &mut rng).unwrap();
+        assert!(close(direction(&orthographic, 0., 0., &mut rng), (look_at - origin).unit_vector()));
+        assert!(close(Some(*corner.direction()), *orthographic.get_ray(0.2, 0.7, &mut rng).unwrap().direction()));
+        assert!(((*corner.origin() - origin).length() - (1.5f32 * 1.5 + 1.).sqrt()).abs() < 1e-4);
+
+        // A 180° fisheye sees 45° to the right halfway to the edge of its
+        // circle, and nothing in the corners.
+        let (forward, right) = (vec3!(0., 0., -1.), vec3!(1., 0., 0.));
+        let fisheye = FisheyeCamera::new(origin, origin + forward, vup, 180., 1.5);
+        assert!(close(direction(&fisheye, 0.5 + 0.25 / 1.5, 0.5, &mut rng), (forward + right).unit_vector()));
+        assert!(fisheye.get_ray(0.99, 0.99, &mut rng).is_none());
+
+        // Panoramas keep the horizon level, even looking down at `look_at`.
+        let equirectangular = EquirectangularCamera::new(origin, look_at, vup);
+        assert!(close(direction(&equirectangular, 0.5, 0.5, &mut rng), forward));
+        assert!(close(direction(&equirectangular, 0.75, 0.5, &mut rng), right));
+        assert!(close(direction(&equirectangular, 0.3, 1., &mut rng), vup));
+
+        // Each face of the cube map looks along its axis from its middle.
+        let cube_map = CubeMapCamera::new(origin, look_at, vup);
+        let faces = [right, -right, vup, -vup, forward, -forward];
+        for (index, face) in faces.iter().enumerate() {
+            let (column, row) = (index % 3, index / 3);
+            let (s, t) = ((column as f32 + 0.5) / 3., 1. - (row as f32 + 0.5) / 2.);
+            assert!(close(direction(&cube_map, s, t, &mut rng), *face), "face {}", index);
+        }
+
+        // Neighbouring faces meet along their edges.
+        assert!(close(direction(&cube_map, 1. / 3. - 1e-6, 0.75, &mut rng), (right - forward).unit_vector()));
     }
 }
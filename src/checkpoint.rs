@@ -0,0 +1,134 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::adaptive::Welford;
+use crate::film::{Film, Filter};
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 2;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// A render part way through: the film so far and the statistics of every
+/// pixel's samples, which also count how many each has taken. A render
+/// resumed from it carries on with the samples it hadn't taken yet, so it
+/// comes out the same as one that was never stopped.
+pub struct Checkpoint {
+    pub film: Film,
+    /// The pixels a row at a time from the top.
+    pub pixels: Vec<Welford>,
+}
+
+impl Checkpoint {
+    pub fn new(width: usize, height: usize, filter: Filter) -> Checkpoint {
+        Checkpoint {
+            film: Film::new(width, height, filter),
+            pixels: vec![Welford::default(); width * height],
+        }
+    }
+
+    /// Reads a checkpoint of a `width` by `height` render, which must have
+    /// been made with options of the same `fingerprint`, one per line.
+    pub fn read<P: AsRef<Path>>(
+        path: P,
+        width: usize,
+        height: usize,
+        fingerprint: &str,
+        filter: Filter,
+    ) -> io::Result<Checkpoint> {
+        let data = fs::read(path)?;
+        let word = |i: usize| {
+            data.get(i..i + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .ok_or_else(|| invalid("checkpoint is truncated"))
+        };
+
+        if data.get(0..4) != Some(&MAGIC[..]) || word(4)? != VERSION {
+            return Err(invalid("not a checkpoint"));
+        }
+        if (word(8)? as usize, word(12)? as usize) != (width, height) {
+            return Err(invalid("checkpoint is of a different size"));
+        }
+
+        let film_start = 20 + word(16)? as usize;
+        let saved = data.get(20..film_start).ok_or_else(|| invalid("checkpoint is truncated"))?;
+        if saved != fingerprint.as_bytes() {
+            let saved = String::from_utf8_lossy(saved);
+            let changed: Vec<String> = saved
+                .lines()
+                .zip(fingerprint.lines())
+                .filter(|(was, is)| was != is)
+                .map(|(was, is)| format!("{} rather than {}", was, is))
+                .collect();
+            let message = format!("checkpoint was made with other options: {}", changed.join(", "));
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+
+        let pixels_start = film_start + 28 * width * height;
+        if data.len() != pixels_start + 12 * width * height {
+            return Err(invalid("checkpoint is the wrong size"));
+        }
+
+        let film = Film::from_bytes(width, height, filter, &data[film_start..pixels_start])?;
+        let pixels = data[pixels_start..]
+            .chunks(12)
+            .map(|b| Welford::from_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7], b[8], b[9], b[10], b[11]]))
+            .collect();
+
+        Ok(Checkpoint { film, pixels })
+    }
+
+    /// Writes the checkpoint next to `path` first and then moves it into
+    /// place, so that a render killed while writing leaves the last one
+    /// whole.
+    pub fn write<P: AsRef<Path>>(&self, path: P, width: usize, height: usize, fingerprint: &str) -> io::Result<()> {
+        let mut data = MAGIC.to_vec();
+        for value in [VERSION, width as u32, height as u32, fingerprint.len() as u32].iter() {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(fingerprint.as_bytes());
+        data.extend(self.film.to_bytes());
+        for pixel in self.pixels.iter() {
+            data.extend_from_slice(&pixel.to_bytes());
+        }
+
+        let path = path.as_ref();
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        fs::write(&partial, data)?;
+        fs::rename(&partial, path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let (width, height) = (3, 2);
+        let filter = Filter::Gaussian(1.5);
+        let mut checkpoint = Checkpoint::new(width, height, filter);
+        checkpoint.film.add_sample(1.2, 0.7, vec3!(0.5, 0.25, 2.));
+        checkpoint.film.add_splat(2.5, 1.5, vec3!(1.));
+        checkpoint.pixels[4].add(vec3!(0.5));
+        checkpoint.pixels[4].add(vec3!(1.5));
+
+        let path = std::env::temp_dir().join(format!("checkpoint_{}.ckpt", std::process::id()));
+        let fingerprint = "scene=\"city\"\nseed=3";
+        checkpoint.write(&path, width, height, fingerprint).unwrap();
+        let read = Checkpoint::read(&path, width, height, fingerprint, filter);
+        assert!(Checkpoint::read(&path, width + 1, height, fingerprint, filter).is_err());
+        let error = Checkpoint::read(&path, width, height, "scene=\"city\"\nseed=4", filter).err().unwrap();
+        assert!(error.to_string().ends_with("options: seed=3 rather than seed=4"));
+        fs::remove_file(&path).unwrap();
+
+        let read = read.unwrap();
+        assert_eq!(read.film.pixels(1.), checkpoint.film.pixels(1.));
+        assert_eq!(read.pixels, checkpoint.pixels);
+    }
+}
@@ -0,0 +1,87 @@
+use rand::RngCore;
+
+use crate::integrator::{Integrator, PathTracer};
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// The bounce count shown in red by the bounce heatmap.
+const MAX_BOUNCES: f32 = 10.;
+
+/// The number of tests shown in red by the traversal cost heatmap.
+const MAX_COST: f32 = 100.;
+
+/// Pictures of what the camera sees other than light, for finding out
+/// what's wrong with a scene. Rays that miss it come out black.
+pub enum DebugView {
+    /// Surface normals, mapped from `[-1, 1]` to `[0, 1]`.
+    Normals,
+    /// The distance to the first hit, from black at the camera to white at
+    /// the given distance.
+    Depth(f32),
+    /// Texture coordinates in red and green.
+    Uv,
+    /// Every material in a color of its own.
+    Material,
+    /// How many times the path tracer's paths bounce.
+    Bounces,
+    /// How many bounding boxes and objects finding the first hit tests.
+    Cost,
+}
+
+/// Shades `t` from blue at 0 through green to red at 1.
+pub fn heatmap(t: f32) -> Vec3 {
+    let t = t.clamp(0., 1.);
+    vec3!(
+        (2. * t - 1.).clamp(0., 1.),
+        1. - (2. * t - 1.).abs(),
+        (1. - 2. * t).clamp(0., 1.)
+    )
+}
+
+/// A color that is the same every time for `id`, but unlike its neighbours'.
+pub fn id_color(id: usize) -> Vec3 {
+    // SplitMix64 scatters nearby ids across the whole range.
+    let mut x = (id as u64).wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+
+    vec3!(
+        (x >> 56) as f32 / 255.,
+        ((x >> 48) & 0xff) as f32 / 255.,
+        ((x >> 40) & 0xff) as f32 / 255.
+    )
+}
+
+impl Integrator for DebugView {
+    fn color(&self, ray: Ray, scene: &Scene, rng: &mut dyn RngCore) -> Vec3 {
+        match self {
+            DebugView::Bounces => {
+                let (_, bounces) = PathTracer::new(false).trace(ray, scene, rng, None);
+                return heatmap(bounces as f32 / MAX_BOUNCES);
+            }
+            DebugView::Cost => {
+                return heatmap(scene.hit_cost(&ray, 0.001, f32::MAX) as f32 / MAX_COST);
+            }
+            _ => {}
+        }
+
+        let record = match scene.hit(&ray, 0.001, f32::MAX) {
+            Some(record) => record,
+            None => return vec3!(0.),
+        };
+
+        match self {
+            DebugView::Normals => 0.5 * (record.normal.unit_vector() + vec3!(1.)),
+            DebugView::Depth(far) => vec3!((record.t * ray.direction().length() / far).min(1.)),
+            DebugView::Uv => vec3!(record.u, record.v, 0.),
+            DebugView::Material => id_color(record.material_id),
+            DebugView::Bounces | DebugView::Cost => unreachable!(),
+        }
+    }
+
+    fn is_radiance(&self) -> bool {
+        false
+    }
+}
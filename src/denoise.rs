@@ -0,0 +1,231 @@
+use crate::image::Image;
+use crate::material::luminance;
+use crate::vec3::Vec3;
+
+/// The B3 spline the à-trous filter spreads at every level.
+const KERNEL: [f32; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+/// Albedo below this is treated as this, so black surfaces don't blow up
+/// the light divided out of them.
+const MIN_ALBEDO: f32 = 0.01;
+
+/// The feature buffers that guide the denoiser, averaged over each pixel's
+/// samples like the image itself, and the variance of each pixel's
+/// luminance.
+pub struct Features<'a> {
+    pub albedo: &'a Image,
+    pub normal: &'a Image,
+    pub depth: &'a Image,
+    pub variance: &'a Image,
+}
+
+/// An edge-avoiding à-trous wavelet filter (Dammertz et al. 2010), with
+/// the variance guided weights of SVGF (Schied et al. 2017).
+///
+/// Each level blurs with a 5×5 kernel whose taps are spread twice as far
+/// apart as the last, so a few levels cover a wide area cheaply. Taps across
+/// a change in normal or depth are weighted down, keeping edges sharp, and
+/// so are taps whose brightness differs by more than the pixel's noise
+/// explains. The albedo is divided out first and multiplied back in at the
+/// end, so that texture detail isn't blurred along with the noise.
+pub struct Denoiser {
+    levels: usize,
+    sigma_luminance: f32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser {
+            levels: 5,
+            sigma_luminance: 4.,
+            sigma_normal: 64.,
+            sigma_depth: 0.02,
+        }
+    }
+}
+
+impl Denoiser {
+    pub fn denoise(&self, image: &Image, features: &Features) -> Image {
+        let (width, height) = (image.width(), image.height());
+        let albedo = |x: usize, y: usize| features.albedo.get(x, y).map(|a| a.max(MIN_ALBEDO));
+
+        let mut light = Image::new(width, height);
+        let mut variance = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let albedo = albedo(x, y);
+                light.set(x, y, image.get(x, y) / albedo);
+                variance.set(x, y, features.variance.get(x, y) / (luminance(&albedo) * luminance(&albedo)));
+            }
+        }
+
+        for level in 0..self.levels {
+            let (filtered, filtered_variance) = self.filter_level(&light, &variance, features, 1 << level);
+            light = filtered;
+            variance = filtered_variance;
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                light.set(x, y, light.get(x, y) * albedo(x, y));
+            }
+        }
+
+        light
+    }
+
+    /// Filters `light` with taps `step` pixels apart, returning it with the
+    /// variance left after filtering.
+    fn filter_level(&self, light: &Image, variance: &Image, features: &Features, step: usize) -> (Image, Image) {
+        let (width, height) = (light.width(), light.height());
+        let blurred = blur(variance);
+        let mut filtered = Image::new(width, height);
+        let mut filtered_variance = Image::new(width, height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let color = light.get(x, y);
+                let normal = features.normal.get(x, y);
+                let depth = features.depth.get(x, y).x;
+                let scale = self.sigma_luminance * blurred.get(x, y).x.sqrt() + 1e-4;
+
+                let mut sum = vec3!(0.);
+                let mut sum_variance = 0.;
+                let mut total = 0.;
+
+                for (j, ky) in KERNEL.iter().enumerate() {
+                    let qy = y as isize + (j as isize - 2) * step as isize;
+                    if qy < 0 || qy >= height as isize {
+                        continue;
+                    }
+
+                    for (i, kx) in KERNEL.iter().enumerate() {
+                        let qx = x as isize + (i as isize - 2) * step as isize;
+                        if qx < 0 || qx >= width as isize {
+                            continue;
+                        }
+
+                        let (qx, qy) = (qx as usize, qy as usize);
+                        let q_color = light.get(qx, qy);
+                        let weight = kx
+                            * ky
+                            * (-(luminance(&color) - luminance(&q_color)).abs() / scale).exp()
+                            * normal_weight(&normal, &features.normal.get(qx, qy), self.sigma_normal)
+                            * depth_weight(depth, features.depth.get(qx, qy).x, self.sigma_depth, step);
+
+                        sum += weight * q_color;
+                        sum_variance += weight * weight * variance.get(qx, qy).x;
+                        total += weight;
+                    }
+                }
+
+                // The tap at the pixel itself always has a positive weight.
+                filtered.set(x, y, sum / total);
+                filtered_variance.set(x, y, vec3!(sum_variance / (total * total)));
+            }
+        }
+
+        (filtered, filtered_variance)
+    }
+}
+
+/// Blurs with a 3×3 Gaussian, to steady the noisy estimates of variance.
+fn blur(image: &Image) -> Image {
+    let (width, height) = (image.width(), image.height());
+    let mut blurred = Image::new(width, height);
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = vec3!(0.);
+            let mut total = 0.;
+
+            for dy in -1..=1_isize {
+                for dx in -1..=1_isize {
+                    let (qx, qy) = (x as isize + dx, y as isize + dy);
+                    if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                        continue;
+                    }
+
+                    let weight = (2 - dx.abs()) as f32 * (2 - dy.abs()) as f32;
+                    sum += weight * image.get(qx as usize, qy as usize);
+                    total += weight;
+                }
+            }
+
+            blurred.set(x, y, sum / total);
+        }
+    }
+
+    blurred
+}
+
+/// Pixels where every ray missed have no normal, and only match each other.
+fn normal_weight(a: &Vec3, b: &Vec3, sigma: f32) -> f32 {
+    match (a.squared_length() > 0., b.squared_length() > 0.) {
+        (false, false) => 1.,
+        (true, true) => a.unit_vector().dot(&b.unit_vector()).max(0.).powf(sigma),
+        _ => 0.,
+    }
+}
+
+/// Compares depths relative to their size, allowing for the distance
+/// between taps on sloped surfaces.
+fn depth_weight(a: f32, b: f32, sigma: f32, step: usize) -> f32 {
+    let scale = sigma * a.max(b).max(1e-3) * step as f32;
+    (-(a - b).abs() / scale).exp()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_smooths_noise_but_keeps_edges() {
+        let (width, height) = (32, 32);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        // Two walls meeting down the middle, one lit and one dark.
+        let mut image = Image::new(width, height);
+        let mut albedo = Image::new(width, height);
+        let mut normal = Image::new(width, height);
+        let mut depth = Image::new(width, height);
+        let mut variance = Image::new(width, height);
+        let expected = |x: usize| if x < width / 2 { 1. } else { 0.1 };
+
+        for y in 0..height {
+            for x in 0..width {
+                let noise = 1. + 0.5 * (rng.gen::<f32>() - 0.5);
+                image.set(x, y, vec3!(expected(x) * noise));
+                albedo.set(x, y, vec3!(1.));
+                normal.set(x, y, if x < width / 2 { vec3!(1., 0., 0.) } else { vec3!(0., 0., 1.) });
+                depth.set(x, y, vec3!(5.));
+                variance.set(x, y, vec3!((0.15 * expected(x)).powi(2)));
+            }
+        }
+
+        let features = Features {
+            albedo: &albedo,
+            normal: &normal,
+            depth: &depth,
+            variance: &variance,
+        };
+        let denoised = Denoiser::default().denoise(&image, &features);
+
+        let error = |image: &Image| {
+            let mut sum = 0.;
+            for y in 0..height {
+                for x in 0..width {
+                    sum += (image.get(x, y).x - expected(x)).abs() / expected(x);
+                }
+            }
+            sum / (width * height) as f32
+        };
+
+        assert!(error(&denoised) < 0.25 * error(&image));
+        assert_eq!(denoised.get(0, 0), Denoiser::default().denoise(&image, &features).get(0, 0));
+    }
+}
@@ -0,0 +1,159 @@
+/// Weights one of two strategies for sampling the same direction (Veach
+/// 1997).
+pub fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other * other);
+    if a + b > 0. {
+        a / (a + b)
+    } else {
+        0.
+    }
+}
+
+/// A piecewise constant 1D distribution over `[0, 1)`, sampled by
+/// inverting its CDF.
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.; n + 1];
+
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].abs() / n as f32;
+        }
+
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            *c = if integral > 0. {
+                *c / integral
+            } else {
+                i as f32 / n as f32
+            };
+        }
+
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Returns a point in `[0, 1)`, its density and the index of the bucket
+    /// it fell in.
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        // The last bucket whose CDF is <= u.
+        let offset = self.cdf.partition_point(|c| *c <= u).clamp(1, self.count()) - 1;
+
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0. {
+            du /= width;
+        }
+
+        let pdf = if self.integral > 0. {
+            self.func[offset].abs() / self.integral
+        } else {
+            1.
+        };
+        let x = ((offset as f32 + du) / self.count() as f32).min(1. - f32::EPSILON);
+
+        (x, pdf, offset)
+    }
+
+    /// The density of `sample` returning `x`.
+    pub fn pdf(&self, x: f32) -> f32 {
+        let offset = ((x * self.count() as f32) as usize).min(self.count() - 1);
+
+        if self.integral > 0. {
+            self.func[offset].abs() / self.integral
+        } else {
+            1.
+        }
+    }
+}
+
+/// A piecewise constant 2D distribution over `[0, 1)²`, sampled by picking
+/// a row from the marginal distribution and then a column within it.
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// Builds the distribution from `func`, stored row by row.
+    pub fn new(func: &[f32], width: usize, height: usize) -> Distribution2D {
+        let conditional: Vec<Distribution1D> = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Returns `(u, v)` and its density.
+    pub fn sample(&self, u1: f32, u2: f32) -> ((f32, f32), f32) {
+        let (v, pdf_v, row) = self.marginal.sample(u2);
+        let (u, pdf_u, _) = self.conditional[row].sample(u1);
+
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f32, v: f32) -> f32 {
+        let row = ((v * self.marginal.count() as f32) as usize).min(self.marginal.count() - 1);
+
+        if self.marginal.integral() > 0. {
+            self.conditional[row].pdf(u) * self.conditional[row].integral() / self.marginal.integral()
+        } else {
+            1.
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample_matches_pdf() {
+        let func = vec![1., 0., 3., 4., 2., 0., 0., 6.];
+        let distribution = Distribution2D::new(&func, 4, 2);
+        let n = 64;
+
+        let mut counts = [0; 8];
+        for i in 0..n {
+            for j in 0..n {
+                let u1 = (i as f32 + 0.5) / n as f32;
+                let u2 = (j as f32 + 0.5) / n as f32;
+                let ((u, v), pdf) = distribution.sample(u1, u2);
+
+                assert!(pdf > 0.);
+                assert_eq!(pdf, distribution.pdf(u, v));
+                counts[(v * 2.) as usize * 4 + (u * 4.) as usize] += 1;
+            }
+        }
+
+        // Each cell is picked in proportion to its value.
+        let total: f32 = func.iter().sum();
+        for (count, f) in counts.iter().zip(func.iter()) {
+            let expected = f / total * (n * n) as f32;
+            assert!((*count as f32 - expected).abs() <= 0.02 * (n * n) as f32);
+        }
+    }
+}
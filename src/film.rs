@@ -0,0 +1,223 @@
+use std::f32::consts::PI;
+use std::io;
+
+use crate::vec3::Vec3;
+
+/// How much a sample counts towards the pixels around it, by its offset
+/// from each pixel's center. Each holds its radius in pixels, past which
+/// it is zero.
+#[derive(Copy, Clone, Debug)]
+pub enum Filter {
+    /// Every sample counts only towards the pixel it is in, at radius 0.5.
+    Box(f32),
+    Tent(f32),
+    /// A Gaussian with a standard deviation of a third of the radius,
+    /// shifted down to reach zero there.
+    Gaussian(f32),
+    /// The cubic of Mitchell and Netravali (1988) with `B = C = 1/3`, which
+    /// sharpens a little with its negative lobes.
+    Mitchell(f32),
+    /// A sinc windowed by a wider one, which is sharp but rings around
+    /// edges.
+    Lanczos(f32),
+}
+
+impl Filter {
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box(radius)
+            | Filter::Tent(radius)
+            | Filter::Gaussian(radius)
+            | Filter::Mitchell(radius)
+            | Filter::Lanczos(radius) => radius,
+        }
+    }
+
+    /// The weight of a sample `(x, y)` pixels from a pixel's center.
+    pub fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let radius = self.radius();
+        if x.abs() > radius {
+            return 0.;
+        }
+
+        match *self {
+            // Half open, so that a sample on the edge between two pixels
+            // only counts towards one.
+            Filter::Box(_) => {
+                if x < radius {
+                    1.
+                } else {
+                    0.
+                }
+            }
+            Filter::Tent(_) => radius - x.abs(),
+            Filter::Gaussian(_) => {
+                let gaussian = |x: f32| (-x * x / (2. * (radius / 3.).powi(2))).exp();
+                gaussian(x) - gaussian(radius)
+            }
+            Filter::Mitchell(_) => mitchell(2. * x.abs() / radius, 1. / 3., 1. / 3.),
+            Filter::Lanczos(_) => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+/// The Mitchell-Netravali cubic at `x` in `[0, 2]`.
+fn mitchell(x: f32, b: f32, c: f32) -> f32 {
+    let (x2, x3) = (x * x, x * x * x);
+    let value = if x < 1. {
+        (12. - 9. * b - 6. * c) * x3 + (-18. + 12. * b + 6. * c) * x2 + (6. - 2. * b)
+    } else {
+        (-b - 6. * c) * x3 + (6. * b + 30. * c) * x2 + (-12. * b - 48. * c) * x + (8. * b + 24. * c)
+    };
+
+    value / 6.
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-5 {
+        1.
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Gathers the samples of a render into pixels, weighting each by the
+/// filter over every pixel in reach. Light that can land anywhere on the
+/// image, like the paths of light tracing or Metropolis light transport, is
+/// splatted into the pixel it lands in instead, and scaled at the end.
+pub struct Film {
+    width: usize,
+    height: usize,
+    filter: Filter,
+    sums: Vec<Vec3>,
+    weights: Vec<f32>,
+    splats: Vec<Vec3>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Filter) -> Film {
+        Film {
+            width,
+            height,
+            filter,
+            sums: vec![vec3!(0.); width * height],
+            weights: vec![0.; width * height],
+            splats: vec![vec3!(0.); width * height],
+        }
+    }
+
+    /// Adds a sample `x` pixels across and `y` down from the top left.
+    pub fn add_sample(&mut self, x: f32, y: f32, color: Vec3) {
+        let radius = self.filter.radius();
+        let range = |p: f32, size: usize| {
+            let first = (p - radius - 0.5).ceil().max(0.) as usize;
+            let last = ((p + radius - 0.5).floor().max(-1.) + 1.) as usize;
+            first..last.min(size)
+        };
+
+        for j in range(y, self.height) {
+            for i in range(x, self.width) {
+                let weight = self.filter.evaluate(x - (i as f32 + 0.5), y - (j as f32 + 0.5));
+                if weight != 0. {
+                    let index = j * self.width + i;
+                    self.sums[index] += weight * color;
+                    self.weights[index] += weight;
+                }
+            }
+        }
+    }
+
+    /// Adds light to the pixel `x` pixels across and `y` down from the top
+    /// left, without weighting it.
+    pub fn add_splat(&mut self, x: f32, y: f32, color: Vec3) {
+        let i = (x.max(0.) as usize).min(self.width - 1);
+        let j = (y.max(0.) as usize).min(self.height - 1);
+        self.splats[j * self.width + i] += color;
+    }
+
+    /// The accumulated samples and splats, for saving a render to resume
+    /// later.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(28 * self.width * self.height);
+        for ((sum, weight), splat) in self.sums.iter().zip(self.weights.iter()).zip(self.splats.iter()) {
+            for value in [sum.x, sum.y, sum.z, *weight, splat.x, splat.y, splat.z].iter() {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        data
+    }
+
+    /// Restores what `to_bytes` saved to a film of the same size.
+    pub fn from_bytes(width: usize, height: usize, filter: Filter, data: &[u8]) -> io::Result<Film> {
+        if data.len() != 28 * width * height {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "film is the wrong size"));
+        }
+
+        let mut film = Film::new(width, height, filter);
+        for (index, pixel) in data.chunks(28).enumerate() {
+            let value = |i: usize| f32::from_le_bytes([pixel[4 * i], pixel[4 * i + 1], pixel[4 * i + 2], pixel[4 * i + 3]]);
+            film.sums[index] = vec3!(value(0), value(1), value(2));
+            film.weights[index] = value(3);
+            film.splats[index] = vec3!(value(4), value(5), value(6));
+        }
+
+        Ok(film)
+    }
+
+    /// The pixels a row at a time from the top, each the weighted average of
+    /// its samples plus its splats times `splat_scale`.
+    pub fn pixels(&self, splat_scale: f32) -> Vec<Vec3> {
+        self.sums
+            .iter()
+            .zip(self.weights.iter())
+            .zip(self.splats.iter())
+            .map(|((sum, weight), splat)| {
+                let average = if *weight != 0. { *sum / *weight } else { vec3!(0.) };
+                average + splat_scale * *splat
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_filters_reconstruct_flat_image() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let filters = [
+            Filter::Box(0.5),
+            Filter::Tent(1.),
+            Filter::Gaussian(1.5),
+            Filter::Mitchell(2.),
+            Filter::Lanczos(3.),
+        ];
+
+        for filter in filters.iter() {
+            let mut film = Film::new(8, 6, *filter);
+            for _ in 0..8 * 6 * 64 {
+                film.add_sample(8. * rng.gen::<f32>(), 6. * rng.gen::<f32>(), vec3!(0.25));
+            }
+            film.add_splat(2.5, 4.5, vec3!(1.));
+
+            for (index, pixel) in film.pixels(0.5).iter().enumerate() {
+                let expected = if index == 4 * 8 + 2 { 0.75 } else { 0.25 };
+                assert!((pixel.x - expected).abs() < 1e-3, "{:?}: {:?}", filter, pixel);
+            }
+        }
+
+        // A box filter keeps samples in their own pixel.
+        let mut film = Film::new(2, 1, Filter::Box(0.5));
+        film.add_sample(1., 0.5, vec3!(1.));
+        film.add_sample(0.9, 0.5, vec3!(0.5));
+        assert_eq!(film.pixels(1.), vec![vec3!(0.5), vec3!(1.)]);
+    }
+}
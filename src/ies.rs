@@ -0,0 +1,294 @@
+use std::fs;
+use std::path::Path;
+
+/// The angular intensity distribution of a luminaire, read from an IES
+/// LM-63 photometric file.
+///
+/// Angles follow type C photometry: the vertical angle is measured from the
+/// nadir, straight down out of the luminaire, and the horizontal angle turns
+/// about that axis.
+#[derive(Debug)]
+pub struct IesProfile {
+    /// Vertical angles in degrees, ascending.
+    vertical: Vec<f32>,
+    /// Horizontal angles in degrees, ascending.
+    horizontal: Vec<f32>,
+    /// Candela values, one row of `vertical.len()` per horizontal angle.
+    candela: Vec<f32>,
+}
+
+impl IesProfile {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<IesProfile, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        IesProfile::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<IesProfile, String> {
+        let mut lines = text.lines();
+
+        // Skip the keywords up to the tilt line.
+        let tilt = loop {
+            match lines.next() {
+                Some(line) if line.trim_start().starts_with("TILT=") => break line.trim()[5..].to_string(),
+                Some(_) => continue,
+                None => return Err(String::from("missing `TILT=` line")),
+            }
+        };
+
+        let rest: Vec<&str> = lines.collect();
+        let mut numbers = rest
+            .iter()
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|word| !word.is_empty())
+            .map(|word| word.parse::<f32>().map_err(|e| format!("invalid number `{}`: {}", word, e)));
+        let mut next = || numbers.next().unwrap_or_else(|| Err(String::from("unexpected end of file")));
+
+        if tilt == "INCLUDE" {
+            // Lamp to luminaire geometry, then the tilt angles and factors,
+            // which only matter for lamps that change output as they tilt.
+            next()?;
+            let count = next()? as usize;
+            for _ in 0..2 * count {
+                next()?;
+            }
+        } else if tilt != "NONE" {
+            return Err(format!("unsupported tilt `{}`", tilt));
+        }
+
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        // Units and luminous opening dimensions.
+        for _ in 0..4 {
+            next()?;
+        }
+        let ballast = next()?;
+        let _ballast_lamp = next()?;
+        let _watts = next()?;
+
+        if photometric_type != 1. {
+            return Err(String::from("only type C photometry is supported"));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(String::from("no angles in profile"));
+        }
+
+        let vertical = (0..vertical_count).map(|_| next()).collect::<Result<Vec<_>, _>>()?;
+        let horizontal = (0..horizontal_count).map(|_| next()).collect::<Result<Vec<_>, _>>()?;
+        let candela = (0..vertical_count * horizontal_count)
+            .map(|_| next().map(|c| c * multiplier * ballast))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(IesProfile {
+            vertical,
+            horizontal,
+            candela,
+        })
+    }
+
+    pub fn max_candela(&self) -> f32 {
+        self.candela.iter().fold(0., |a, &b| a.max(b))
+    }
+
+    /// The intensity at `vertical` degrees from the nadir and `horizontal`
+    /// degrees about it, interpolated between the measured angles.
+    pub fn candela(&self, vertical: f32, horizontal: f32) -> f32 {
+        let (first, last) = (self.horizontal[0], *self.horizontal.last().unwrap());
+
+        // Profiles cover only as much of the circle as their symmetry needs.
+        let mut h = horizontal.rem_euclid(360.);
+        if last <= 0. {
+            h = 0.;
+        } else if last <= 90. {
+            h = if h > 180. { 360. - h } else { h };
+            h = if h > 90. { 180. - h } else { h };
+        } else if first >= 90. && last <= 270. {
+            // Symmetric about the 90-270 plane.
+            if !(90. ..=270.).contains(&h) {
+                h = (180. - h).rem_euclid(360.);
+            }
+        } else if last <= 180. && h > 180. {
+            h = 360. - h;
+        }
+
+        let (v0, v1, tv) = match bracket(&self.vertical, vertical) {
+            Some(bracket) => bracket,
+            None => return 0.,
+        };
+        let (h0, h1, th) = match bracket(&self.horizontal, h) {
+            Some(bracket) => bracket,
+            // Whole circle profiles that stop short of 360° wrap around to
+            // their first angle.
+            None => {
+                let gap = 360. + first - last;
+                let t = if gap > 0. { (h - last).rem_euclid(360.) / gap } else { 0. };
+                (self.horizontal.len() - 1, 0, t.clamp(0., 1.))
+            }
+        };
+
+        let at = |h: usize, v: usize| self.candela[h * self.vertical.len() + v];
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+        lerp(
+            lerp(at(h0, v0), at(h0, v1), tv),
+            lerp(at(h1, v0), at(h1, v1), tv),
+            th,
+        )
+    }
+}
+
+/// The indices of the angles either side of `x` and how far it lies between
+/// them, or `None` if it is outside the range.
+fn bracket(angles: &[f32], x: f32) -> Option<(usize, usize, f32)> {
+    let (first, last) = (angles[0], angles[angles.len() - 1]);
+    if x < first - 1e-4 || x > last + 1e-4 {
+        return None;
+    }
+    if angles.len() == 1 {
+        return Some((0, 0, 0.));
+    }
+
+    let i = angles.partition_point(|a| *a <= x).clamp(1, angles.len() - 1) - 1;
+    let span = angles[i + 1] - angles[i];
+    let t = if span > 0. { (x - angles[i]) / span } else { 0. };
+
+    Some((i, i + 1, t.clamp(0., 1.)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A made-up bilaterally symmetric downlight with a doubled candela
+    // multiplier.
+    const PROFILE: &str = "IESNA:LM-63-2002
+[TEST] 1
+[MANUFAC] Example
+TILT=NONE
+1 1000 2 5 3 1 2 0.5 0.5 0
+1.0 1.0 40
+0 22.5 45 67.5 90
+0 90 180
+500 450 300 100 0
+500 400 250 50 0
+500 350 200 25 0
+";
+
+    #[test]
+    fn test_candela_values() {
+        let profile = IesProfile::parse(PROFILE).unwrap();
+
+        assert_eq!(profile.max_candela(), 1000.);
+        assert_eq!(profile.candela(0., 0.), 1000.);
+        assert_eq!(profile.candela(45., 0.), 600.);
+        assert_eq!(profile.candela(45., 90.), 500.);
+        assert_eq!(profile.candela(67.5, 180.), 50.);
+        assert_eq!(profile.candela(90., 90.), 0.);
+
+        // Interpolated between measured angles.
+        assert_eq!(profile.candela(33.75, 0.), 750.);
+        assert_eq!(profile.candela(45., 45.), 550.);
+
+        // Mirrored across the 0-180 plane, and dark above the horizon.
+        assert_eq!(profile.candela(45., 270.), 500.);
+        assert_eq!(profile.candela(120., 0.), 0.);
+    }
+
+    // A made-up wall washer laid out the way manufacturers export them:
+    // absolute photometry (-1 lumens), keywords, values wrapped across lines,
+    // and horizontal angles from 90 to 270 for symmetry about that plane.
+    const WALL_WASHER: &str = "IESNA:LM-63-2002
+[TEST] 2104-118
+[TESTLAB] Example Photometric Laboratory
+[ISSUEDATE] 14-MAR-2021
+[MANUFAC] Example Lighting
+[LUMCAT] WW-24-30K
+[LUMINAIRE] LED wall washer, asymmetric distribution
+[LAMP] LED module, 3000 K
+[MORE] Measured with the wall toward 0 degrees horizontal.
+TILT=NONE
+1 -1 1.0 4 5 1 2 0.305 0.076 0.0
+1.0 1.0 24.0
+0.0 30.0
+60.0 90.0
+90.0 135.0 180.0 225.0
+270.0
+800.0 700.0 300.0 0.0
+800.0 900.0 600.0 50.0
+800.0 1100.0
+900.0 100.0
+800.0 900.0 600.0 50.0
+800.0 700.0 300.0 0.0
+";
+
+    #[test]
+    fn test_symmetric_about_90_270_plane() {
+        let profile = IesProfile::parse(WALL_WASHER).unwrap();
+
+        assert_eq!(profile.max_candela(), 1100.);
+        assert_eq!(profile.candela(30., 180.), 1100.);
+        assert_eq!(profile.candela(60., 135.), 600.);
+
+        // The wall side is mirrored from the measured side.
+        assert_eq!(profile.candela(30., 0.), 1100.);
+        assert_eq!(profile.candela(30., 45.), 900.);
+        assert_eq!(profile.candela(30., 315.), 900.);
+        assert_eq!(profile.candela(90., 0.), 100.);
+    }
+
+    // The example file from the LM-63-1995 standard: a 400 W metal halide
+    // flood with quadrant symmetry and a lamp tilt table.
+    const STANDARD_EXAMPLE: &str = "IESNA:LM-63-1995
+[TEST] BT-1234
+[DATE] 18-FEB-1995
+[MANUFAC] Aardvark Lighting Inc.
+[LUMCAT] SCRWF-8-HY
+[LUMINAIRE] Wide beam flood to be used with tilt
+[LAMPCAT] MH400/T15
+[LAMP] 400 Watt Metal Halide
+TILT=INCLUDE
+1
+7
+0 15 30 45 60 75 90
+1.0 .95 .94 .90 .88 .87 .94
+1 10000 1 5 3 1 1 .5 .6 0
+1.0 1.0 495
+0 22.5 45 67.5 90
+0 45 90
+10000 50000 25000 10000 5000
+10000 35000 16500 3400 0
+10000 20000 10000 5000 1000
+";
+
+    #[test]
+    fn test_standard_example() {
+        let profile = IesProfile::parse(STANDARD_EXAMPLE).unwrap();
+
+        assert_eq!(profile.max_candela(), 50000.);
+        assert_eq!(profile.candela(0., 0.), 10000.);
+        assert_eq!(profile.candela(22.5, 0.), 50000.);
+        assert_eq!(profile.candela(45., 45.), 16500.);
+        assert_eq!(profile.candela(67.5, 45.), 3400.);
+        assert_eq!(profile.candela(90., 45.), 0.);
+        assert_eq!(profile.candela(90., 90.), 1000.);
+
+        // The measured quadrant stands in for the other three.
+        assert_eq!(profile.candela(22.5, 135.), 35000.);
+        assert_eq!(profile.candela(22.5, 180.), 50000.);
+        assert_eq!(profile.candela(45., 270.), 10000.);
+        assert_eq!(profile.candela(67.5, 315.), 3400.);
+    }
+
+    #[test]
+    fn test_tilt_include() {
+        let profile = IesProfile::parse(
+            "IESNA91\nTILT=INCLUDE\n1\n2\n0 90\n1 1\n1 500 1 2 1 1 2 0 0 0\n1 1 10\n0 90\n0\n200 100\n",
+        )
+        .unwrap();
+
+        assert_eq!(profile.candela(45., 123.), 150.);
+    }
+}
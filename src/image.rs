@@ -0,0 +1,546 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::inflate::zlib_decompress;
+use crate::vec3::Vec3;
+
+/// A grid of linear RGB pixels, stored row by row from the top left.
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![vec3!(0.); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Vec3) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Reads an image, picking the format from the file extension.
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("hdr") | Some("pic") => Image::parse_hdr(&fs::read(path)?),
+            Some("exr") => Image::parse_exr(&fs::read(path)?),
+            _ => Image::read_ppm(path),
+        }
+    }
+
+    /// Parses a Radiance RGBE (`.hdr`) image, either flat or run length
+    /// encoded.
+    pub fn parse_hdr(data: &[u8]) -> io::Result<Image> {
+        let mut pos = 0;
+        let mut line = || {
+            let start = pos.min(data.len());
+            while pos < data.len() && data[pos] != b'\n' {
+                pos += 1;
+            }
+            let end = pos.min(data.len());
+            pos += 1;
+            String::from_utf8_lossy(&data[start..end]).into_owned()
+        };
+
+        if !line().starts_with("#?") {
+            return Err(invalid("not a Radiance HDR"));
+        }
+
+        loop {
+            let header = line();
+            if header.is_empty() {
+                break;
+            }
+            if header.starts_with("FORMAT=") && header != "FORMAT=32-bit_rle_rgbe" {
+                return Err(invalid("unsupported HDR pixel format"));
+            }
+        }
+
+        let resolution = line();
+        let fields: Vec<&str> = resolution.split_whitespace().collect();
+        if fields.len() != 4 || fields[0] != "-Y" || fields[2] != "+X" {
+            return Err(invalid("unsupported HDR orientation"));
+        }
+
+        let number = |s: &str| s.parse::<usize>().map_err(|_| invalid("bad HDR resolution"));
+        let height = number(fields[1])?;
+        let width = number(fields[3])?;
+        if width == 0 || height == 0 {
+            return Err(invalid("HDR image is empty"));
+        }
+        let mut image = Image::new(width, height);
+        let mut scanline = vec![[0u8; 4]; width];
+        let truncated = || invalid("truncated HDR");
+
+        for y in 0..height {
+            let rle = (8..32768).contains(&width)
+                && data.get(pos..pos + 4).is_some_and(|b| {
+                    b[0] == 2 && b[1] == 2 && ((b[2] as usize) << 8 | b[3] as usize) == width
+                });
+
+            if rle {
+                pos += 4;
+                for channel in 0..4 {
+                    let mut x = 0;
+                    while x < width {
+                        let count = *data.get(pos).ok_or_else(truncated)? as usize;
+                        pos += 1;
+
+                        if count > 128 {
+                            let value = *data.get(pos).ok_or_else(truncated)?;
+                            pos += 1;
+                            for pixel in scanline.iter_mut().skip(x).take(count - 128) {
+                                pixel[channel] = value;
+                            }
+                            x += count - 128;
+                        } else {
+                            let values = data.get(pos..pos + count).ok_or_else(truncated)?;
+                            pos += count;
+                            for (pixel, value) in scanline.iter_mut().skip(x).zip(values.iter()) {
+                                pixel[channel] = *value;
+                            }
+                            x += count;
+                        }
+                    }
+                }
+            } else {
+                for pixel in scanline.iter_mut() {
+                    let bytes = data.get(pos..pos + 4).ok_or_else(truncated)?;
+                    pixel.copy_from_slice(bytes);
+                    pos += 4;
+                }
+            }
+
+            for (x, rgbe) in scanline.iter().enumerate() {
+                let color = if rgbe[3] == 0 {
+                    vec3!(0.)
+                } else {
+                    let scale = 2f32.powi(i32::from(rgbe[3]) - 136);
+                    vec3!(
+                        (f32::from(rgbe[0]) + 0.5) * scale,
+                        (f32::from(rgbe[1]) + 0.5) * scale,
+                        (f32::from(rgbe[2]) + 0.5) * scale
+                    )
+                };
+                image.set(x, y, color);
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// Parses a single part, scanline OpenEXR image. Only uncompressed and
+    /// ZIP compressed files with half or float `R`, `G` and `B` channels are
+    /// supported.
+    pub fn parse_exr(data: &[u8]) -> io::Result<Image> {
+        let truncated = || invalid("truncated EXR");
+        // Offsets come from the file, so adding to them is checked.
+        let range = |start: usize, size: usize| start.checked_add(size).and_then(|end| data.get(start..end));
+        let u32_at = |pos: usize| -> io::Result<u32> {
+            let b = range(pos, 4).ok_or_else(truncated)?;
+            Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        let cstr_at = |pos: usize| -> io::Result<(String, usize)> {
+            let end = data[pos.min(data.len())..]
+                .iter()
+                .position(|b| *b == 0)
+                .ok_or_else(truncated)?;
+            Ok((String::from_utf8_lossy(&data[pos..pos + end]).into_owned(), pos + end + 1))
+        };
+
+        if u32_at(0)? != 20_000_630 {
+            return Err(invalid("not an OpenEXR image"));
+        }
+        if u32_at(4)? & 0x1e00 != 0 {
+            return Err(invalid("only single part scanline EXRs are supported"));
+        }
+
+        let mut pos = 8;
+        let mut channels: Vec<(String, u32)> = vec![];
+        let mut compression = 0;
+        let mut window = [0i32; 4];
+
+        loop {
+            let (name, next) = cstr_at(pos)?;
+            pos = next;
+            if name.is_empty() {
+                break;
+            }
+
+            let (_kind, next) = cstr_at(pos)?;
+            let size = u32_at(next)? as usize;
+            let value = range(next + 4, size).ok_or_else(truncated)?;
+            pos = next + 4 + size;
+
+            match name.as_str() {
+                "channels" => {
+                    let mut i = 0;
+                    while i < value.len() && value[i] != 0 {
+                        let end = i + value[i..].iter().position(|b| *b == 0).ok_or_else(truncated)?;
+                        let channel = String::from_utf8_lossy(&value[i..end]).into_owned();
+                        let kind = value.get(end + 1..end + 5).ok_or_else(truncated)?;
+                        channels.push((channel, u32::from_le_bytes([kind[0], kind[1], kind[2], kind[3]])));
+                        i = end + 17;
+                    }
+                }
+                "compression" => compression = *value.first().ok_or_else(truncated)?,
+                "dataWindow" => {
+                    for (i, w) in window.iter_mut().enumerate() {
+                        let b = value.get(4 * i..4 * i + 4).ok_or_else(truncated)?;
+                        *w = i32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // ZIPS blocks hold one scanline each, and ZIP blocks sixteen.
+        let lines_per_block = match compression {
+            0 | 2 => 1,
+            3 => 16,
+            _ => return Err(invalid("only uncompressed and ZIP compressed EXRs are supported")),
+        };
+
+        let width = (window[2] - window[0] + 1).max(0) as usize;
+        let height = (window[3] - window[1] + 1).max(0) as usize;
+        if width == 0 || height == 0 {
+            return Err(invalid("EXR image is empty"));
+        }
+        let mut image = Image::new(width, height);
+
+        let bytes = |kind: u32| if kind == 1 { 2 } else { 4 };
+        let line_size: usize = channels.iter().map(|(_, kind)| bytes(*kind) * width).sum();
+
+        for block in 0..height.div_ceil(lines_per_block) {
+            let entry = pos.checked_add(8 * block).ok_or_else(truncated)?;
+            let offset = u64::from(u32_at(entry)?) | u64::from(u32_at(entry + 4)?) << 32;
+            let offset = usize::try_from(offset).map_err(|_| truncated())?;
+            let first = u32_at(offset)? as i32 - window[1];
+            let size = u32_at(offset.checked_add(4).ok_or_else(truncated)?)? as usize;
+            let packed = range(offset + 8, size).ok_or_else(truncated)?;
+            if first < 0 || first as usize >= height {
+                return Err(invalid("EXR block is outside the image"));
+            }
+
+            // Blocks that compression wouldn't make smaller are stored as is.
+            let lines = lines_per_block.min(height - first as usize);
+            let block = if compression == 0 || size >= lines * line_size {
+                packed.to_vec()
+            } else {
+                unzip_exr_block(packed)?
+            };
+            if block.len() < lines * line_size {
+                return Err(truncated());
+            }
+
+            let mut at = 0;
+            for y in first as usize..first as usize + lines {
+                for (name, kind) in channels.iter() {
+                    let channel = match name.as_str() {
+                        "R" => Some(0),
+                        "G" => Some(1),
+                        "B" => Some(2),
+                        _ => None,
+                    };
+
+                    for x in 0..width {
+                        let b = &block[at..at + bytes(*kind)];
+                        at += bytes(*kind);
+
+                        let value = match kind {
+                            1 => half_to_f32(u16::from_le_bytes([b[0], b[1]])),
+                            2 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                            _ => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32,
+                        };
+
+                        if let Some(c) = channel {
+                            let mut pixel = image.get(x, y);
+                            match c {
+                                0 => pixel.x = value,
+                                1 => pixel.y = value,
+                                _ => pixel.z = value,
+                            }
+                            image.set(x, y, pixel);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// Reads a binary (P6) or plain (P3) PPM, scaled to `[0, 1]` without
+    /// any gamma decoding.
+    pub fn read_ppm<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        let data = fs::read(path)?;
+        let mut pos = 0;
+
+        let mut token = || -> io::Result<String> {
+            loop {
+                while pos < data.len() && data[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                if pos < data.len() && data[pos] == b'#' {
+                    while pos < data.len() && data[pos] != b'\n' {
+                        pos += 1;
+                    }
+                } else {
+                    break;
+                }
+            }
+
+            let start = pos;
+            while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid("unexpected end of PPM"));
+            }
+
+            Ok(String::from_utf8_lossy(&data[start..pos]).into_owned())
+        };
+
+        let number = |s: String| s.parse::<usize>().map_err(|_| invalid("bad number in PPM"));
+
+        let magic = token()?;
+        let width = number(token()?)?;
+        let height = number(token()?)?;
+        let max = number(token()?)? as f32;
+        let mut image = Image::new(width, height);
+
+        match magic.as_str() {
+            "P3" => {
+                for i in 0..width * height {
+                    let r = number(token()?)? as f32;
+                    let g = number(token()?)? as f32;
+                    let b = number(token()?)? as f32;
+                    image.pixels[i] = vec3!(r, g, b) / max;
+                }
+            }
+            "P6" => {
+                let bytes = if max > 255. { 2 } else { 1 };
+                let start = pos + 1;
+                let raster = data
+                    .get(start..start + width * height * 3 * bytes)
+                    .ok_or_else(|| invalid("truncated PPM"))?;
+
+                let sample = |i: usize| {
+                    if bytes == 2 {
+                        f32::from(u16::from_be_bytes([raster[2 * i], raster[2 * i + 1]]))
+                    } else {
+                        f32::from(raster[i])
+                    }
+                };
+
+                for i in 0..width * height {
+                    image.pixels[i] = vec3!(sample(3 * i), sample(3 * i + 1), sample(3 * i + 2)) / max;
+                }
+            }
+            _ => return Err(invalid("not a PPM")),
+        }
+
+        Ok(image)
+    }
+
+    /// Encodes the image as an uncompressed scanline OpenEXR image with
+    /// float `R`, `G` and `B` channels.
+    pub fn to_exr(&self) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&20_000_630u32.to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+
+        let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+            for text in [name, kind].iter() {
+                data.extend_from_slice(text.as_bytes());
+                data.push(0);
+            }
+            data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            data.extend_from_slice(value);
+        };
+
+        // Channels are listed, and stored, in alphabetical order.
+        let mut channels = vec![];
+        for name in ["B", "G", "R"].iter() {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&2u32.to_le_bytes());
+            channels.extend_from_slice(&[0; 4]);
+            channels.extend_from_slice(&1i32.to_le_bytes());
+            channels.extend_from_slice(&1i32.to_le_bytes());
+        }
+        channels.push(0);
+
+        let mut window = vec![];
+        for w in [0, 0, self.width as i32 - 1, self.height as i32 - 1].iter() {
+            window.extend_from_slice(&w.to_le_bytes());
+        }
+
+        attribute("channels", "chlist", &channels);
+        attribute("compression", "compression", &[0]);
+        attribute("dataWindow", "box2i", &window);
+        attribute("displayWindow", "box2i", &window);
+        attribute("lineOrder", "lineOrder", &[0]);
+        attribute("pixelAspectRatio", "float", &1f32.to_le_bytes());
+        attribute("screenWindowCenter", "v2f", &[0; 8]);
+        attribute("screenWindowWidth", "float", &1f32.to_le_bytes());
+        data.push(0);
+
+        let line = 8 + 12 * self.width;
+        let start = data.len() + 8 * self.height;
+        for y in 0..self.height {
+            data.extend_from_slice(&((start + y * line) as u64).to_le_bytes());
+        }
+
+        for y in 0..self.height {
+            data.extend_from_slice(&(y as i32).to_le_bytes());
+            data.extend_from_slice(&(12 * self.width as u32).to_le_bytes());
+
+            for channel in [2, 1, 0].iter() {
+                for x in 0..self.width {
+                    data.extend_from_slice(&self.get(x, y).axis(*channel).to_le_bytes());
+                }
+            }
+        }
+
+        data
+    }
+
+    pub fn write_exr<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_exr())
+    }
+
+    /// Writes a binary PPM, clamping to `[0, 1]` without any gamma
+    /// encoding.
+    pub fn write_ppm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut data = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        for pixel in self.pixels.iter() {
+            for c in 0..3 {
+                data.push((255.99 * pixel.axis(c).clamp(0., 1.)) as u8);
+            }
+        }
+
+        fs::write(path, data)
+    }
+}
+
+/// Undoes the ZIP compression of an EXR block: zlib, after a predictor
+/// that stores each byte as its difference from the last, and after
+/// splitting the bytes into the even and then the odd ones.
+fn unzip_exr_block(packed: &[u8]) -> io::Result<Vec<u8>> {
+    let mut bytes = zlib_decompress(packed)?;
+    for i in 1..bytes.len() {
+        bytes[i] = bytes[i - 1].wrapping_add(bytes[i]).wrapping_sub(128);
+    }
+
+    let half = bytes.len().div_ceil(2);
+    let (even, odd) = bytes.split_at(half);
+    Ok((0..bytes.len())
+        .map(|i| if i % 2 == 0 { even[i / 2] } else { odd[i / 2] })
+        .collect())
+}
+
+fn half_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1. } else { 1. };
+    let exponent = i32::from((h >> 10) & 0x1f);
+    let mantissa = f32::from(h & 0x3ff);
+
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 => {
+            if mantissa == 0. {
+                f32::INFINITY
+            } else {
+                f32::NAN
+            }
+        }
+        _ => (1. + mantissa / 1024.) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_parse_hdr() {
+        let mut data = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+        data.extend_from_slice(&[128, 64, 0, 129, 0, 0, 0, 0]);
+
+        let image = Image::parse_hdr(&data).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(image.get(0, 0), vec3!(128.5, 64.5, 0.5) / 128.);
+        assert_eq!(image.get(1, 0), vec3!(0.));
+
+        let empty = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 0 +X 0\n";
+        assert!(Image::parse_hdr(empty).is_err());
+    }
+
+    #[test]
+    fn test_exr_round_trip() {
+        let mut image = Image::new(3, 2);
+        image.set(0, 0, vec3!(1., 2., 3.));
+        image.set(2, 1, vec3!(-0.5, 1e6, 0.25));
+
+        let data = image.to_exr();
+        let parsed = Image::parse_exr(&data).unwrap();
+        assert_eq!((parsed.width(), parsed.height()), (3, 2));
+        assert_eq!(parsed.pixels, image.pixels);
+
+        // Offsets past the end of the file, or of memory, are errors. The
+        // first block starts just after the table of both blocks' offsets.
+        let table = (0..data.len() - 8)
+            .find(|&i| u64::from_le_bytes(data[i..i + 8].try_into().unwrap()) == i as u64 + 16)
+            .unwrap();
+        for offset in [u64::MAX, u64::MAX - 6, 1 << 40, data.len() as u64 - 6].iter() {
+            let mut broken = data.clone();
+            broken[table..table + 8].copy_from_slice(&offset.to_le_bytes());
+            assert!(Image::parse_exr(&broken).is_err(), "offset {}", offset);
+        }
+
+        // A ZIP compressed block.
+        let packed = [
+            120, 218, 99, 104, 169, 111, 111, 170, 173, 104, 232, 104, 174, 171, 108, 172, 193, 194, 193, 41, 1, 225,
+            0, 0, 241, 242, 31, 133,
+        ];
+        let expected: Vec<u8> = (0..64u32).map(|i| (i * i % 13) as u8).collect();
+        assert_eq!(unzip_exr_block(&packed).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_half_to_f32() {
+        assert_eq!(half_to_f32(0x3c00), 1.);
+        assert_eq!(half_to_f32(0xc000), -2.);
+        assert_eq!(half_to_f32(0x3555), 0.333_251_95);
+    }
+}
@@ -0,0 +1,230 @@
+use std::io;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+/// The order the code lengths of the code length code are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Reads a deflate stream a bit at a time, least significant bit first.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or_else(|| invalid("truncated deflate stream"))?;
+            self.pos += 1;
+            self.buffer |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Skips to the next whole byte.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code, decoded a bit at a time as in zlib's `puff`.
+struct Huffman {
+    /// How many codes there are of each length.
+    counts: [u16; 16],
+    /// The symbols in order of their codes.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for length in lengths.iter() {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for length in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = i32::from(self.counts[length]);
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(invalid("bad Huffman code"))
+    }
+}
+
+/// The codes of a block compressed with the fixed Huffman codes.
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [8; 288];
+    lengths[144..256].iter_mut().for_each(|length| *length = 9);
+    lengths[256..280].iter_mut().for_each(|length| *length = 7);
+
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+/// Reads the codes a block compressed with its own Huffman codes starts
+/// with.
+fn dynamic_codes(bits: &mut Bits) -> io::Result<(Huffman, Huffman)> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+
+    let mut lengths = [0; 19];
+    for index in CODE_LENGTH_ORDER.iter().take(code_lengths) {
+        lengths[*index] = bits.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&lengths);
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let symbol = code_length_code.decode(bits)?;
+        let (length, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or_else(|| invalid("bad code lengths"))?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+    if lengths.len() > literals + distances {
+        return Err(invalid("bad code lengths"));
+    }
+
+    Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+/// Decompresses a zlib stream (RFC 1950 and 1951), without checking its
+/// checksum.
+pub fn zlib_decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 2 || data[0] & 0x0f != 8 || data[1] & 0x20 != 0 {
+        return Err(invalid("not a zlib stream"));
+    }
+
+    let mut bits = Bits { data, pos: 2, buffer: 0, count: 0 };
+    let mut out = Vec::new();
+
+    loop {
+        let last = bits.bits(1)? == 1;
+        let (literals, distances) = match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = data.get(bits.pos..bits.pos + 4).ok_or_else(|| invalid("truncated deflate stream"))?;
+                let length = usize::from(u16::from_le_bytes([header[0], header[1]]));
+                let stored = data
+                    .get(bits.pos + 4..bits.pos + 4 + length)
+                    .ok_or_else(|| invalid("truncated deflate stream"))?;
+                out.extend_from_slice(stored);
+                bits.pos += 4 + length;
+
+                if last {
+                    return Ok(out);
+                }
+                continue;
+            }
+            1 => fixed_codes(),
+            2 => dynamic_codes(&mut bits)?,
+            _ => return Err(invalid("bad deflate block")),
+        };
+
+        loop {
+            let symbol = literals.decode(&mut bits)? as usize;
+            if symbol < 256 {
+                out.push(symbol as u8);
+                continue;
+            }
+            if symbol == 256 {
+                break;
+            }
+
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err(invalid("bad deflate length"));
+            }
+            let length = usize::from(LENGTH_BASE[symbol]) + bits.bits(u32::from(LENGTH_EXTRA[symbol]))? as usize;
+
+            let symbol = distances.decode(&mut bits)? as usize;
+            if symbol >= DISTANCE_BASE.len() {
+                return Err(invalid("bad deflate distance"));
+            }
+            let distance = usize::from(DISTANCE_BASE[symbol]) + bits.bits(u32::from(DISTANCE_EXTRA[symbol]))? as usize;
+            if distance > out.len() {
+                return Err(invalid("deflate distance too far back"));
+            }
+
+            // The copy may overlap what it writes, to repeat a run.
+            let start = out.len() - distance;
+            for i in 0..length {
+                out.push(out[start + i]);
+            }
+        }
+
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_zlib_decompress() {
+        // 600 bytes compressed by zlib with its own Huffman codes.
+        let expected: Vec<u8> = (0..600).map(|i| b"abcdefgh"[(i * i + i / 7) % 8]).collect();
+        let compressed = [
+            120, 218, 237, 203, 65, 1, 0, 48, 8, 2, 192, 172, 160, 40, 253, 19, 140, 4, 75, 224, 227, 158, 7, 138, 160,
+            138, 53, 177, 93, 29, 178, 58, 52, 152, 216, 89, 70, 121, 29, 104, 56, 112, 239, 222, 231, 61, 72, 43, 235,
+            92,
+        ];
+        assert_eq!(zlib_decompress(&compressed).unwrap(), expected);
+
+        // A stored block.
+        assert_eq!(zlib_decompress(&[120, 1, 1, 3, 0, 252, 255, 1, 2, 3, 0, 13, 0, 7]).unwrap(), vec![1, 2, 3]);
+        assert!(zlib_decompress(&compressed[..30]).is_err());
+    }
+}
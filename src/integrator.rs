@@ -0,0 +1,286 @@
+use rand::{Rng, RngCore};
+
+use crate::aov::Aovs;
+use crate::camera::Camera;
+use crate::distribution::power_heuristic;
+use crate::material::random_cosine_direction;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::{Channels, SampledWavelengths, Spectrum};
+use crate::vec3::Vec3;
+
+const MAX_DEPTH: usize = 50;
+
+/// A way of working out what the camera sees along each of its rays.
+pub trait Integrator {
+    /// The color seen back along the camera ray `ray`.
+    fn color(&self, ray: Ray, scene: &Scene, rng: &mut dyn RngCore) -> Vec3;
+
+    /// Like `color`, also filling in `aovs` if the integrator can.
+    fn color_with_aovs(&self, ray: Ray, scene: &Scene, rng: &mut dyn RngCore, _aovs: &mut Aovs) -> Vec3 {
+        self.color(ray, scene, rng)
+    }
+
+    /// Like `color`, also adding any light the paths carry to the camera
+    /// elsewhere on its image, as light tracing does, to `splats`.
+    fn color_with_splats(
+        &self,
+        ray: Ray,
+        scene: &Scene,
+        _camera: &dyn Camera,
+        rng: &mut dyn RngCore,
+        _splats: &mut Vec<Splat>,
+    ) -> Vec3 {
+        self.color(ray, scene, rng)
+    }
+
+    /// Whether `color_with_aovs` fills in AOVs.
+    fn has_aovs(&self) -> bool {
+        false
+    }
+
+    /// Whether `color` returns light, which is gamma corrected for display,
+    /// rather than a picture of some other quantity that is shown as is.
+    fn is_radiance(&self) -> bool {
+        true
+    }
+}
+
+/// Light that reaches the camera at `(s, t)` on its image, as for
+/// `Camera::get_ray`, rather than through the pixel being sampled.
+pub struct Splat {
+    pub s: f32,
+    pub t: f32,
+    pub color: Vec3,
+}
+
+/// Traces a path from each ray, sampling a light at every diffuse bounce and
+/// weighting it against hitting lights by chance with multiple importance
+/// sampling. In spectral mode the path carries a handful of wavelengths
+/// instead of RGB, so that dispersive materials split light into its
+/// spectrum.
+pub struct PathTracer {
+    spectral: bool,
+}
+
+/// The light a path has gathered, kept apart by the number of bounces it
+/// took and, if AOVs want them, by the light it came from.
+struct Gathered {
+    direct: Spectrum,
+    indirect: Spectrum,
+    lights: Option<Vec<Spectrum>>,
+}
+
+impl Gathered {
+    fn add(&mut self, light: Spectrum, bounces: usize) {
+        if bounces <= 1 {
+            self.direct += light;
+        } else {
+            self.indirect += light;
+        }
+    }
+
+    fn add_to_light(&mut self, index: usize, light: Spectrum) {
+        if let Some(lights) = &mut self.lights {
+            lights[index] += light;
+        }
+    }
+}
+
+impl PathTracer {
+    pub fn new(spectral: bool) -> PathTracer {
+        PathTracer { spectral }
+    }
+
+    /// The radiance arriving back along `ray`, and how many times the path
+    /// scattered. Fills in `aovs` if given.
+    pub fn trace(&self, ray: Ray, scene: &Scene, rng: &mut dyn RngCore, mut aovs: Option<&mut Aovs>) -> (Vec3, usize) {
+        let mut channels = if self.spectral {
+            Channels::Spectral(SampledWavelengths::sample_uniform(rng.gen()))
+        } else {
+            Channels::Rgb
+        };
+
+        let mut ray = ray.with_wavelength(channels.hero());
+        let mut throughput = Spectrum::splat(1.);
+        let mut gathered = Gathered {
+            direct: Spectrum::splat(0.),
+            indirect: Spectrum::splat(0.),
+            lights: aovs.as_ref().map(|_| vec![Spectrum::splat(0.); scene.lights.len()]),
+        };
+        let mut scatter_pdf = 0.;
+        let origin = *ray.origin();
+        let mut previous = origin;
+        let mut depth = 0;
+
+        while depth <= MAX_DEPTH {
+            let record = match scene.hit(&ray, 0.001, f32::MAX) {
+                Some(record) => record,
+                None => {
+                    let direction = ray.direction().unit_vector();
+                    let weight = if scatter_pdf > 0. {
+                        power_heuristic(scatter_pdf, scene.environment_pdf(&previous, &direction))
+                    } else {
+                        1.
+                    };
+
+                    gathered.add(throughput * channels.spectrum_of(&(weight * scene.environment(&direction))), depth);
+                    if gathered.lights.is_some() {
+                        for (index, light) in scene.lights.iter().enumerate() {
+                            let environment = weight * light.environment(&direction);
+                            gathered.add_to_light(index, throughput * channels.spectrum_of(&environment));
+                        }
+                    }
+                    break;
+                }
+            };
+
+            let opacity = record.material.opacity(&record);
+            if opacity < 1. && rng.gen::<f32>() >= opacity {
+                ray = Ray::new(record.p, *ray.direction()).with_wavelength(ray.wavelength());
+                continue;
+            }
+
+            if let (Some(aovs), 0) = (&mut aovs, depth) {
+                aovs.albedo = record.material.albedo(&record);
+                aovs.normal = record.material.shading_normal(&record);
+                aovs.depth = (record.p - origin).length();
+                aovs.position = record.p;
+                aovs.object = Some(record.object);
+                aovs.material = Some(record.material_id);
+            }
+
+            let emitted = record.material.emitted(&ray, &record);
+            if emitted != vec3!(0.) {
+                let weight = match record.light {
+                    Some(index) if scatter_pdf > 0. => {
+                        let direction = ray.direction().unit_vector();
+                        power_heuristic(scatter_pdf, scene.light_pdf(&previous, &direction, index))
+                    }
+                    _ => 1.,
+                };
+
+                let light = throughput * channels.spectrum_of(&(weight * emitted));
+                gathered.add(light, depth);
+                if let Some(index) = record.light {
+                    gathered.add_to_light(index, light);
+                }
+            }
+
+            if let Some((index, light, pick_pdf)) = scene.pick_light(&record.p, rng) {
+                if let Some(sample) = light.sample(&record.p, rng) {
+                    let f = record.material.eval(&ray, &record, &sample.wi);
+                    let light_pdf = pick_pdf * sample.pdf;
+
+                    if f != vec3!(0.) && light_pdf > 0. {
+                        let transmittance = scene.transmittance(&record.p, &sample.wi, sample.distance);
+                        let weight = if light.is_delta() {
+                            1.
+                        } else {
+                            power_heuristic(light_pdf, record.material.pdf(&ray, &record, &sample.wi))
+                        };
+                        let contribution = f * sample.radiance * (transmittance * weight / light_pdf);
+
+                        let light = throughput * channels.spectrum_of(&contribution);
+                        gathered.add(light, depth + 1);
+                        gathered.add_to_light(index, light);
+                    }
+                }
+            }
+
+            let scatter = match record.material.scatter(&ray, &record, rng) {
+                Some(scatter) => scatter,
+                None => break,
+            };
+
+            scatter_pdf = if scatter.specular {
+                0.
+            } else {
+                let direction = scatter.ray.direction().unit_vector();
+                record.material.pdf(&ray, &record, &direction)
+            };
+
+            if record.material.dispersive() {
+                channels.terminate_secondary();
+            }
+
+            throughput *= channels.spectrum_of(&scatter.attenuation);
+            previous = record.p;
+            ray = scatter.ray.with_wavelength(channels.hero());
+            depth += 1;
+        }
+
+        if let Some(aovs) = aovs {
+            aovs.direct = channels.to_rgb(&gathered.direct);
+            aovs.indirect = channels.to_rgb(&gathered.indirect);
+            for (aov, light) in aovs.lights.iter_mut().zip(gathered.lights.iter().flatten()) {
+                *aov = channels.to_rgb(light);
+            }
+        }
+
+        (channels.to_rgb(&(gathered.direct + gathered.indirect)), depth)
+    }
+}
+
+impl Integrator for PathTracer {
+    fn color(&self, ray: Ray, scene: &Scene, rng: &mut dyn RngCore) -> Vec3 {
+        self.trace(ray, scene, rng, None).0
+    }
+
+    fn color_with_aovs(&self, ray: Ray, scene: &Scene, rng: &mut dyn RngCore, aovs: &mut Aovs) -> Vec3 {
+        self.trace(ray, scene, rng, Some(aovs)).0
+    }
+
+    fn has_aovs(&self) -> bool {
+        true
+    }
+}
+
+/// Ambient occlusion: how much of the hemisphere above the first hit is
+/// open, looking no further than `distance`. Rays that miss the scene come
+/// out white.
+pub struct AmbientOcclusion {
+    distance: f32,
+}
+
+impl AmbientOcclusion {
+    pub fn new(distance: f32) -> AmbientOcclusion {
+        AmbientOcclusion { distance }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn color(&self, mut ray: Ray, scene: &Scene, rng: &mut dyn RngCore) -> Vec3 {
+        // Partially opaque surfaces are passed through stochastically, as
+        // path tracing does.
+        let record = loop {
+            let record = match scene.hit(&ray, 0.001, f32::MAX) {
+                Some(record) => record,
+                None => return vec3!(1.),
+            };
+
+            let opacity = record.material.opacity(&record);
+            if opacity < 1. && rng.gen::<f32>() >= opacity {
+                ray = Ray::new(record.p, *ray.direction());
+                continue;
+            }
+            break record;
+        };
+
+        // Cosine weighting makes the estimate the fraction of diffuse light
+        // from a uniformly bright sky that gets through.
+        let normal = if record.normal.dot(ray.direction()) > 0. {
+            -record.normal
+        } else {
+            record.normal
+        };
+        let direction = Onb::from_w(&normal).to_world(&random_cosine_direction(rng));
+
+        vec3!(scene.transmittance(&record.p, &direction, self.distance))
+    }
+
+    fn is_radiance(&self) -> bool {
+        false
+    }
+}
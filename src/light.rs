@@ -0,0 +1,832 @@
+use rand::{Rng, RngCore};
+use std::f32::consts::PI;
+use std::path::Path;
+
+use crate::distribution::Distribution2D;
+use crate::ies::IesProfile;
+use crate::light_sampler::LightBounds;
+use crate::image::Image;
+use crate::aabb::Aabb;
+use crate::material::{luminance, random_cosine_direction};
+use crate::objects::{random_unit_vector, Shape};
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// A direction toward a light picked by `Light::sample`.
+pub struct LightSample {
+    pub wi: Vec3,
+    /// How far along `wi` the light is, or infinity for lights at infinity.
+    pub distance: f32,
+    /// The surface normal at the sampled point, or zero for lights without a
+    /// surface.
+    pub normal: Vec3,
+    pub radiance: Vec3,
+    /// The solid angle density with which `wi` was picked.
+    pub pdf: f32,
+}
+
+/// A ray of light leaving a light, picked by `Light::sample_emission`.
+pub struct EmissionSample {
+    pub ray: Ray,
+    /// The surface normal at the origin, or the direction of the ray for
+    /// lights without a surface.
+    pub normal: Vec3,
+    pub radiance: Vec3,
+    /// The area density of the origin, which is one for point lights.
+    pub pdf_position: f32,
+    /// The solid angle density of the direction, which is one for
+    /// directional lights.
+    pub pdf_direction: f32,
+}
+
+/// Where the disk that rays from a light at infinity start on lies, for a
+/// scene within `bounds`: its center and radius.
+fn bounding_sphere(bounds: &Aabb) -> (Vec3, f32) {
+    (bounds.center(), (0.5 * bounds.diagonal().length()).max(1e-3))
+}
+
+/// Something that emits light and can be sampled directly from a point in
+/// the scene.
+pub trait Light {
+    fn sample(&self, p: &Vec3, rng: &mut dyn RngCore) -> Option<LightSample>;
+
+    /// The solid angle density with which `sample` picks the unit direction
+    /// `wi` from `p`. Always zero for delta lights.
+    fn pdf(&self, p: &Vec3, wi: &Vec3) -> f32;
+
+    /// The radiance arriving along rays that leave the scene heading in the
+    /// unit direction `direction`.
+    fn environment(&self, _direction: &Vec3) -> Vec3 {
+        vec3!(0.)
+    }
+
+    /// Whether the light surrounds the scene, rather than sitting in it.
+    fn is_infinite(&self) -> bool {
+        false
+    }
+
+    /// Whether the light is a point or direction that rays can never hit by
+    /// chance, so `sample` returns a `pdf` of one rather than a density.
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    /// Whether light arrives from infinitely far away, as from lights at
+    /// infinity and directional lights.
+    fn is_distant(&self) -> bool {
+        self.is_infinite()
+    }
+
+    /// Where the light is and how much it emits, for picking between many
+    /// lights. Lights at infinity have no bounds.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+
+    /// Picks a ray leaving the light into a scene within `bounds`, for
+    /// tracing paths from the lights. Distant lights send parallel rays from
+    /// a disk covering the scene.
+    fn sample_emission(&self, bounds: &Aabb, rng: &mut dyn RngCore) -> Option<EmissionSample> {
+        if !self.is_distant() {
+            return None;
+        }
+
+        let (center, radius) = bounding_sphere(bounds);
+        let sample = self.sample(&center, rng)?;
+        let frame = Onb::from_w(&sample.wi);
+        let r = radius * rng.gen::<f32>().sqrt();
+        let phi = 2. * PI * rng.gen::<f32>();
+        let origin = center + frame.to_world(&vec3!(r * phi.cos(), r * phi.sin(), radius));
+
+        Some(EmissionSample {
+            ray: Ray::new(origin, -sample.wi),
+            normal: -sample.wi,
+            radiance: sample.radiance,
+            pdf_position: 1. / (PI * radius * radius),
+            pdf_direction: sample.pdf,
+        })
+    }
+
+    /// The densities with which `sample_emission` would pick a ray leaving
+    /// `p`, with normal `normal`, along the unit direction `direction`.
+    fn pdf_emission(&self, bounds: &Aabb, _p: &Vec3, _normal: &Vec3, direction: &Vec3) -> (f32, f32) {
+        if !self.is_distant() {
+            return (0., 0.);
+        }
+
+        let (center, radius) = bounding_sphere(bounds);
+        (1. / (PI * radius * radius), self.pdf(&center, &-direction))
+    }
+}
+
+/// Light emitted uniformly from the front of a shape, whose material should
+/// be a `DiffuseLight` of the same emission.
+pub struct AreaLight {
+    shape: Box<dyn Shape>,
+    emission: Vec3,
+}
+
+impl AreaLight {
+    pub fn new(shape: Box<dyn Shape>, emission: Vec3) -> AreaLight {
+        AreaLight { shape, emission }
+    }
+}
+
+impl Light for AreaLight {
+    fn sample(&self, p: &Vec3, rng: &mut dyn RngCore) -> Option<LightSample> {
+        let (q, normal, pdf) = self.shape.sample(p, rng)?;
+        let to_light = q - p;
+        let distance = to_light.length();
+        let wi = to_light / distance;
+
+        if pdf <= 0. || !pdf.is_finite() || normal.dot(&wi) >= 0. {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            distance,
+            normal,
+            radiance: self.emission,
+            pdf,
+        })
+    }
+
+    fn pdf(&self, p: &Vec3, wi: &Vec3) -> f32 {
+        self.shape.pdf(p, wi)
+    }
+
+    fn sample_emission(&self, _bounds: &Aabb, rng: &mut dyn RngCore) -> Option<EmissionSample> {
+        let (origin, normal) = self.shape.sample_area(rng);
+        let local = random_cosine_direction(rng);
+        let direction = Onb::from_w(&normal).to_world(&local);
+
+        Some(EmissionSample {
+            ray: Ray::new(origin, direction),
+            normal,
+            radiance: self.emission,
+            pdf_position: 1. / self.shape.area(),
+            pdf_direction: local.z / PI,
+        })
+    }
+
+    fn pdf_emission(&self, _bounds: &Aabb, _p: &Vec3, normal: &Vec3, direction: &Vec3) -> (f32, f32) {
+        (1. / self.shape.area(), normal.dot(direction).max(0.) / PI)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let (w, cos_theta_o) = self.shape.normal_bounds();
+
+        Some(LightBounds {
+            bounds: self.shape.bounding_box(),
+            phi: PI * luminance(&self.emission) * self.shape.area(),
+            w,
+            cos_theta_o,
+            cos_theta_e: 0.,
+            two_sided: false,
+        })
+    }
+}
+
+/// A photometric profile along with the frame it is measured in, where `w`
+/// points toward the nadir, `u` along the 0° horizontal plane and `v` along
+/// the 90° plane, counterclockwise seen from above as in LM-63.
+struct Profile {
+    profile: IesProfile,
+    frame: Onb,
+}
+
+impl Profile {
+    /// Without an `aim`, the 0° plane points along `+x` for a luminaire
+    /// hanging straight down, and tilts with it the shortest way to any
+    /// other nadir, so the frame only turns abruptly for luminaires facing
+    /// straight up. With one, the 0° plane points along `aim` projected
+    /// across the nadir.
+    fn new(profile: IesProfile, nadir: &Vec3, aim: Option<Vec3>) -> Profile {
+        let w = nadir.unit_vector();
+        let aim = aim.map(|aim| aim - w * w.dot(&aim)).filter(|aim| aim.squared_length() > 1e-12);
+        let u = match aim {
+            Some(aim) => aim.unit_vector(),
+            // +x rotated about `-y × w`, which takes `-y` to `w`.
+            None if w.y < 1. - 1e-6 => vec3!(1. - w.x * w.x / (1. - w.y), w.x, -w.x * w.z / (1. - w.y)).unit_vector(),
+            None => vec3!(1., 0., 0.),
+        };
+
+        Profile {
+            profile,
+            frame: Onb { u, v: u.cross(&w), w },
+        }
+    }
+
+    /// The intensity toward the unit direction `w` as a fraction of the
+    /// peak.
+    fn scale(&self, w: &Vec3) -> f32 {
+        let max = self.profile.max_candela();
+        if max <= 0. {
+            return 0.;
+        }
+
+        let local = self.frame.to_local(w);
+        let vertical = local.z.clamp(-1., 1.).acos().to_degrees();
+        let horizontal = local.y.atan2(local.x).to_degrees();
+        self.profile.candela(vertical, horizontal) / max
+    }
+}
+
+/// A point emitting equally in all directions, with inverse square falloff.
+pub struct PointLight {
+    position: Vec3,
+    intensity: Vec3,
+    profile: Option<Profile>,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+            profile: None,
+        }
+    }
+
+    /// Shapes the emission with a luminaire's photometric profile, hanging
+    /// with its nadir pointing down `-y` and its 0° plane toward `aim`, or
+    /// `+x` without one. The intensity becomes that of the brightest
+    /// direction.
+    pub fn with_profile(self, profile: IesProfile, aim: Option<Vec3>) -> PointLight {
+        PointLight {
+            profile: Some(Profile::new(profile, &vec3!(0., -1., 0.), aim)),
+            ..self
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: &Vec3, _rng: &mut dyn RngCore) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.length();
+        let wi = to_light / distance;
+        let scale = self.profile.as_ref().map_or(1., |profile| profile.scale(&-wi));
+
+        if scale <= 0. {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            distance,
+            normal: vec3!(0.),
+            radiance: self.intensity * scale / (distance * distance),
+            pdf: 1.,
+        })
+    }
+
+    fn pdf(&self, _p: &Vec3, _wi: &Vec3) -> f32 {
+        0.
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample_emission(&self, _bounds: &Aabb, rng: &mut dyn RngCore) -> Option<EmissionSample> {
+        let direction = random_unit_vector(rng);
+        let scale = self.profile.as_ref().map_or(1., |profile| profile.scale(&direction));
+
+        Some(EmissionSample {
+            ray: Ray::new(self.position, direction),
+            normal: direction,
+            radiance: self.intensity * scale,
+            pdf_position: 1.,
+            pdf_direction: 1. / (4. * PI),
+        })
+    }
+
+    fn pdf_emission(&self, _bounds: &Aabb, _p: &Vec3, _normal: &Vec3, _direction: &Vec3) -> (f32, f32) {
+        (1., 1. / (4. * PI))
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: Aabb::new(self.position, self.position),
+            phi: 4. * PI * luminance(&self.intensity),
+            w: vec3!(0., 0., 1.),
+            cos_theta_o: -1.,
+            cos_theta_e: 0.,
+            two_sided: false,
+        })
+    }
+}
+
+/// A point light restricted to a cone. Full intensity inside the inner
+/// angle ramps down to nothing at the outer angle, with the ramp raised to
+/// the power of `falloff`.
+pub struct SpotLight {
+    position: Vec3,
+    direction: Vec3,
+    intensity: Vec3,
+    cos_inner: f32,
+    cos_outer: f32,
+    falloff: f32,
+    profile: Option<Profile>,
+}
+
+impl SpotLight {
+    /// The cone angles are measured from `direction` to the edge, in
+    /// degrees.
+    pub fn new(position: Vec3, direction: Vec3, intensity: Vec3, inner: f32, outer: f32) -> SpotLight {
+        let outer = outer.max(inner);
+
+        SpotLight {
+            position,
+            direction: direction.unit_vector(),
+            intensity,
+            cos_inner: inner.to_radians().cos(),
+            cos_outer: outer.to_radians().cos(),
+            falloff: 1.,
+            profile: None,
+        }
+    }
+
+    pub fn with_falloff(self, falloff: f32) -> SpotLight {
+        SpotLight { falloff, ..self }
+    }
+
+    /// Shapes the emission inside the cone with a luminaire's photometric
+    /// profile, with its nadir along the spot direction and its 0° plane
+    /// toward `aim` (see `Profile::new`). The intensity becomes that of the
+    /// brightest direction.
+    pub fn with_profile(self, profile: IesProfile, aim: Option<Vec3>) -> SpotLight {
+        SpotLight {
+            profile: Some(Profile::new(profile, &self.direction, aim)),
+            ..self
+        }
+    }
+
+    fn cone(&self, cosine: f32) -> f32 {
+        if cosine >= self.cos_inner {
+            return 1.;
+        }
+
+        let t = (cosine - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t.clamp(0., 1.).powf(self.falloff)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: &Vec3, _rng: &mut dyn RngCore) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.length();
+        let wi = to_light / distance;
+
+        let mut cone = self.cone(-wi.dot(&self.direction));
+        if let Some(profile) = &self.profile {
+            cone *= profile.scale(&-wi);
+        }
+        if cone <= 0. {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            distance,
+            normal: vec3!(0.),
+            radiance: self.intensity * cone / (distance * distance),
+            pdf: 1.,
+        })
+    }
+
+    fn pdf(&self, _p: &Vec3, _wi: &Vec3) -> f32 {
+        0.
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample_emission(&self, _bounds: &Aabb, rng: &mut dyn RngCore) -> Option<EmissionSample> {
+        let cos_theta = 1. - rng.gen::<f32>() * (1. - self.cos_outer);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * rng.gen::<f32>();
+        let direction = Onb::from_w(&self.direction).to_world(&vec3!(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+
+        let mut cone = self.cone(cos_theta);
+        if let Some(profile) = &self.profile {
+            cone *= profile.scale(&direction);
+        }
+
+        Some(EmissionSample {
+            ray: Ray::new(self.position, direction),
+            normal: direction,
+            radiance: self.intensity * cone,
+            pdf_position: 1.,
+            pdf_direction: 1. / (2. * PI * (1. - self.cos_outer)),
+        })
+    }
+
+    fn pdf_emission(&self, _bounds: &Aabb, _p: &Vec3, _normal: &Vec3, direction: &Vec3) -> (f32, f32) {
+        if direction.dot(&self.direction) >= self.cos_outer {
+            (1., 1. / (2. * PI * (1. - self.cos_outer)))
+        } else {
+            (1., 0.)
+        }
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let solid_angle = 2. * PI * (1. - 0.5 * (self.cos_inner + self.cos_outer));
+        let spread = self.cos_outer.acos() - self.cos_inner.acos();
+
+        Some(LightBounds {
+            bounds: Aabb::new(self.position, self.position),
+            phi: solid_angle * luminance(&self.intensity),
+            w: self.direction,
+            cos_theta_o: self.cos_inner,
+            cos_theta_e: spread.cos(),
+            two_sided: false,
+        })
+    }
+}
+
+/// Parallel light travelling along `direction`, as from a very distant
+/// source.
+pub struct DirectionalLight {
+    direction: Vec3,
+    irradiance: Vec3,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Vec3) -> DirectionalLight {
+        DirectionalLight {
+            direction: direction.unit_vector(),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: &Vec3, _rng: &mut dyn RngCore) -> Option<LightSample> {
+        Some(LightSample {
+            wi: -self.direction,
+            distance: f32::INFINITY,
+            normal: vec3!(0.),
+            radiance: self.irradiance,
+            pdf: 1.,
+        })
+    }
+
+    fn pdf(&self, _p: &Vec3, _wi: &Vec3) -> f32 {
+        0.
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn is_distant(&self) -> bool {
+        true
+    }
+}
+
+/// Parses a list of lights, one per line:
+///
+/// ```text
+/// # Comments and blank lines are ignored.
+/// point       <x y z> <r g b>
+/// spot        <x y z> <dx dy dz> <r g b> <inner> <outer> [falloff]
+/// directional <dx dy dz> <r g b>
+/// ```
+///
+/// Spot cone angles are in degrees, and the direction of a directional
+/// light is the way its light travels. Point and spot lines can end with
+/// `ies <file> [aim <x y z>]` to shape them with a photometric profile whose
+/// 0° plane points along `aim`; relative files are found from `directory`,
+/// normally the one holding the lights file.
+pub fn parse_lights(text: &str, directory: &Path) -> Result<Vec<Box<dyn Light>>, String> {
+    let mut lights: Vec<Box<dyn Light>> = vec![];
+
+    for (number, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        let mut words = line.split_whitespace();
+        let kind = match words.next() {
+            Some(kind) => kind,
+            None => continue,
+        };
+
+        let mut words: Vec<&str> = words.collect();
+        let aim = match words.iter().position(|word| *word == "aim") {
+            Some(i) => {
+                let aim = words[i + 1..]
+                    .iter()
+                    .map(|word| word.parse::<f32>())
+                    .collect::<Result<Vec<f32>, _>>()
+                    .map_err(|e| format!("line {}: {}", number + 1, e))?;
+                if aim.len() != 3 {
+                    return Err(format!("line {}: expected 3 numbers after `aim`, got {}", number + 1, aim.len()));
+                }
+                words.truncate(i);
+                Some(vec3!(aim[0], aim[1], aim[2]))
+            }
+            None => None,
+        };
+        let profile = match words.iter().position(|word| *word == "ies") {
+            Some(i) if kind == "point" || kind == "spot" => {
+                let path = words
+                    .get(i + 1)
+                    .ok_or_else(|| format!("line {}: missing IES file", number + 1))?;
+                let profile = IesProfile::read(directory.join(path))
+                    .map_err(|e| format!("line {}: couldn't read `{}`: {}", number + 1, path, e))?;
+                words.truncate(i);
+                Some(profile)
+            }
+            _ => None,
+        };
+        if aim.is_some() && profile.is_none() {
+            return Err(format!("line {}: `aim` needs an IES profile", number + 1));
+        }
+
+        let values = words
+            .into_iter()
+            .map(|word| word.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .map_err(|e| format!("line {}: {}", number + 1, e))?;
+        let vector = |i: usize| vec3!(values[i], values[i + 1], values[i + 2]);
+        let expect = |counts: &[usize]| {
+            if counts.contains(&values.len()) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "line {}: expected {:?} numbers for a {} light, got {}",
+                    number + 1,
+                    counts,
+                    kind,
+                    values.len()
+                ))
+            }
+        };
+
+        match kind {
+            "point" => {
+                expect(&[6])?;
+                let light = PointLight::new(vector(0), vector(3));
+                lights.push(Box::new(match profile {
+                    Some(profile) => light.with_profile(profile, aim),
+                    None => light,
+                }));
+            }
+            "spot" => {
+                expect(&[11, 12])?;
+                let light = SpotLight::new(vector(0), vector(3), vector(6), values[9], values[10]);
+                let falloff = values.get(11).copied().unwrap_or(1.);
+                let light = light.with_falloff(falloff);
+                lights.push(Box::new(match profile {
+                    Some(profile) => light.with_profile(profile, aim),
+                    None => light,
+                }));
+            }
+            "directional" => {
+                expect(&[6])?;
+                lights.push(Box::new(DirectionalLight::new(vector(0), vector(3))));
+            }
+            other => return Err(format!("line {}: unknown light `{}`", number + 1, other)),
+        }
+    }
+
+    Ok(lights)
+}
+
+/// Cuts a line at the first `#` that starts a word, leaving any in the
+/// middle of a file name.
+fn strip_comment(line: &str) -> &str {
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        if c == '#' && previous.is_whitespace() {
+            return &line[..i];
+        }
+        previous = c;
+    }
+    line
+}
+
+/// An image of the surroundings in equirectangular (latitude-longitude)
+/// layout, such as a `.hdr` or `.exr` light probe. Directions are sampled in
+/// proportion to the brightness of the image.
+pub struct EnvironmentLight {
+    image: Image,
+    distribution: Distribution2D,
+    /// The rotation about the vertical axis, in radians.
+    rotation: f32,
+    intensity: f32,
+}
+
+impl EnvironmentLight {
+    pub fn new(image: Image) -> EnvironmentLight {
+        let (width, height) = (image.width(), image.height());
+        let mut func = Vec::with_capacity(width * height);
+
+        // Rows near the poles cover less solid angle, so they are sampled
+        // less often.
+        for y in 0..height {
+            let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+            for x in 0..width {
+                func.push(luminance(&image.get(x, y)).max(0.) * sin_theta);
+            }
+        }
+
+        EnvironmentLight {
+            distribution: Distribution2D::new(&func, width, height),
+            image,
+            rotation: 0.,
+            intensity: 1.,
+        }
+    }
+
+    /// Turns the environment about the vertical axis by `degrees`.
+    pub fn with_rotation(self, degrees: f32) -> EnvironmentLight {
+        EnvironmentLight {
+            rotation: degrees.to_radians(),
+            ..self
+        }
+    }
+
+    /// Scales the brightness of the image.
+    pub fn with_intensity(self, intensity: f32) -> EnvironmentLight {
+        EnvironmentLight { intensity, ..self }
+    }
+
+    /// The image coordinates of a world space direction. The centre of the
+    /// image faces down `-z`, and `v` runs from the top.
+    fn uv(&self, direction: &Vec3) -> (f32, f32) {
+        let (sin, cos) = self.rotation.sin_cos();
+        let x = cos * direction.x - sin * direction.z;
+        let z = sin * direction.x + cos * direction.z;
+
+        let u = 0.5 + x.atan2(-z) / (2. * PI);
+        let v = direction.y.clamp(-1., 1.).acos() / PI;
+        (u.rem_euclid(1.), v)
+    }
+
+    fn direction(&self, u: f32, v: f32) -> Vec3 {
+        let phi = 2. * PI * (u - 0.5);
+        let theta = PI * v;
+        let (x, z) = (theta.sin() * phi.sin(), -theta.sin() * phi.cos());
+
+        let (sin, cos) = self.rotation.sin_cos();
+        vec3!(cos * x + sin * z, theta.cos(), -sin * x + cos * z)
+    }
+
+    fn lookup(&self, u: f32, v: f32) -> Vec3 {
+        let x = ((u * self.image.width() as f32) as usize).min(self.image.width() - 1);
+        let y = ((v * self.image.height() as f32) as usize).min(self.image.height() - 1);
+        self.intensity * self.image.get(x, y)
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn sample(&self, _p: &Vec3, rng: &mut dyn RngCore) -> Option<LightSample> {
+        let ((u, v), pdf) = self.distribution.sample(rng.gen(), rng.gen());
+        let sin_theta = (PI * v).sin();
+        if pdf <= 0. || sin_theta <= 0. {
+            return None;
+        }
+
+        Some(LightSample {
+            wi: self.direction(u, v),
+            distance: f32::INFINITY,
+            normal: vec3!(0.),
+            radiance: self.lookup(u, v),
+            pdf: pdf / (2. * PI * PI * sin_theta),
+        })
+    }
+
+    fn pdf(&self, _p: &Vec3, wi: &Vec3) -> f32 {
+        let (u, v) = self.uv(wi);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0. {
+            return 0.;
+        }
+
+        self.distribution.pdf(u, v) / (2. * PI * PI * sin_theta)
+    }
+
+    fn environment(&self, direction: &Vec3) -> Vec3 {
+        let (u, v) = self.uv(direction);
+        self.lookup(u, v)
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_light_falloff_and_cones() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let bounds = Aabb::new(vec3!(-1.), vec3!(1.));
+        let p = vec3!(0.);
+
+        // Point lights fall off with the square of the distance, and agree
+        // with themselves about the densities of the rays they emit.
+        let point = PointLight::new(vec3!(0., 2., 0.), vec3!(4.));
+        let near = point.sample(&p, &mut rng).unwrap();
+        let far = point.sample(&vec3!(0., -2., 0.), &mut rng).unwrap();
+        assert_eq!((near.wi, near.distance), (vec3!(0., 1., 0.), 2.));
+        assert_eq!(near.radiance, vec3!(1.));
+        assert_eq!(far.radiance, vec3!(0.25));
+
+        let emitted = point.sample_emission(&bounds, &mut rng).unwrap();
+        let pdf = point.pdf_emission(&bounds, emitted.ray.origin(), &emitted.normal, emitted.ray.direction());
+        assert_eq!(pdf, (emitted.pdf_position, emitted.pdf_direction));
+
+        // Spot lights are full inside the inner cone, ramp down to the
+        // outer one and are dark outside it.
+        let spot = SpotLight::new(vec3!(0., 1., 0.), vec3!(0., -1., 0.), vec3!(1.), 30., 60.);
+        // The point on the ground `angle` degrees off the spot direction.
+        let degrees = |angle: f32| vec3!(angle.to_radians().tan(), 0., 0.);
+        let inside = spot.sample(&degrees(20.), &mut rng).unwrap();
+        assert!((inside.radiance.x * inside.distance * inside.distance - 1.).abs() < 1e-5);
+        let edge = spot.sample(&degrees(45.), &mut rng).unwrap();
+        let expected = (45f32.to_radians().cos() - 0.5) / (30f32.to_radians().cos() - 0.5);
+        assert!((edge.radiance.x * edge.distance * edge.distance - expected).abs() < 1e-5);
+        assert!(spot.sample(&degrees(70.), &mut rng).is_none());
+
+        let emitted = spot.sample_emission(&bounds, &mut rng).unwrap();
+        assert!(emitted.ray.direction().dot(&vec3!(0., -1., 0.)) >= 0.5 - 1e-5);
+        let pdf = spot.pdf_emission(&bounds, emitted.ray.origin(), &emitted.normal, emitted.ray.direction());
+        assert_eq!(pdf, (emitted.pdf_position, emitted.pdf_direction));
+
+        // Directional light arrives the same from everywhere.
+        let directional = DirectionalLight::new(vec3!(0., -2., 0.), vec3!(3.));
+        for q in [p, vec3!(100., -50., 3.)].iter() {
+            let sample = directional.sample(q, &mut rng).unwrap();
+            assert_eq!((sample.wi, sample.radiance), (vec3!(0., 1., 0.), vec3!(3.)));
+            assert_eq!(sample.distance, f32::INFINITY);
+        }
+    }
+
+    #[test]
+    fn test_parse_lights() {
+        // A profile in a directory with `#` in its name, next to the
+        // lights file rather than the working directory.
+        let directory = std::env::temp_dir().join(format!("lights_{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("lamps#2")).unwrap();
+        let ies = "IESNA91\nTILT=NONE\n1 1000 1 2 1 1 2 0 0 0\n1 1 10\n0 90\n0\n200 100\n";
+        std::fs::write(directory.join("lamps#2/down.ies"), ies).unwrap();
+
+        let text = "# Lights\n\npoint 0 2 0 4 4 4 # overhead\n\
+                    point 0 2 0 4 4 4 ies lamps#2/down.ies #profiled\n\
+                    directional 0 -1 0 1 1 1\n\
+                    spot 0 2 0 0 -1 0 4 4 4 30 40 ies lamps#2/down.ies aim 0 0 1\n";
+        let lights = parse_lights(text, &directory);
+        let missing = parse_lights("point 0 2 0 4 4 4 ies lamps#2/down.ies", Path::new(""));
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert!(missing.is_err());
+        let lights = lights.unwrap();
+        assert_eq!(lights.len(), 4);
+        let mut rng = StdRng::seed_from_u64(1);
+        let plain = lights[0].sample(&vec3!(0.), &mut rng).unwrap();
+        let profiled = lights[1].sample(&vec3!(0.), &mut rng).unwrap();
+        assert_eq!(plain.radiance, vec3!(1.));
+        assert_eq!(profiled.radiance, vec3!(1.));
+        // `1#dim` is one word, so it isn't a comment.
+        assert!(parse_lights("directional 0 -1 0 1 1 1#dim", &directory).is_err());
+        assert!(parse_lights("point 0 2 0 4 4 4 aim 0 0 1", &directory).is_err());
+    }
+
+    #[test]
+    fn test_profile_frame() {
+        // Brightest toward 0°, dimming counterclockwise seen from above.
+        let ies = "IESNA91\nTILT=NONE\n1 1000 1 2 4 1 2 0 0 0\n1 1 10\n0 90\n0 90 180 270\n\
+                   400 400 400 300 400 200 400 100\n";
+        let down = vec3!(0., -1., 0.);
+        let diagonal = |x: f32, z: f32| vec3!(x, -1., z).unit_vector();
+
+        let profile = Profile::new(IesProfile::parse(ies).unwrap(), &down, None);
+        assert_eq!(profile.scale(&diagonal(1., 0.)), 1.);
+        assert_eq!(profile.scale(&diagonal(0., -1.)), 0.875);
+        assert_eq!(profile.scale(&diagonal(-1., 0.)), 0.75);
+        assert_eq!(profile.scale(&diagonal(0., 1.)), 0.625);
+
+        let aimed = Profile::new(IesProfile::parse(ies).unwrap(), &down, Some(vec3!(0., 5., 2.)));
+        assert!((aimed.scale(&diagonal(0., 1.)) - 1.).abs() < 1e-5);
+        assert!((aimed.scale(&diagonal(1., 0.)) - 0.875).abs() < 1e-5);
+
+        // Nudging the nadir barely moves the frame, wherever it points
+        // other than straight up.
+        let nadirs = [vec3!(1., -1., 1e-3), vec3!(0.01, -1., 1e-3), vec3!(1., 1e-3, 0.), vec3!(1e-3, 0., 1.)];
+        for nadir in nadirs.iter() {
+            let nudge = vec3!(-1e-3, 2e-3, -2e-3);
+            let a = Profile::new(IesProfile::parse(ies).unwrap(), nadir, None).frame;
+            let b = Profile::new(IesProfile::parse(ies).unwrap(), &(nadir + nudge), None).frame;
+            assert!((a.u - b.u).length() < 1e-2 && (a.v - b.v).length() < 1e-2);
+            assert!(a.u.dot(&a.w).abs() < 1e-5 && (a.u.cross(&a.w) - a.v).length() < 1e-5);
+        }
+    }
+}
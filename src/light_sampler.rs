@@ -0,0 +1,490 @@
+use std::f32::consts::PI;
+
+use crate::aabb::Aabb;
+use crate::distribution::Distribution1D;
+use crate::light::Light;
+use crate::vec3::Vec3;
+
+/// Where a light is and which way it shines, for estimating how much it
+/// could contribute to a point without sampling it.
+#[derive(Copy, Clone, Debug)]
+pub struct LightBounds {
+    pub bounds: Aabb,
+    /// The total emitted power, as luminance.
+    pub phi: f32,
+    /// The axis of the cone that contains every surface normal.
+    pub w: Vec3,
+    /// The cosine of the half angle of that cone.
+    pub cos_theta_o: f32,
+    /// The cosine of the angle beyond the normals at which light is still
+    /// emitted.
+    pub cos_theta_e: f32,
+    pub two_sided: bool,
+}
+
+/// `cos(max(0, a - b))` from the sines and cosines of `a` and `b`.
+fn cos_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        1.
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+
+/// `sin(max(0, a - b))` from the sines and cosines of `a` and `b`.
+fn sin_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        0.
+    } else {
+        sin_a * cos_b - cos_a * sin_b
+    }
+}
+
+fn sin_from_cos(cos: f32) -> f32 {
+    (1. - cos * cos).max(0.).sqrt()
+}
+
+/// Rotates `v` by `theta` about the unit `axis` (Rodrigues' formula).
+fn rotate(v: &Vec3, axis: &Vec3, theta: f32) -> Vec3 {
+    let (sin, cos) = theta.sin_cos();
+    cos * v + sin * axis.cross(v) + (1. - cos) * axis.dot(v) * axis
+}
+
+/// The smallest cone holding the cones of directions around `a` and `b`.
+fn union_cones(a: &Vec3, cos_a: f32, b: &Vec3, cos_b: f32) -> (Vec3, f32) {
+    let theta_a = cos_a.clamp(-1., 1.).acos();
+    let theta_b = cos_b.clamp(-1., 1.).acos();
+    let theta_d = a.dot(b).clamp(-1., 1.).acos();
+
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return (*a, cos_a);
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return (*b, cos_b);
+    }
+
+    let theta_o = (theta_a + theta_d + theta_b) / 2.;
+    let axis = a.cross(b);
+    if theta_o >= PI || axis.squared_length() < 1e-12 {
+        return (*a, -1.);
+    }
+
+    let w = rotate(a, &axis.unit_vector(), theta_o - theta_a);
+    (w.unit_vector(), theta_o.cos())
+}
+
+impl LightBounds {
+    pub fn union(&self, other: &LightBounds) -> LightBounds {
+        if self.phi <= 0. {
+            return *other;
+        }
+        if other.phi <= 0. {
+            return *self;
+        }
+
+        let (w, cos_theta_o) = union_cones(&self.w, self.cos_theta_o, &other.w, other.cos_theta_o);
+
+        LightBounds {
+            bounds: self.bounds.union(&other.bounds),
+            phi: self.phi + other.phi,
+            w,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    /// A conservative estimate of the light's contribution at `p`, after
+    /// pbrt-v4's take on Conty Estevez and Kulla (2018).
+    pub fn importance(&self, p: &Vec3) -> f32 {
+        let center = self.bounds.center();
+        let radius2 = self.bounds.diagonal().squared_length() / 4.;
+        let to_p = p - center;
+        let distance2 = to_p.squared_length().max(self.bounds.diagonal().length() / 2.);
+
+        let wi = to_p.unit_vector();
+        let mut cos_theta_w = self.w.dot(&wi);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        if !cos_theta_w.is_finite() {
+            // `p` is at the center, so light could arrive from anywhere.
+            cos_theta_w = 1.;
+        }
+        let sin_theta_w = sin_from_cos(cos_theta_w);
+
+        // The cone of directions from the center that the bounds subtend.
+        let cos_theta_b = if self.bounds.contains(p) || to_p.squared_length() <= radius2 {
+            -1.
+        } else {
+            (1. - radius2 / to_p.squared_length()).max(0.).sqrt()
+        };
+        let sin_theta_b = sin_from_cos(cos_theta_b);
+
+        let sin_theta_o = sin_from_cos(self.cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.;
+        }
+
+        self.phi * cos_theta_p / distance2
+    }
+}
+
+/// Picks which light to sample for next event estimation.
+pub trait LightSampler {
+    /// Returns the index of a light to sample from `p` along with the
+    /// probability of having picked it, given a uniform `u` in `[0, 1)`.
+    fn pick(&self, p: &Vec3, u: f32) -> Option<(usize, f32)>;
+
+    /// The probability with which `pick` returns `light` from `p`.
+    fn pmf(&self, p: &Vec3, light: usize) -> f32;
+}
+
+/// Picks every light with the same probability.
+pub struct UniformLightSampler {
+    count: usize,
+}
+
+impl UniformLightSampler {
+    pub fn new(lights: &[Box<dyn Light>]) -> UniformLightSampler {
+        UniformLightSampler { count: lights.len() }
+    }
+}
+
+impl LightSampler for UniformLightSampler {
+    fn pick(&self, _p: &Vec3, u: f32) -> Option<(usize, f32)> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let index = ((u * self.count as f32) as usize).min(self.count - 1);
+        Some((index, 1. / self.count as f32))
+    }
+
+    fn pmf(&self, _p: &Vec3, _light: usize) -> f32 {
+        if self.count == 0 {
+            0.
+        } else {
+            1. / self.count as f32
+        }
+    }
+}
+
+/// Lights without bounds, such as environments and directional lights, are
+/// picked uniformly with a probability proportional to their number, with
+/// the bounded lights counting as one more.
+struct Unbounded {
+    lights: Vec<usize>,
+    /// The probability of picking one of the unbounded lights at all.
+    probability: f32,
+}
+
+impl Unbounded {
+    fn new(lights: &[Box<dyn Light>], any_bounded: bool) -> Unbounded {
+        let unbounded: Vec<usize> = (0..lights.len()).filter(|&i| lights[i].bounds().is_none()).collect();
+        let probability = if any_bounded {
+            unbounded.len() as f32 / (unbounded.len() + 1) as f32
+        } else if unbounded.is_empty() {
+            0.
+        } else {
+            1.
+        };
+
+        Unbounded {
+            lights: unbounded,
+            probability,
+        }
+    }
+
+    /// Picks one of the unbounded lights if `u` falls in their share, or
+    /// otherwise returns `u` remapped to `[0, 1)` for the bounded lights.
+    fn pick(&self, u: f32) -> Result<(usize, f32), f32> {
+        if u < self.probability {
+            let n = self.lights.len();
+            let index = ((u / self.probability * n as f32) as usize).min(n - 1);
+            Ok((self.lights[index], self.probability / n as f32))
+        } else {
+            Err(((u - self.probability) / (1. - self.probability)).min(1. - f32::EPSILON))
+        }
+    }
+
+    fn pmf(&self, light: usize) -> Option<f32> {
+        if self.lights.contains(&light) {
+            Some(self.probability / self.lights.len() as f32)
+        } else {
+            None
+        }
+    }
+}
+
+/// Picks lights in proportion to the power they emit.
+pub struct PowerLightSampler {
+    unbounded: Unbounded,
+    bounded: Vec<usize>,
+    distribution: Option<Distribution1D>,
+    /// The position of each light in `bounded`.
+    slots: Vec<Option<usize>>,
+}
+
+impl PowerLightSampler {
+    pub fn new(lights: &[Box<dyn Light>]) -> PowerLightSampler {
+        let mut bounded = vec![];
+        let mut power = vec![];
+        let mut slots = vec![None; lights.len()];
+
+        for (i, light) in lights.iter().enumerate() {
+            if let Some(bounds) = light.bounds() {
+                if bounds.phi > 0. {
+                    slots[i] = Some(bounded.len());
+                    bounded.push(i);
+                    power.push(bounds.phi);
+                }
+            }
+        }
+
+        PowerLightSampler {
+            unbounded: Unbounded::new(lights, !bounded.is_empty()),
+            distribution: if power.is_empty() { None } else { Some(Distribution1D::new(power)) },
+            bounded,
+            slots,
+        }
+    }
+}
+
+impl LightSampler for PowerLightSampler {
+    fn pick(&self, _p: &Vec3, u: f32) -> Option<(usize, f32)> {
+        let u = match self.unbounded.pick(u) {
+            Ok(picked) => return Some(picked),
+            Err(u) => u,
+        };
+
+        let distribution = self.distribution.as_ref()?;
+        let (_, _, offset) = distribution.sample(u);
+        let pmf = self.pmf(&vec3!(0.), self.bounded[offset]);
+        Some((self.bounded[offset], pmf))
+    }
+
+    fn pmf(&self, _p: &Vec3, light: usize) -> f32 {
+        if let Some(pmf) = self.unbounded.pmf(light) {
+            return pmf;
+        }
+
+        match (&self.distribution, self.slots.get(light).copied().flatten()) {
+            (Some(distribution), Some(slot)) => {
+                let x = (slot as f32 + 0.5) / distribution.count() as f32;
+                (1. - self.unbounded.probability) * distribution.pdf(x) / distribution.count() as f32
+            }
+            _ => 0.,
+        }
+    }
+}
+
+/// A node of the light BVH. Interior nodes store the index of their second
+/// child; the first child follows them directly.
+struct LightNode {
+    bounds: LightBounds,
+    kind: LightNodeKind,
+}
+
+enum LightNodeKind {
+    Leaf(usize),
+    Interior(usize),
+}
+
+/// Picks lights by walking down a hierarchy of light bounds, choosing each
+/// child in proportion to its estimated contribution to the point being
+/// shaded, so nearby lights facing the point are favoured.
+pub struct BvhLightSampler {
+    unbounded: Unbounded,
+    nodes: Vec<LightNode>,
+    /// The left (0) and right (1) turns from the root to each light, lowest
+    /// bit first, or `None` for lights not in the tree.
+    trails: Vec<Option<u64>>,
+}
+
+impl BvhLightSampler {
+    pub fn new(lights: &[Box<dyn Light>]) -> BvhLightSampler {
+        let mut bounded: Vec<(usize, LightBounds)> = lights
+            .iter()
+            .enumerate()
+            .filter_map(|(i, light)| light.bounds().map(|bounds| (i, bounds)))
+            .filter(|(_, bounds)| bounds.phi > 0.)
+            .collect();
+
+        let mut sampler = BvhLightSampler {
+            unbounded: Unbounded::new(lights, !bounded.is_empty()),
+            nodes: vec![],
+            trails: vec![None; lights.len()],
+        };
+
+        if !bounded.is_empty() {
+            sampler.build(&mut bounded, 0, 0);
+        }
+        sampler
+    }
+
+    /// Appends the subtree for `lights`, reached by `trail` at `depth`, and
+    /// returns its bounds.
+    fn build(&mut self, lights: &mut [(usize, LightBounds)], trail: u64, depth: u32) -> LightBounds {
+        // Median splits keep the depth well within the 64 bits of a trail.
+        if lights.len() == 1 {
+            let (index, bounds) = lights[0];
+            self.trails[index] = Some(trail);
+            self.nodes.push(LightNode {
+                bounds,
+                kind: LightNodeKind::Leaf(index),
+            });
+            return bounds;
+        }
+
+        let centers = lights.iter().fold(Aabb::empty(), |centers, (_, bounds)| {
+            let center = bounds.bounds.center();
+            centers.union(&Aabb::new(center, center))
+        });
+        let axis = centers.longest_axis();
+
+        let mid = lights.len() / 2;
+        lights.select_nth_unstable_by(mid, |(_, a), (_, b)| {
+            a.bounds.center().axis(axis).total_cmp(&b.bounds.center().axis(axis))
+        });
+        let (left, right) = lights.split_at_mut(mid);
+
+        let node = self.nodes.len();
+        self.nodes.push(LightNode {
+            bounds: left[0].1,
+            kind: LightNodeKind::Interior(0),
+        });
+
+        let left_bounds = self.build(left, trail, depth + 1);
+        let second = self.nodes.len();
+        let right_bounds = self.build(right, trail | 1 << depth, depth + 1);
+
+        let bounds = left_bounds.union(&right_bounds);
+        self.nodes[node] = LightNode {
+            bounds,
+            kind: LightNodeKind::Interior(second),
+        };
+        bounds
+    }
+}
+
+impl LightSampler for BvhLightSampler {
+    fn pick(&self, p: &Vec3, u: f32) -> Option<(usize, f32)> {
+        let mut u = match self.unbounded.pick(u) {
+            Ok(picked) => return Some(picked),
+            Err(u) => u,
+        };
+
+        if self.nodes.is_empty() || self.nodes[0].bounds.importance(p) <= 0. {
+            return None;
+        }
+
+        let mut pmf = 1. - self.unbounded.probability;
+        let mut node = 0;
+
+        loop {
+            match self.nodes[node].kind {
+                LightNodeKind::Leaf(index) => return Some((index, pmf)),
+                LightNodeKind::Interior(second) => {
+                    let left = self.nodes[node + 1].bounds.importance(p);
+                    let right = self.nodes[second].bounds.importance(p);
+                    if left + right <= 0. {
+                        return None;
+                    }
+
+                    let p_left = left / (left + right);
+                    if u < p_left {
+                        u = (u / p_left).min(1. - f32::EPSILON);
+                        pmf *= p_left;
+                        node += 1;
+                    } else {
+                        u = ((u - p_left) / (1. - p_left)).min(1. - f32::EPSILON);
+                        pmf *= 1. - p_left;
+                        node = second;
+                    }
+                }
+            }
+        }
+    }
+
+    fn pmf(&self, p: &Vec3, light: usize) -> f32 {
+        if let Some(pmf) = self.unbounded.pmf(light) {
+            return pmf;
+        }
+
+        let mut trail = match self.trails.get(light).copied().flatten() {
+            Some(trail) => trail,
+            None => return 0.,
+        };
+
+        let mut pmf = 1. - self.unbounded.probability;
+        let mut node = 0;
+
+        loop {
+            match self.nodes[node].kind {
+                LightNodeKind::Leaf(_) => return pmf,
+                LightNodeKind::Interior(second) => {
+                    let left = self.nodes[node + 1].bounds.importance(p);
+                    let right = self.nodes[second].bounds.importance(p);
+                    if left + right <= 0. {
+                        return 0.;
+                    }
+
+                    if trail & 1 == 0 {
+                        pmf *= left / (left + right);
+                        node += 1;
+                    } else {
+                        pmf *= right / (left + right);
+                        node = second;
+                    }
+                    trail >>= 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::light::{DirectionalLight, PointLight, SpotLight};
+
+    #[test]
+    fn test_pick_matches_pmf() {
+        let mut lights: Vec<Box<dyn Light>> = vec![Box::new(DirectionalLight::new(vec3!(0., -1., 0.), vec3!(1.)))];
+        for i in 0..20 {
+            let position = vec3!(i as f32, 1. + (i % 3) as f32, -(i as f32) / 2.);
+            lights.push(Box::new(PointLight::new(position, vec3!(1. + i as f32))));
+            lights.push(Box::new(SpotLight::new(position, vec3!(1., -1., 0.), vec3!(5.), 20., 40.)));
+        }
+
+        let samplers: Vec<Box<dyn LightSampler>> = vec![
+            Box::new(UniformLightSampler::new(&lights)),
+            Box::new(PowerLightSampler::new(&lights)),
+            Box::new(BvhLightSampler::new(&lights)),
+        ];
+        let p = vec3!(3., 0., -2.);
+        let n = 100_000;
+
+        for sampler in samplers.iter() {
+            let total: f32 = (0..lights.len()).map(|i| sampler.pmf(&p, i)).sum();
+            assert!((total - 1.).abs() < 1e-4, "pmf sums to {}", total);
+
+            let mut counts = vec![0; lights.len()];
+            for i in 0..n {
+                let (index, pmf) = sampler.pick(&p, (i as f32 + 0.5) / n as f32).unwrap();
+                assert!((pmf - sampler.pmf(&p, index)).abs() < 1e-5);
+                counts[index] += 1;
+            }
+
+            for (i, count) in counts.iter().enumerate() {
+                let expected = sampler.pmf(&p, i);
+                assert!((*count as f32 / n as f32 - expected).abs() < 2e-3);
+            }
+        }
+    }
+}
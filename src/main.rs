@@ -1,87 +1,518 @@
 #[macro_use]
 mod vec3;
+mod aabb;
+mod adaptive;
+mod aov;
+mod bdpt;
+mod bvh;
 mod camera;
+mod checkpoint;
+mod debug;
+mod denoise;
+mod distribution;
+mod film;
+mod ies;
+mod image;
+mod inflate;
+mod integrator;
+mod light;
+mod light_sampler;
 mod material;
+mod mlt;
 mod objects;
+mod onb;
+mod options;
+mod photon_map;
+mod photon_mapping;
+mod progress;
 mod ray;
+mod sampler;
+mod scene;
+mod scenes;
+mod sky;
+mod spectrum;
+mod texture;
+mod tiles;
 
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+use std::path::Path;
+use std::time::Instant;
 
-use camera::Camera;
-use objects::{Hittable, HittableList};
-use ray::Ray;
+use adaptive::{AdaptiveSampling, Welford};
+use aov::{AovFilm, Aovs};
+use bdpt::Bdpt;
+use bvh::Bvh;
+use camera::{Camera, CubeMapCamera, EquirectangularCamera, FisheyeCamera, OrthographicCamera, PerspectiveCamera};
+use checkpoint::Checkpoint;
+use debug::{heatmap, DebugView};
+use denoise::{Denoiser, Features};
+use film::Filter;
+use image::Image;
+use integrator::{AmbientOcclusion, Integrator, PathTracer};
+use light::{EnvironmentLight, Light};
+use light_sampler::{BvhLightSampler, LightSampler, PowerLightSampler, UniformLightSampler};
+use mlt::Mlt;
+use objects::HittableList;
+use options::Options;
+use photon_mapping::{PhotonMapping, Sppm};
+use progress::Progress;
+use sampler::{BlueNoise, Halton, Independent, Sampler, SamplerRng, Sobol, Stratified};
+use scene::Scene;
+use sky::{GradientSky, PreethamSky, SunLight};
+use tiles::{tiles, Rect, TileOrder};
 use vec3::Vec3;
 
-const MAX_DEPTH: i32 = 50;
+/// How the image is made, chosen with `--integrator`. Most integrators work
+/// out each camera ray on its own, but some render the whole image at once.
+enum Renderer {
+    Rays(Box<dyn Integrator>),
+    Sppm(Sppm),
+    Mlt(Mlt),
+}
+
+fn main() {
+    let options = Options::from_args().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    // An equirectangular panorama is twice as wide as high, so that its
+    // pixels span as much latitude as longitude. The rest are 3:2, which
+    // gives a cube map square faces.
+    let shape = if options.camera == "equirectangular" { 2. } else { 1.5 };
+    let (width, height) = match (options.width, options.height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (width, (width as f32 / shape).round() as usize),
+        (None, Some(height)) => ((height as f32 * shape).round() as usize, height),
+        (None, None) => ((800. * shape) as usize, 800),
+    };
+    if width == 0 || height == 0 {
+        eprintln!("the image is empty");
+        std::process::exit(1);
+    }
 
-fn color(ray: Ray, world: &Box<dyn Hittable>, rng: &mut ThreadRng) -> Vec3 {
-    let mut ray = ray;
-    let mut mult: Vec3 = vec3!(1.);
-    let mut depth = 0;
+    let mut rng = Pcg32::seed_from_u64(options.seed);
 
-    loop {
-        if depth > MAX_DEPTH {
-            return vec3!(0.);
+    let look_from = vec3!(13., 2., 3.);
+    let look_at = vec3!(0., 0., 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.1;
+
+    let vup = vec3!(0., 1., 0.);
+    let aspect = width as f32 / height as f32;
+    let camera: Box<dyn Camera> = match options.camera.as_str() {
+        "perspective" => {
+            let fov = options.fov.unwrap_or(20.);
+            Box::new(PerspectiveCamera::new(look_from, look_at, vup, fov, aspect, aperture, dist_to_focus))
+        }
+        "orthographic" => {
+            // By default, as much as the perspective camera sees at the
+            // point it looks at.
+            let half_fov = options.fov.unwrap_or(20.).to_radians() / 2.;
+            let view_height = options
+                .view_height
+                .unwrap_or_else(|| 2. * half_fov.tan() * (look_from - look_at).length());
+            Box::new(OrthographicCamera::new(look_from, look_at, vup, view_height, aspect))
         }
+        "fisheye" => Box::new(FisheyeCamera::new(look_from, look_at, vup, options.fov.unwrap_or(180.), aspect)),
+        "equirectangular" => Box::new(EquirectangularCamera::new(look_from, look_at, vup)),
+        "cube-map" => Box::new(CubeMapCamera::new(look_from, look_at, vup)),
+        other => {
+            eprintln!("unknown camera `{}`", other);
+            std::process::exit(1);
+        }
+    };
 
-        if let Some(record) = world.hit(&ray, 0.001, std::f32::MAX) {
-            if let Some((attenuation, scattered)) = record.material.scatter(&ray, &record, rng) {
-                ray = scattered;
-                mult *= &attenuation;
-            } else {
-                return vec3!(0.);
-            }
-        } else {
-            let unit_direction = ray.direction().unit_vector();
-            let t = 0.5 * (unit_direction.y() + 1.);
-            return mult * ((1. - t) * vec3!(1.) + t * vec3!(0.5, 0.7, 1.0));
+    let (world, mut lights): (HittableList, Vec<Box<dyn Light>>) = match options.scene.as_str() {
+        "random" => (HittableList::random(&mut rng), vec![]),
+        "principled" => (scenes::principled(), vec![]),
+        "dispersion" => (scenes::dispersion(), vec![]),
+        "absorption" => (scenes::absorption(), vec![]),
+        "layered" => (scenes::layered(), vec![]),
+        "cutout" => (scenes::cutout(), vec![]),
+        "surface" => {
+            let normal_map = options.normal_map.as_ref().map(|path| {
+                Image::read_ppm(path).unwrap_or_else(|e| {
+                    eprintln!("couldn't read normal map `{}`: {}", path, e);
+                    std::process::exit(1);
+                })
+            });
+            (scenes::surface_detail(normal_map), vec![])
         }
+        "city" => scenes::city(&mut rng),
+        "interior" => scenes::interior(),
+        other => {
+            eprintln!("unknown scene `{}`", other);
+            std::process::exit(1);
+        }
+    };
 
-        depth += 1;
+    if let Some(path) = &options.environment {
+        let image = Image::read(path).unwrap_or_else(|e| {
+            eprintln!("couldn't read environment `{}`: {}", path, e);
+            std::process::exit(1);
+        });
+        let environment = EnvironmentLight::new(image)
+            .with_rotation(options.environment_rotation)
+            .with_intensity(options.environment_intensity);
+        lights.push(Box::new(environment));
     }
-}
 
-fn main() {
-    let width: i16 = 1200;
-    let height: i16 = 800;
-    let samples: i16 = 10;
+    if let Some(path) = &options.lights {
+        let parsed = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| {
+                let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+                light::parse_lights(&text, directory)
+            })
+            .unwrap_or_else(|e| {
+                eprintln!("couldn't read lights `{}`: {}", path, e);
+                std::process::exit(1);
+            });
+        lights.extend(parsed);
+    }
 
-    let mut rng = ThreadRng::default();
+    if options.sky {
+        let sun = sky::sun_direction(options.sun_elevation, options.sun_azimuth);
+        let ground = vec3!(options.ground_albedo);
 
-    let look_from = vec3!(13., 2., 3.);
-    let look_at = vec3!(0., 0., 0.);
-    let dist_to_focus = 10.;
-    let aperture = 0.1;
+        lights.push(Box::new(PreethamSky::new(sun, options.turbidity, ground)));
+        lights.push(Box::new(SunLight::new(sun, options.turbidity, options.sun_size)));
+    }
+
+    if !lights.iter().any(|light| light.is_infinite()) {
+        lights.push(Box::new(GradientSky));
+    }
+
+    let sampler: Box<dyn LightSampler> = match options.light_sampler.as_str() {
+        "uniform" => Box::new(UniformLightSampler::new(&lights)),
+        "power" => Box::new(PowerLightSampler::new(&lights)),
+        "bvh" => Box::new(BvhLightSampler::new(&lights)),
+        other => {
+            eprintln!("unknown light sampler `{}`", other);
+            std::process::exit(1);
+        }
+    };
+
+    let scene = Scene::new(Box::new(Bvh::new(world)), lights, sampler);
+
+    let spectral = options.spectral;
+    let renderer = match options.integrator.as_str() {
+        "path" => Renderer::Rays(Box::new(PathTracer::new(spectral))),
+        "bdpt" => Renderer::Rays(Box::new(Bdpt::new(&scene, spectral))),
+        "photon" => Renderer::Rays(Box::new(PhotonMapping::new(
+            &scene,
+            camera.as_ref(),
+            options.photons,
+            options.photon_radius,
+            &mut rng,
+        ))),
+        "sppm" => Renderer::Sppm(Sppm::new(options.photons, options.photon_radius)),
+        "mlt" => Renderer::Mlt(Mlt::new(Box::new(PathTracer::new(spectral)))),
+        "ao" => Renderer::Rays(Box::new(AmbientOcclusion::new(options.ao_distance))),
+        "normals" => Renderer::Rays(Box::new(DebugView::Normals)),
+        "depth" => Renderer::Rays(Box::new(DebugView::Depth(2. * (look_from - look_at).length()))),
+        "uv" => Renderer::Rays(Box::new(DebugView::Uv)),
+        "material" => Renderer::Rays(Box::new(DebugView::Material)),
+        "bounces" => Renderer::Rays(Box::new(DebugView::Bounces)),
+        "bvh" => Renderer::Rays(Box::new(DebugView::Cost)),
+        other => {
+            eprintln!("unknown integrator `{}`", other);
+            std::process::exit(1);
+        }
+    };
+
+    let aovs_supported = match &renderer {
+        Renderer::Rays(integrator) => integrator.has_aovs(),
+        _ => false,
+    };
+    let aov_film_wanted = options.aovs.is_some() || options.denoise;
+    if options.aovs.is_some() && !aovs_supported {
+        eprintln!("integrator `{}` can't write AOVs", options.integrator);
+        std::process::exit(1);
+    }
+    if options.denoise && !aovs_supported {
+        eprintln!("integrator `{}` can't be denoised, as it doesn't write AOVs", options.integrator);
+        std::process::exit(1);
+    }
+    if options.adaptive && !matches!(renderer, Renderer::Rays(_)) {
+        eprintln!("integrator `{}` can't sample adaptively", options.integrator);
+        std::process::exit(1);
+    }
+    if options.checkpoint.is_some() && !matches!(renderer, Renderer::Rays(_)) {
+        eprintln!("integrator `{}` can't be checkpointed", options.integrator);
+        std::process::exit(1);
+    }
+    let open_ended = options.time_budget.is_some() || options.target_noise.is_some();
+    if open_ended && !matches!(renderer, Renderer::Rays(_)) {
+        eprintln!("integrator `{}` can only stop at a number of samples", options.integrator);
+        std::process::exit(1);
+    }
+    if options.checkpoint.is_some() && aov_film_wanted {
+        eprintln!("AOVs aren't kept in checkpoints, so can't be written or denoised when checkpointing");
+        std::process::exit(1);
+    }
+
+    let cropping = options.crop_window.is_some() || options.pixel_bounds.is_some();
+    if cropping && !matches!(renderer, Renderer::Rays(_)) {
+        eprintln!("integrator `{}` can't render a crop", options.integrator);
+        std::process::exit(1);
+    }
+    let crop = match (options.pixel_bounds, options.crop_window) {
+        (Some(bounds), _) => Rect::from_bounds(bounds, width, height),
+        (None, Some(window)) => Rect::from_window(window, width, height),
+        (None, None) => Some(Rect::new(0, 0, width, height)),
+    }
+    .unwrap_or_else(|| {
+        eprintln!("the crop is empty");
+        std::process::exit(1);
+    });
+    // Images cover the crop, and are put back in the whole image unless
+    // only the crop is wanted.
+    let frame = |image: Image| if options.cropped { image } else { crop.uncrop(&image, width, height) };
+
+    let tile_order = match options.tile_order.as_str() {
+        "scanline" => TileOrder::Scanline,
+        "spiral" => TileOrder::Spiral,
+        "hilbert" => TileOrder::Hilbert,
+        other => {
+            eprintln!("unknown tile order `{}`", other);
+            std::process::exit(1);
+        }
+    };
+    // A render with a time budget or target noise takes as many samples as
+    // it needs, unless told otherwise.
+    let samples = options.samples.or(if open_ended { None } else { Some(10) });
+    let sampling = if options.adaptive {
+        AdaptiveSampling::new(options.min_samples, options.max_samples, options.noise_threshold)
+    } else {
+        AdaptiveSampling::fixed(samples.unwrap_or(u32::MAX))
+    };
+
+    let seed = options.seed;
+    let mut pixel_sampler: Box<dyn Sampler> = match options.sampler.as_str() {
+        "independent" => Box::new(Independent::new(seed)),
+        "stratified" => Box::new(Stratified::new(sampling.max_samples(), seed)),
+        "halton" => Box::new(Halton::new(seed)),
+        "sobol" => Box::new(Sobol::new(seed)),
+        "blue-noise" => Box::new(BlueNoise::new(seed)),
+        other => {
+            eprintln!("unknown sampler `{}`", other);
+            std::process::exit(1);
+        }
+    };
+
+    let radius = |default| options.filter_radius.unwrap_or(default);
+    let filter = match options.filter.as_str() {
+        "box" => Filter::Box(radius(0.5)),
+        "tent" => Filter::Tent(radius(1.)),
+        "gaussian" => Filter::Gaussian(radius(1.5)),
+        "mitchell" => Filter::Mitchell(radius(2.)),
+        "lanczos" => Filter::Lanczos(radius(3.)),
+        other => {
+            eprintln!("unknown filter `{}`", other);
+            std::process::exit(1);
+        }
+    };
+
+    // The denoiser is guided by the AOVs too.
+    let mut aov_film = if aov_film_wanted {
+        Some(AovFilm::new(crop.width, crop.height, scene.lights.len()))
+    } else {
+        None
+    };
+
+    let samples = samples.unwrap_or(u32::MAX) as usize;
+    let mut sample_counts = vec![samples as u32; crop.area()];
+    let pixels = match &renderer {
+        Renderer::Sppm(sppm) => sppm.render(&scene, camera.as_ref(), width, height, samples, &mut rng),
+        Renderer::Mlt(mlt) => mlt.render(&scene, camera.as_ref(), width, height, samples, &mut rng),
+        Renderer::Rays(integrator) => {
+            // Stratified samples are spread over all of a pixel's samples, so
+            // how many there are changes them too.
+            let mut fingerprint = options.fingerprint();
+            if options.sampler == "stratified" {
+                fingerprint += &format!("\nstrata={}", sampling.max_samples());
+            }
+
+            // Pixels reach the samples within the filter radius of their
+            // centers, so a margin around the crop is sampled too for its
+            // edges to come out as in the whole image.
+            let margin = (filter.radius() - 0.5).ceil().max(0.) as usize;
+            let padded = crop.grow(margin, width, height);
+
+            let mut checkpoint = match &options.checkpoint {
+                Some(path) if Path::new(path).exists() => {
+                    Checkpoint::read(path, padded.width, padded.height, &fingerprint, filter).unwrap_or_else(|e| {
+                        eprintln!("couldn't resume from checkpoint `{}`: {}", path, e);
+                        std::process::exit(1);
+                    })
+                }
+                _ => Checkpoint::new(padded.width, padded.height, filter),
+            };
+            let write_checkpoint = |checkpoint: &Checkpoint| {
+                if let Some(path) = &options.checkpoint {
+                    checkpoint.write(path, padded.width, padded.height, &fingerprint).unwrap_or_else(|e| {
+                        eprintln!("couldn't write checkpoint `{}`: {}", path, e);
+                        std::process::exit(1);
+                    });
+                }
+            };
+
+            let mut progress = Progress::new(
+                Some(sampling.max_samples()).filter(|samples| *samples != u32::MAX),
+                options.time_budget,
+                options.target_noise.map(|percent| percent / 100.),
+                options.progress_interval,
+            );
+            let mut last_checkpoint = Instant::now();
+            let mut aovs = Aovs::new(scene.lights.len());
+            let mut splats = Vec::new();
 
-    let camera = Camera::new(
-        look_from,
-        look_at,
-        vec3!(0., 1., 0.),
-        20.,
-        f32::from(width) / f32::from(height),
-        aperture,
-        dist_to_focus,
-    );
+            // Each pass takes one more sample through every pixel that wants
+            // one, so that the image is whole whenever the render stops.
+            let tiles = tiles(&padded, options.tile_size, tile_order);
+            let mut sampled = true;
+            while sampled && !progress.is_done(&checkpoint.pixels) {
+                sampled = false;
 
-    print!("P3\n{} {}\n255\n", width, height);
+                for tile in tiles.iter() {
+                    for row in tile.y..tile.y + tile.height {
+                        // Samples are numbered across the whole image, so a
+                        // crop comes out the same as that part of it.
+                        let j = height - 1 - row;
+                        for i in tile.x..tile.x + tile.width {
+                            let (film_x, film_y) = (i - padded.x, row - padded.y);
+                            let pixel = &mut checkpoint.pixels[film_y * padded.width + film_x];
+                            if sampling.is_done(pixel) {
+                                continue;
+                            }
 
-    let world: Box<dyn Hittable> = Box::new(HittableList::random(&mut rng));
+                            pixel_sampler.start_pixel_sample(i, j, pixel.count());
+                            let (dx, dy) = pixel_sampler.get_2d();
+                            let x = i as f32 + dx;
+                            let y = row as f32 + dy;
+                            let u = x / width as f32;
+                            let v = 1. - y / height as f32;
 
-    for j in (0..height).rev() {
-        for i in 0..width {
-            let mut col = vec3!(0.);
+                            // The rest of the sample's dimensions go to the
+                            // lens and the integrator.
+                            let mut rng = SamplerRng(pixel_sampler.as_mut());
 
-            for _ in 0..samples {
-                let u = (f32::from(i) + rng.gen::<f32>()) / f32::from(width);
-                let v = (f32::from(j) + rng.gen::<f32>()) / f32::from(height);
+                            // Past the image circle of a fisheye, the camera
+                            // sees black.
+                            let ray = camera.get_ray(u, v, &mut rng);
+                            let col = match &mut aov_film {
+                                Some(aov_film) => {
+                                    aovs.clear();
+                                    let col = ray.map_or(vec3!(0.), |ray| {
+                                        integrator.color_with_aovs(ray, &scene, &mut rng, &mut aovs)
+                                    });
+                                    if crop.contains(i, row) {
+                                        aov_film.add(i - crop.x, row - crop.y, &aovs);
+                                    }
+                                    col
+                                }
+                                None => ray.map_or(vec3!(0.), |ray| {
+                                    integrator.color_with_splats(ray, &scene, camera.as_ref(), &mut rng, &mut splats)
+                                }),
+                            };
+                            pixel.add(col);
+                            checkpoint.film.add_sample(x - padded.x as f32, y - padded.y as f32, col);
 
-                let ray = camera.get_ray(u, v, &mut rng);
-                col += color(ray, &world, &mut rng);
+                            // Splats past the padded crop are lost with the
+                            // rest of the image.
+                            for splat in splats.drain(..) {
+                                let x = splat.s * width as f32 - padded.x as f32;
+                                let y = (1. - splat.t) * height as f32 - padded.y as f32;
+                                if (0. ..padded.width as f32).contains(&x) && (0. ..padded.height as f32).contains(&y) {
+                                    checkpoint.film.add_splat(x, y, splat.color);
+                                }
+                            }
+                            sampled = true;
+                        }
+                    }
+
+                    if progress.out_of_time() {
+                        break;
+                    }
+                    progress.log_if_due(&checkpoint.pixels);
+                    if last_checkpoint.elapsed().as_secs_f32() >= options.checkpoint_interval {
+                        write_checkpoint(&checkpoint);
+                        last_checkpoint = Instant::now();
+                    }
+                }
             }
 
-            col /= f32::from(samples);
-            col = col.map(|x| x.sqrt());
+            write_checkpoint(&checkpoint);
+            progress.log(&checkpoint.pixels);
+            let counts: Vec<u32> = checkpoint.pixels.iter().map(Welford::count).collect();
+            sample_counts = crop.trim(&padded, &counts);
+
+            // Any sample can splat anywhere on the whole image, so each pixel
+            // gets its share of the splats of every sample taken.
+            let taken: f64 = counts.iter().map(|count| f64::from(*count)).sum();
+            let splat_scale = if taken > 0. { (width * height) as f64 / taken } else { 0. };
+            crop.trim(&padded, &checkpoint.film.pixels(splat_scale as f32))
+        }
+    };
+
+    let mut image = Image::new(crop.width, crop.height);
+    for (index, pixel) in pixels.iter().enumerate() {
+        image.set(index % crop.width, index / crop.width, *pixel);
+    }
+
+    if let Some(path) = &options.sample_heatmap {
+        let (min, max) = (sampling.min_samples() as f32, sampling.max_samples() as f32);
+        let mut counts = Image::new(crop.width, crop.height);
+        for (index, count) in sample_counts.iter().enumerate() {
+            let t = if max > min { (*count as f32 - min) / (max - min) } else { 0. };
+            counts.set(index % crop.width, index / crop.width, heatmap(t));
+        }
+
+        frame(counts).write_ppm(path).unwrap_or_else(|e| {
+            eprintln!("couldn't write sample heatmap `{}`: {}", path, e);
+            std::process::exit(1);
+        });
+    }
+
+    if let (Some(film), Some(prefix)) = (&aov_film, &options.aovs) {
+        film.write(prefix, frame).unwrap_or_else(|e| {
+            eprintln!("couldn't write AOVs `{}`: {}", prefix, e);
+            std::process::exit(1);
+        });
+    }
+
+    if let (Some(film), true) = (&aov_film, options.denoise) {
+        let layer = |name| film.layer(name).expect("AOV films have every surface layer");
+        let (albedo, normal, depth) = (layer("albedo"), layer("normal"), layer("depth"));
+        let variance = film.variance();
+        let features = Features {
+            albedo: &albedo,
+            normal: &normal,
+            depth: &depth,
+            variance: &variance,
+        };
+
+        image = Denoiser::default().denoise(&image, &features);
+    }
+
+    // Light is gamma corrected for display, but debug views are shown as
+    // they are.
+    let gamma = match &renderer {
+        Renderer::Rays(integrator) => integrator.is_radiance(),
+        _ => true,
+    };
+
+    let image = frame(image);
+    print!("P3\n{} {}\n255\n", image.width(), image.height());
+
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let col = image.get(x, y);
+            let col = if gamma { col.map(|x| x.sqrt()) } else { col };
+            let col = col.map(|x| x.min(1.));
 
             let ir = (255.99 * col.r()).floor() as i32;
             let ig = (255.99 * col.g()).floor() as i32;
@@ -1,24 +1,15 @@
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use rand::{Rng, RngCore};
+use std::f32::consts::PI;
+use std::rc::Rc;
 
-use crate::objects::HitRecord;
+use crate::objects::{random_unit_vector, HitRecord};
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::texture::{SolidColor, Texture};
 use crate::vec3::Vec3;
 
-fn random_in_unit_sphere(rng: &mut ThreadRng) -> Vec3 {
-    let mut p;
-    loop {
-        p = 2. * vec3!(rng.gen(), rng.gen(), rng.gen()) - vec3!(1.);
-        if p.squared_length() < 1. {
-            break;
-        }
-    }
-
-    p
-}
-
 fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
-    return v - 2. * v.dot(n) * n;
+    v - 2. * v.dot(n) * n
 }
 
 fn refract(v: &Vec3, n: &Vec3, ni_over_nt: f32) -> Option<Vec3> {
@@ -27,7 +18,7 @@ fn refract(v: &Vec3, n: &Vec3, ni_over_nt: f32) -> Option<Vec3> {
     let discriminant = 1. - ni_over_nt * ni_over_nt * (1. - dt * dt);
 
     if discriminant > 0. {
-        Some(ni_over_nt * (&uv - n * dt) - n * discriminant.sqrt())
+        Some(ni_over_nt * (uv - n * dt) - n * discriminant.sqrt())
     } else {
         None
     }
@@ -36,20 +27,83 @@ fn refract(v: &Vec3, n: &Vec3, ni_over_nt: f32) -> Option<Vec3> {
 fn schlick(cosine: f32, ref_idx: f32) -> f32 {
     let mut r0 = (1. - ref_idx) / (1. + ref_idx);
     r0 = r0 * r0;
-    return r0 + (1. - r0) * (1. - cosine).powf(5.);
+    r0 + (1. - r0) * (1. - cosine).powf(5.)
+}
+
+/// A direction sampled by `Material::scatter`, along with the BSDF times
+/// cosine divided by the probability of picking it.
+pub struct Scatter {
+    pub attenuation: Vec3,
+    pub ray: Ray,
+    /// Whether the ray came from a perfectly specular lobe, which `eval` and
+    /// `pdf` leave out since lights can never be sampled along it.
+    pub specular: bool,
 }
 
 pub trait Material {
-    fn scatter(&self, ray_in: &Ray, record: &HitRecord, rng: &mut ThreadRng)
-        -> Option<(Vec3, Ray)>;
+    fn scatter(&self, ray_in: &Ray, record: &HitRecord, rng: &mut dyn RngCore) -> Option<Scatter>;
+
+    /// The BSDF times the cosine to the normal, for light arriving from the
+    /// unit direction `wi` and leaving back along `ray_in`. Specular lobes
+    /// are left out.
+    fn eval(&self, _ray_in: &Ray, _record: &HitRecord, _wi: &Vec3) -> Vec3 {
+        vec3!(0.)
+    }
+
+    /// The solid angle density with which `scatter` picks `wi`, leaving out
+    /// specular lobes.
+    fn pdf(&self, _ray_in: &Ray, _record: &HitRecord, _wi: &Vec3) -> f32 {
+        0.
+    }
+
+    /// The radiance the surface emits back along `ray_in`.
+    fn emitted(&self, _ray_in: &Ray, _record: &HitRecord) -> Vec3 {
+        vec3!(0.)
+    }
+
+    /// Whether the direction chosen by `scatter` depends on the wavelength of
+    /// the incoming ray, in which case a spectral path can only carry on
+    /// with its hero wavelength.
+    fn dispersive(&self) -> bool {
+        false
+    }
+
+    /// Whether every lobe is specular, so that `eval` and `pdf` are always
+    /// zero.
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    /// How much of the surface is present at the hit, in `[0, 1]`. Shapes
+    /// skip hits with no opacity at all, and the integrator passes through
+    /// partially opaque hits stochastically.
+    fn opacity(&self, _record: &HitRecord) -> f32 {
+        1.
+    }
+
+    /// The color of the surface under white light, for denoisers and
+    /// compositing rather than for shading.
+    fn albedo(&self, _record: &HitRecord) -> Vec3 {
+        vec3!(1.)
+    }
+
+    /// The normal shading uses at the hit, which may be perturbed from the
+    /// geometric one.
+    fn shading_normal(&self, record: &HitRecord) -> Vec3 {
+        record.normal
+    }
 }
 
 pub struct Lambertian {
-    albedo: Vec3,
+    albedo: Rc<dyn Texture>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Vec3) -> Lambertian {
+        Lambertian::textured(Rc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn textured(albedo: Rc<dyn Texture>) -> Lambertian {
         Lambertian { albedo }
     }
 }
@@ -57,15 +111,58 @@ impl Lambertian {
 impl Material for Lambertian {
     fn scatter(
         &self,
-        _ray_in: &Ray,
+        ray_in: &Ray,
         record: &HitRecord,
-        rng: &mut ThreadRng,
-    ) -> Option<(Vec3, Ray)> {
-        let target = record.p + record.normal + random_in_unit_sphere(rng);
-        let scattered = Ray::new(record.p, target - record.p);
-        let attenuation = self.albedo;
+        rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        let frame = Onb::from_w(&facing_normal(ray_in, record));
+        let direction = frame.to_world(&random_cosine_direction(rng));
+
+        Some(Scatter {
+            attenuation: self.albedo.value(record.u, record.v, &record.p),
+            ray: Ray::new(record.p, direction),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> Vec3 {
+        let cosine = wi.dot(&facing_normal(ray_in, record)).max(0.);
+        self.albedo.value(record.u, record.v, &record.p) * cosine / PI
+    }
+
+    fn pdf(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> f32 {
+        wi.dot(&facing_normal(ray_in, record)).max(0.) / PI
+    }
 
-        Some((attenuation, scattered))
+    fn albedo(&self, record: &HitRecord) -> Vec3 {
+        self.albedo.value(record.u, record.v, &record.p)
+    }
+}
+
+/// Emits light uniformly from the front of a surface, and absorbs
+/// everything that arrives. Add it to a scene with
+/// `scene::add_area_light` so it can be sampled directly.
+pub struct DiffuseLight {
+    emission: Vec3,
+}
+
+impl DiffuseLight {
+    pub fn new(emission: Vec3) -> DiffuseLight {
+        DiffuseLight { emission }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _ray_in: &Ray, _record: &HitRecord, _rng: &mut dyn RngCore) -> Option<Scatter> {
+        None
+    }
+
+    fn emitted(&self, ray_in: &Ray, record: &HitRecord) -> Vec3 {
+        if ray_in.direction().dot(&record.normal) < 0. {
+            self.emission
+        } else {
+            vec3!(0.)
+        }
     }
 }
 
@@ -78,7 +175,7 @@ impl Metal {
     pub fn new(albedo: Vec3, f: f32) -> Metal {
         Metal {
             albedo,
-            fuzz: if f < 1. && f >= 0. { f } else { 1. },
+            fuzz: if (0. ..1.).contains(&f) { f } else { 1. },
         }
     }
 }
@@ -88,27 +185,101 @@ impl Material for Metal {
         &self,
         ray_in: &Ray,
         record: &HitRecord,
-        rng: &mut ThreadRng,
-    ) -> Option<(Vec3, Ray)> {
+        rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
         let reflected = reflect(&ray_in.direction().unit_vector(), &record.normal);
-        let scattered = Ray::new(record.p, reflected + self.fuzz * random_in_unit_sphere(rng));
+        // Fuzz moves the reflection to a random point on a sphere around it,
+        // which always takes two numbers.
+        let scattered = Ray::new(record.p, reflected + self.fuzz * random_unit_vector(rng));
         let attenuation = self.albedo;
 
         if scattered.direction().dot(&record.normal) > 0. {
-            Some((attenuation, scattered))
+            Some(Scatter {
+                attenuation,
+                ray: scattered,
+                specular: true,
+            })
         } else {
             None
         }
     }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self, _record: &HitRecord) -> Vec3 {
+        self.albedo
+    }
+}
+
+/// The wavelength used for the index of refraction when rendering in RGB.
+const RGB_WAVELENGTH: f32 = 550.;
+
+/// How a dielectric's index of refraction varies with wavelength.
+pub enum Ior {
+    Constant(f32),
+    /// `n = a + b / λ²`, with `λ` in micrometres.
+    Cauchy { a: f32, b: f32 },
+    /// `n² = 1 + Σ bᵢλ² / (λ² - cᵢ)`, with `λ` in micrometres.
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Ior {
+    /// The index of refraction at `lambda` nanometres.
+    pub fn at(&self, lambda: f32) -> f32 {
+        let l2 = (lambda / 1000.) * (lambda / 1000.);
+
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                let sum: f32 = b.iter().zip(c.iter()).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1. + sum).sqrt()
+            }
+        }
+    }
 }
 
 pub struct Dielectric {
-    ref_idx: f32,
+    ior: Ior,
+    absorption: Vec3,
 }
 
 impl Dielectric {
     pub fn new(ref_idx: f32) -> Dielectric {
-        Dielectric { ref_idx }
+        Dielectric {
+            ior: Ior::Constant(ref_idx),
+            absorption: vec3!(0.),
+        }
+    }
+
+    pub fn cauchy(a: f32, b: f32) -> Dielectric {
+        Dielectric {
+            ior: Ior::Cauchy { a, b },
+            absorption: vec3!(0.),
+        }
+    }
+
+    pub fn sellmeier(b: [f32; 3], c: [f32; 3]) -> Dielectric {
+        Dielectric {
+            ior: Ior::Sellmeier { b, c },
+            absorption: vec3!(0.),
+        }
+    }
+
+    /// Sets the absorption coefficient of the medium, per unit of distance
+    /// travelled inside it (Beer-Lambert law).
+    pub fn with_absorption(self, absorption: Vec3) -> Dielectric {
+        Dielectric { absorption, ..self }
+    }
+
+    /// Sets the absorption so that light travelling `distance` through the
+    /// medium is tinted to `color`. `distance` must be positive.
+    pub fn with_transmission_color(self, color: Vec3, distance: f32) -> Dielectric {
+        assert!(distance > 0., "transmission distance must be positive, not {}", distance);
+        let absorption = color.map(|c| -c.max(1e-6).ln() / distance);
+        self.with_absorption(absorption)
     }
 }
 
@@ -117,30 +288,35 @@ impl Material for Dielectric {
         &self,
         ray_in: &Ray,
         record: &HitRecord,
-        rng: &mut ThreadRng,
-    ) -> Option<(Vec3, Ray)> {
+        rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        let ref_idx = self.ior.at(ray_in.wavelength().unwrap_or(RGB_WAVELENGTH));
         let outward_normal;
         let reflected = reflect(ray_in.direction(), &record.normal);
         let ni_over_nt;
 
-        let attenuation = vec3!(1.);
+        let mut attenuation = vec3!(1.);
 
         let scattered;
         let cosine;
 
         if ray_in.direction().dot(&record.normal) > 0. {
+            // The ray has travelled through the medium to get here.
+            let distance = record.t * ray_in.direction().length();
+            attenuation = self.absorption.map(|a| (-a * distance).exp());
+
             outward_normal = -&record.normal;
-            ni_over_nt = self.ref_idx;
-            cosine = self.ref_idx * ray_in.direction().dot(&record.normal) / ray_in.direction().length();
+            ni_over_nt = ref_idx;
+            cosine = ref_idx * ray_in.direction().dot(&record.normal) / ray_in.direction().length();
         } else {
             outward_normal = record.normal;
-            ni_over_nt = 1. / self.ref_idx;
+            ni_over_nt = 1. / ref_idx;
             cosine = -(ray_in.direction().dot(&record.normal)) / ray_in.direction().length();
         }
 
         if let Some(refracted) = refract(ray_in.direction(), &outward_normal, ni_over_nt) {
             let reflect_prob: f32 = rng.gen();
-            if reflect_prob < schlick(cosine, self.ref_idx) {
+            if reflect_prob < schlick(cosine, ref_idx) {
                 scattered = Ray::new(record.p, reflected);
             } else {
                 scattered = Ray::new(record.p, refracted);
@@ -149,6 +325,1170 @@ impl Material for Dielectric {
             scattered = Ray::new(record.p, reflected);
         }
 
-        Some((attenuation, scattered))
+        Some(Scatter {
+            attenuation,
+            ray: scattered,
+            specular: true,
+        })
+    }
+
+    fn dispersive(&self) -> bool {
+        !matches!(self.ior, Ior::Constant(_))
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+/// The normal at the hit flipped to face against the incoming ray, for
+/// materials that look the same from both sides.
+fn facing_normal(ray_in: &Ray, record: &HitRecord) -> Vec3 {
+    let normal = record.normal.unit_vector();
+    if ray_in.direction().dot(&normal) > 0. {
+        -normal
+    } else {
+        normal
+    }
+}
+
+pub fn random_cosine_direction(rng: &mut dyn RngCore) -> Vec3 {
+    let r1: f32 = rng.gen();
+    let r2: f32 = rng.gen();
+    let phi = 2. * PI * r1;
+    let r = r2.sqrt();
+
+    vec3!(r * phi.cos(), r * phi.sin(), (1. - r2).max(0.).sqrt())
+}
+
+pub fn luminance(c: &Vec3) -> f32 {
+    0.3 * c.r() + 0.6 * c.g() + 0.1 * c.b()
+}
+
+fn lerp(t: f32, a: Vec3, b: Vec3) -> Vec3 {
+    (1. - t) * a + t * b
+}
+
+fn schlick_weight(cosine: f32) -> f32 {
+    (1. - cosine).clamp(0., 1.).powf(5.)
+}
+
+/// Unpolarised Fresnel reflectance of a dielectric interface, where `eta` is
+/// the ratio of the inside over the outside index and `cos_i` is measured
+/// against the outward normal.
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let (cos_i, eta) = if cos_i < 0. { (-cos_i, 1. / eta) } else { (cos_i, eta) };
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+
+    if sin2_t >= 1. {
+        return 1.;
+    }
+
+    let cos_t = (1. - sin2_t).sqrt();
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+
+    (r_parl * r_parl + r_perp * r_perp) / 2.
+}
+
+/// Refracts `wi` through a surface with normal `n` (on the same side as
+/// `wi`), where `eta` is the incident over the transmitted index.
+fn refract_about(wi: &Vec3, n: &Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = n.dot(wi);
+    let sin2_t = eta * eta * (1. - cos_i * cos_i).max(0.);
+
+    if sin2_t >= 1. {
+        return None;
+    }
+
+    let cos_t = (1. - sin2_t).sqrt();
+    Some(eta * -wi + (eta * cos_i - cos_t) * n)
+}
+
+/// Anisotropic GGX (Trowbridge-Reitz) microfacet distribution.
+struct Ggx {
+    ax: f32,
+    ay: f32,
+}
+
+impl Ggx {
+    fn d(&self, h: &Vec3) -> f32 {
+        let e = (h.x / self.ax).powi(2) + (h.y / self.ay).powi(2) + h.z * h.z;
+        1. / (PI * self.ax * self.ay * e * e)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f32 {
+        if w.z == 0. {
+            return 0.;
+        }
+
+        let tan2 = ((self.ax * w.x).powi(2) + (self.ay * w.y).powi(2)) / (w.z * w.z);
+        ((1. + tan2).sqrt() - 1.) / 2.
+    }
+
+    fn g(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+
+    fn g1(&self, w: &Vec3) -> f32 {
+        1. / (1. + self.lambda(w))
+    }
+
+    /// Samples a microfacet normal visible from `wo` (Heitz 2018), returned
+    /// in the upper hemisphere.
+    fn sample_h(&self, wo: &Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        let u1: f32 = rng.gen();
+        let u2: f32 = rng.gen();
+        let wo = if wo.z < 0. { -wo } else { *wo };
+
+        let vh = vec3!(self.ax * wo.x, self.ay * wo.y, wo.z).unit_vector();
+        let len_sq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len_sq > 0. {
+            vec3!(-vh.y, vh.x, 0.) / len_sq.sqrt()
+        } else {
+            vec3!(1., 0., 0.)
+        };
+        let t2 = vh.cross(&t1);
+
+        let r = u1.sqrt();
+        let phi = 2. * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1. + vh.z);
+        let p2 = (1. - s) * (1. - p1 * p1).max(0.).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1. - p1 * p1 - p2 * p2).max(0.).sqrt() * vh;
+
+        vec3!(self.ax * nh.x, self.ay * nh.y, nh.z.max(1e-6)).unit_vector()
+    }
+
+    fn pdf_h(&self, wo: &Vec3, h: &Vec3) -> f32 {
+        let cos = wo.dot(h) * wo.z.signum();
+        if cos <= 0. {
+            return 0.;
+        }
+
+        self.g1(wo) * cos * self.d(h) / wo.z.abs()
+    }
+}
+
+/// The GTR1 distribution used by the Disney clearcoat lobe.
+struct Gtr1 {
+    alpha: f32,
+}
+
+impl Gtr1 {
+    fn d(&self, h: &Vec3) -> f32 {
+        let a2 = self.alpha * self.alpha;
+        (a2 - 1.) / (PI * a2.ln() * (1. + (a2 - 1.) * h.z * h.z))
+    }
+
+    fn sample_h(&self, rng: &mut dyn RngCore) -> Vec3 {
+        let u1: f32 = rng.gen();
+        let u2: f32 = rng.gen();
+        let a2 = self.alpha * self.alpha;
+        let cos_theta = ((1. - a2.powf(1. - u1)) / (1. - a2)).max(0.).sqrt();
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * u2;
+
+        vec3!(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+    }
+
+    fn pdf_h(&self, h: &Vec3) -> f32 {
+        self.d(h) * h.z.abs()
+    }
+}
+
+fn smith_g1(cosine: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let c2 = cosine * cosine;
+    2. * cosine.abs() / (cosine.abs() + (a2 + c2 - a2 * c2).sqrt())
+}
+
+/// The Disney "principled" BSDF (Burley 2012, 2015).
+///
+/// All parameters are in `[0, 1]` and map directly onto the inputs exposed by
+/// glTF and most DCC tools. The index of refraction used by the transmission
+/// lobe is derived from `specular`, so `0.5` corresponds to an IOR of 1.5.
+pub struct Principled {
+    pub base_color: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub specular: f32,
+    pub specular_tint: f32,
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    pub clearcoat: f32,
+    pub clearcoat_gloss: f32,
+    pub transmission: f32,
+    pub anisotropic: f32,
+    pub subsurface: f32,
+}
+
+impl Default for Principled {
+    fn default() -> Principled {
+        Principled {
+            base_color: vec3!(0.8),
+            metallic: 0.,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.,
+            sheen: 0.,
+            sheen_tint: 0.5,
+            clearcoat: 0.,
+            clearcoat_gloss: 1.,
+            transmission: 0.,
+            anisotropic: 0.,
+            subsurface: 0.,
+        }
+    }
+}
+
+impl Principled {
+    pub fn new(base_color: Vec3) -> Principled {
+        Principled {
+            base_color,
+            ..Principled::default()
+        }
+    }
+
+    fn ior(&self) -> f32 {
+        let r0 = (0.08 * self.specular).clamp(0., 0.99).sqrt();
+        (1. + r0) / (1. - r0)
+    }
+
+    fn tint(&self) -> Vec3 {
+        let lum = luminance(&self.base_color);
+        if lum > 0. {
+            self.base_color / lum
+        } else {
+            vec3!(1.)
+        }
+    }
+
+    fn distribution(&self) -> Ggx {
+        let aspect = (1. - 0.9 * self.anisotropic).sqrt();
+        let a = self.roughness * self.roughness;
+
+        Ggx {
+            ax: (a / aspect).max(0.001),
+            ay: (a * aspect).max(0.001),
+        }
+    }
+
+    fn clearcoat_distribution(&self) -> Gtr1 {
+        Gtr1 {
+            alpha: 0.1 + (0.001 - 0.1) * self.clearcoat_gloss,
+        }
+    }
+
+    fn diffuse_weight(&self) -> f32 {
+        (1. - self.metallic) * (1. - self.transmission)
+    }
+
+    fn transmission_weight(&self) -> f32 {
+        (1. - self.metallic) * self.transmission
+    }
+
+    /// The Disney Fresnel term: a dielectric interface (which handles total
+    /// internal reflection) blended towards tinted Schlick for metals.
+    fn fresnel(&self, cos_i: f32) -> Vec3 {
+        let dielectric = fresnel_dielectric(cos_i, self.ior())
+            * lerp(self.specular_tint, vec3!(1.), self.tint());
+        let r0 = lerp(
+            self.metallic,
+            0.08 * self.specular * lerp(self.specular_tint, vec3!(1.), self.tint()),
+            self.base_color,
+        );
+        let metal = r0 + (vec3!(1.) - r0) * schlick_weight(cos_i.abs());
+
+        lerp(self.metallic, dielectric, metal)
+    }
+
+    /// The lobes that can be sampled for an outgoing direction on the given
+    /// side of the surface.
+    fn lobes(&self, outside: bool) -> ([bool; 4], f32) {
+        let lobes = [
+            outside && self.diffuse_weight() > 0.,
+            true,
+            outside && self.clearcoat > 0.,
+            self.transmission_weight() > 0.,
+        ];
+        let count = lobes.iter().filter(|x| **x).count() as f32;
+
+        (lobes, count)
+    }
+
+    /// Evaluates `f(wo, wi) * |cos(wi)|` in the local shading frame.
+    fn eval_local(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if wo.z == 0. || wi.z == 0. {
+            return vec3!(0.);
+        }
+
+        let ggx = self.distribution();
+        let reflect = wo.z * wi.z > 0.;
+        let mut f = vec3!(0.);
+
+        if reflect {
+            let mut h = *wo + *wi;
+            if h.squared_length() == 0. {
+                return vec3!(0.);
+            }
+            h = h.unit_vector();
+            if h.z < 0. {
+                h = -h;
+            }
+
+            let cos_o = wo.z.abs();
+            let cos_i = wi.z.abs();
+            let l_dot_h = wi.dot(&h);
+
+            if wo.z > 0. {
+                let fl = schlick_weight(cos_i);
+                let fv = schlick_weight(cos_o);
+                let fh = schlick_weight(l_dot_h);
+                let rough = self.roughness;
+
+                let fd90 = 0.5 + 2. * l_dot_h * l_dot_h * rough;
+                let fd = (1. + (fd90 - 1.) * fl) * (1. + (fd90 - 1.) * fv);
+
+                let fss90 = l_dot_h * l_dot_h * rough;
+                let fss = (1. + (fss90 - 1.) * fl) * (1. + (fss90 - 1.) * fv);
+                let ss = 1.25 * (fss * (1. / (cos_i + cos_o) - 0.5) + 0.5);
+
+                let sheen = fh * self.sheen * lerp(self.sheen_tint, vec3!(1.), self.tint());
+                let diffuse = (fd + (ss - fd) * self.subsurface) / PI * self.base_color;
+
+                f += self.diffuse_weight() * (diffuse + sheen);
+
+                if self.clearcoat > 0. {
+                    let gtr1 = self.clearcoat_distribution();
+                    let fr = 0.04 + 0.96 * fh;
+                    let gr = smith_g1(cos_i, 0.25) * smith_g1(cos_o, 0.25);
+                    f += vec3!(0.25 * self.clearcoat * gtr1.d(&h) * fr * gr / (4. * cos_i * cos_o));
+                }
+            }
+
+            let specular = ggx.d(&h) * ggx.g(wo, wi) / (4. * cos_i * cos_o);
+            f += specular * self.fresnel(l_dot_h);
+        } else if self.transmission_weight() > 0. {
+            let eta = if wo.z > 0. { self.ior() } else { 1. / self.ior() };
+            let mut h = (*wo + eta * *wi).unit_vector();
+            if h.z < 0. {
+                h = -h;
+            }
+
+            let o_dot_h = wo.dot(&h);
+            let i_dot_h = wi.dot(&h);
+            if o_dot_h * i_dot_h > 0. {
+                return vec3!(0.);
+            }
+
+            let denom = o_dot_h + eta * i_dot_h;
+            let fresnel = fresnel_dielectric(o_dot_h, self.ior());
+            let t = (1. - fresnel) * ggx.d(&h) * ggx.g(wo, wi) * (i_dot_h * o_dot_h).abs()
+                / (wi.z * wo.z * denom * denom).abs();
+
+            f += self.transmission_weight() * t * self.base_color.map(|x| x.sqrt());
+        }
+
+        f * wi.z.abs()
+    }
+
+    /// The density with which `sample_local` generates `wi`.
+    fn pdf_local(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        if wo.z == 0. || wi.z == 0. {
+            return 0.;
+        }
+
+        let (lobes, count) = self.lobes(wo.z > 0.);
+        let ggx = self.distribution();
+        let mut pdf = 0.;
+
+        if wo.z * wi.z > 0. {
+            let mut h = (*wo + *wi).unit_vector();
+            if h.z < 0. {
+                h = -h;
+            }
+            let o_dot_h = wo.dot(&h).abs();
+            let reflect_pdf = ggx.pdf_h(wo, &h) / (4. * o_dot_h);
+
+            if lobes[0] {
+                pdf += wi.z.abs() / PI;
+            }
+            if lobes[1] {
+                pdf += reflect_pdf;
+            }
+            if lobes[2] {
+                pdf += self.clearcoat_distribution().pdf_h(&h) / (4. * o_dot_h);
+            }
+            if lobes[3] {
+                pdf += reflect_pdf * fresnel_dielectric(wo.dot(&h), self.ior());
+            }
+        } else if lobes[3] {
+            let eta = if wo.z > 0. { self.ior() } else { 1. / self.ior() };
+            let mut h = (*wo + eta * *wi).unit_vector();
+            if h.z < 0. {
+                h = -h;
+            }
+
+            let o_dot_h = wo.dot(&h);
+            let i_dot_h = wi.dot(&h);
+            if o_dot_h * i_dot_h < 0. {
+                let denom = o_dot_h + eta * i_dot_h;
+                let dwh_dwi = (eta * eta * i_dot_h).abs() / (denom * denom);
+                pdf += ggx.pdf_h(wo, &h) * dwh_dwi * (1. - fresnel_dielectric(o_dot_h, self.ior()));
+            }
+        }
+
+        pdf / count
+    }
+
+    fn sample_local(&self, wo: &Vec3, rng: &mut dyn RngCore) -> Option<Vec3> {
+        let (lobes, count) = self.lobes(wo.z > 0.);
+        let mut choice = (rng.gen::<f32>() * count) as usize;
+        let lobe = lobes
+            .iter()
+            .position(|active| {
+                if *active {
+                    if choice == 0 {
+                        return true;
+                    }
+                    choice -= 1;
+                }
+                false
+            })
+            .unwrap_or(1);
+
+        // Directions that end up on the wrong side of the surface for the
+        // lobe that generated them are not accounted for by `pdf_local`.
+        let reflect_about = |h: Vec3| {
+            let wi = -*wo + 2. * wo.dot(&h) * h;
+            if wi.z * wo.z > 0. {
+                Some(wi)
+            } else {
+                None
+            }
+        };
+
+        match lobe {
+            0 => Some(random_cosine_direction(rng)),
+            1 => reflect_about(self.distribution().sample_h(wo, rng)),
+            2 => reflect_about(self.clearcoat_distribution().sample_h(rng)),
+            _ => {
+                let h = self.distribution().sample_h(wo, rng);
+                if rng.gen::<f32>() < fresnel_dielectric(wo.dot(&h), self.ior()) {
+                    reflect_about(h)
+                } else {
+                    let n = if wo.dot(&h) > 0. { h } else { -h };
+                    let eta = if wo.z > 0. { 1. / self.ior() } else { self.ior() };
+                    refract_about(wo, &n, eta).filter(|wi| wi.z * wo.z < 0.)
+                }
+            }
+        }
+    }
+}
+
+impl Material for Principled {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        let frame = record.frame();
+        let wo = frame.to_local(&-ray_in.direction().unit_vector());
+        let wi = self.sample_local(&wo, rng)?;
+
+        let pdf = self.pdf_local(&wo, &wi);
+        if pdf <= 0. {
+            return None;
+        }
+
+        Some(Scatter {
+            attenuation: self.eval_local(&wo, &wi) / pdf,
+            ray: Ray::new(record.p, frame.to_world(&wi)),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> Vec3 {
+        let frame = record.frame();
+        let wo = frame.to_local(&-ray_in.direction().unit_vector());
+        self.eval_local(&wo, &frame.to_local(wi))
+    }
+
+    fn pdf(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> f32 {
+        let frame = record.frame();
+        let wo = frame.to_local(&-ray_in.direction().unit_vector());
+        self.pdf_local(&wo, &frame.to_local(wi))
+    }
+
+    fn albedo(&self, _record: &HitRecord) -> Vec3 {
+        self.base_color
+    }
+}
+
+/// Stochastically picks between two materials, choosing `b` with the
+/// probability given by a grayscale weight texture.
+pub struct Mix {
+    a: Rc<dyn Material>,
+    b: Rc<dyn Material>,
+    weight: Rc<dyn Texture>,
+}
+
+impl Mix {
+    pub fn new(a: Rc<dyn Material>, b: Rc<dyn Material>, weight: f32) -> Mix {
+        Mix::textured(a, b, Rc::new(SolidColor::new(vec3!(weight))))
+    }
+
+    pub fn textured(a: Rc<dyn Material>, b: Rc<dyn Material>, weight: Rc<dyn Texture>) -> Mix {
+        Mix { a, b, weight }
+    }
+}
+
+impl Material for Mix {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        let weight = self.weight.scalar(record.u, record.v, &record.p);
+
+        if rng.gen::<f32>() < weight {
+            self.b.scatter(ray_in, record, rng)
+        } else {
+            self.a.scatter(ray_in, record, rng)
+        }
+    }
+
+    fn eval(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> Vec3 {
+        let weight = self.weight.scalar(record.u, record.v, &record.p);
+        lerp(weight, self.a.eval(ray_in, record, wi), self.b.eval(ray_in, record, wi))
+    }
+
+    fn pdf(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> f32 {
+        let weight = self.weight.scalar(record.u, record.v, &record.p);
+        let (a, b) = (self.a.pdf(ray_in, record, wi), self.b.pdf(ray_in, record, wi));
+        a + (b - a) * weight
+    }
+
+    fn emitted(&self, ray_in: &Ray, record: &HitRecord) -> Vec3 {
+        let weight = self.weight.scalar(record.u, record.v, &record.p);
+        lerp(weight, self.a.emitted(ray_in, record), self.b.emitted(ray_in, record))
+    }
+
+    fn dispersive(&self) -> bool {
+        self.a.dispersive() || self.b.dispersive()
+    }
+
+    fn is_specular(&self) -> bool {
+        self.a.is_specular() && self.b.is_specular()
+    }
+
+    fn opacity(&self, record: &HitRecord) -> f32 {
+        let weight = self.weight.scalar(record.u, record.v, &record.p);
+        let (a, b) = (self.a.opacity(record), self.b.opacity(record));
+        a + (b - a) * weight
+    }
+
+    fn albedo(&self, record: &HitRecord) -> Vec3 {
+        let weight = self.weight.scalar(record.u, record.v, &record.p);
+        lerp(weight, self.a.albedo(record), self.b.albedo(record))
+    }
+
+    fn shading_normal(&self, record: &HitRecord) -> Vec3 {
+        let weight = self.weight.scalar(record.u, record.v, &record.p);
+        lerp(weight, self.a.shading_normal(record), self.b.shading_normal(record)).unit_vector()
+    }
+}
+
+/// A thin, smooth dielectric coating over another material, such as the
+/// clearcoat on car paint or the varnish on wood.
+///
+/// Light either reflects off the coating according to its Fresnel term, or
+/// passes through it, scatters off the base and is attenuated by the
+/// coating's Fresnel transmittance on the way out. Light reflected back down
+/// inside the coating is discarded, and the base sees the unrefracted ray.
+pub struct Coated {
+    base: Rc<dyn Material>,
+    ior: f32,
+    tint: Vec3,
+}
+
+impl Coated {
+    pub fn new(base: Rc<dyn Material>, ior: f32) -> Coated {
+        Coated {
+            base,
+            ior,
+            tint: vec3!(1.),
+        }
+    }
+
+    /// Sets the color of light that has passed straight down through the
+    /// coating and back up again. Light at grazing angles travels further
+    /// through the coating and so is tinted more strongly.
+    pub fn with_tint(self, tint: Vec3) -> Coated {
+        Coated { tint, ..self }
+    }
+
+    /// The cosine of the angle inside the coating for light arriving at
+    /// `cosine` from outside.
+    fn refracted_cosine(&self, cosine: f32) -> f32 {
+        let sin2 = (1. - cosine * cosine) / (self.ior * self.ior);
+        (1. - sin2).max(0.).sqrt()
+    }
+
+    /// The fraction of light scattered by the base from `cos_in` to
+    /// `cos_out` that makes it back out through the coating.
+    fn transmittance(&self, cos_in: f32, cos_out: f32) -> Vec3 {
+        if cos_out <= 0. {
+            return vec3!(1.);
+        }
+
+        let path = 0.5 / self.refracted_cosine(cos_in) + 0.5 / self.refracted_cosine(cos_out);
+        (1. - fresnel_dielectric(cos_out, self.ior)) * self.tint.map(|t| t.powf(path))
+    }
+}
+
+impl Material for Coated {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        let direction = ray_in.direction().unit_vector();
+        let cos_in = -direction.dot(&record.normal);
+
+        if cos_in <= 0. {
+            return self.base.scatter(ray_in, record, rng);
+        }
+
+        if rng.gen::<f32>() < fresnel_dielectric(cos_in, self.ior) {
+            let reflected = reflect(&direction, &record.normal);
+            return Some(Scatter {
+                attenuation: vec3!(1.),
+                ray: Ray::new(record.p, reflected),
+                specular: true,
+            });
+        }
+
+        let scatter = self.base.scatter(ray_in, record, rng)?;
+        let cos_out = scatter.ray.direction().unit_vector().dot(&record.normal);
+
+        Some(Scatter {
+            attenuation: scatter.attenuation * self.transmittance(cos_in, cos_out),
+            ..scatter
+        })
+    }
+
+    fn eval(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> Vec3 {
+        let cos_in = -ray_in.direction().unit_vector().dot(&record.normal);
+        let base = self.base.eval(ray_in, record, wi);
+
+        if cos_in <= 0. {
+            return base;
+        }
+
+        let cos_out = wi.dot(&record.normal);
+        (1. - fresnel_dielectric(cos_in, self.ior)) * base * self.transmittance(cos_in, cos_out)
+    }
+
+    fn pdf(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> f32 {
+        let cos_in = -ray_in.direction().unit_vector().dot(&record.normal);
+        let base = self.base.pdf(ray_in, record, wi);
+
+        if cos_in <= 0. {
+            base
+        } else {
+            (1. - fresnel_dielectric(cos_in, self.ior)) * base
+        }
+    }
+
+    /// Light the base emits, less what the coating reflects back down.
+    fn emitted(&self, ray_in: &Ray, record: &HitRecord) -> Vec3 {
+        let cos_out = -ray_in.direction().unit_vector().dot(&record.normal);
+        let base = self.base.emitted(ray_in, record);
+
+        if cos_out <= 0. {
+            base
+        } else {
+            (1. - fresnel_dielectric(cos_out, self.ior)) * base
+        }
+    }
+
+    fn dispersive(&self) -> bool {
+        self.base.dispersive()
+    }
+
+    fn is_specular(&self) -> bool {
+        self.base.is_specular()
+    }
+
+    fn opacity(&self, record: &HitRecord) -> f32 {
+        self.base.opacity(record)
+    }
+
+    fn albedo(&self, record: &HitRecord) -> Vec3 {
+        self.base.albedo(record)
+    }
+
+    fn shading_normal(&self, record: &HitRecord) -> Vec3 {
+        self.base.shading_normal(record)
+    }
+}
+
+/// Perturbs the shading normal of another material with a tangent space
+/// normal map, where red, green and blue encode the `u`, `v` and normal axes
+/// of the hit's tangent frame.
+pub struct NormalMapped {
+    base: Rc<dyn Material>,
+    map: Rc<dyn Texture>,
+    strength: f32,
+}
+
+impl NormalMapped {
+    pub fn new(base: Rc<dyn Material>, map: Rc<dyn Texture>) -> NormalMapped {
+        NormalMapped {
+            base,
+            map,
+            strength: 1.,
+        }
+    }
+
+    /// Scales the tangential part of the mapped normal.
+    pub fn with_strength(self, strength: f32) -> NormalMapped {
+        NormalMapped { strength, ..self }
+    }
+
+    fn perturb(&self, record: &HitRecord) -> HitRecord {
+        let c = 2. * self.map.value(record.u, record.v, &record.p) - vec3!(1.);
+        let local = vec3!(self.strength * c.x, self.strength * c.y, c.z.max(1e-3));
+
+        HitRecord {
+            normal: record.frame().to_world(&local).unit_vector(),
+            ..record.clone()
+        }
+    }
+}
+
+impl Material for NormalMapped {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        self.base.scatter(ray_in, &self.perturb(record), rng)
+    }
+
+    fn eval(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> Vec3 {
+        self.base.eval(ray_in, &self.perturb(record), wi)
+    }
+
+    fn pdf(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> f32 {
+        self.base.pdf(ray_in, &self.perturb(record), wi)
+    }
+
+    /// Emission follows the geometric normal, as lights sample the shape.
+    fn emitted(&self, ray_in: &Ray, record: &HitRecord) -> Vec3 {
+        self.base.emitted(ray_in, record)
+    }
+
+    fn dispersive(&self) -> bool {
+        self.base.dispersive()
+    }
+
+    fn is_specular(&self) -> bool {
+        self.base.is_specular()
+    }
+
+    fn opacity(&self, record: &HitRecord) -> f32 {
+        self.base.opacity(record)
+    }
+
+    fn albedo(&self, record: &HitRecord) -> Vec3 {
+        self.base.albedo(record)
+    }
+
+    fn shading_normal(&self, record: &HitRecord) -> Vec3 {
+        self.base.shading_normal(&self.perturb(record))
+    }
+}
+
+/// Perturbs the shading normal of another material as if the surface were
+/// displaced along its normal by a scalar height texture.
+pub struct BumpMapped {
+    base: Rc<dyn Material>,
+    height: Rc<dyn Texture>,
+    scale: f32,
+}
+
+/// The step in texture space used to differentiate the height texture.
+const BUMP_DELTA: f32 = 1. / 2048.;
+
+impl BumpMapped {
+    pub fn new(base: Rc<dyn Material>, height: Rc<dyn Texture>, scale: f32) -> BumpMapped {
+        BumpMapped {
+            base,
+            height,
+            scale,
+        }
+    }
+
+    fn perturb(&self, record: &HitRecord) -> HitRecord {
+        let height = |du: f32, dv: f32| {
+            let p = record.p + du * record.dpdu + dv * record.dpdv;
+            self.scale * self.height.scalar(record.u + du, record.v + dv, &p)
+        };
+
+        let h = height(0., 0.);
+        let dhdu = (height(BUMP_DELTA, 0.) - h) / BUMP_DELTA;
+        let dhdv = (height(0., BUMP_DELTA) - h) / BUMP_DELTA;
+
+        let n = record.normal.unit_vector();
+        let dpdu = record.dpdu + dhdu * n;
+        let dpdv = record.dpdv + dhdv * n;
+
+        let mut normal = dpdu.cross(&dpdv).unit_vector();
+        if normal.dot(&n) < 0. {
+            normal = -normal;
+        }
+        if !normal.x.is_finite() {
+            return record.clone();
+        }
+
+        HitRecord {
+            normal,
+            dpdu,
+            dpdv,
+            ..record.clone()
+        }
+    }
+}
+
+impl Material for BumpMapped {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        self.base.scatter(ray_in, &self.perturb(record), rng)
+    }
+
+    fn eval(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> Vec3 {
+        self.base.eval(ray_in, &self.perturb(record), wi)
+    }
+
+    fn pdf(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> f32 {
+        self.base.pdf(ray_in, &self.perturb(record), wi)
+    }
+
+    fn emitted(&self, ray_in: &Ray, record: &HitRecord) -> Vec3 {
+        self.base.emitted(ray_in, record)
+    }
+
+    fn dispersive(&self) -> bool {
+        self.base.dispersive()
+    }
+
+    fn is_specular(&self) -> bool {
+        self.base.is_specular()
+    }
+
+    fn opacity(&self, record: &HitRecord) -> f32 {
+        self.base.opacity(record)
+    }
+
+    fn albedo(&self, record: &HitRecord) -> Vec3 {
+        self.base.albedo(record)
+    }
+
+    fn shading_normal(&self, record: &HitRecord) -> Vec3 {
+        self.base.shading_normal(&self.perturb(record))
+    }
+}
+
+/// Cuts holes in another material with a grayscale opacity texture, for
+/// foliage and fences modelled as textured cards.
+pub struct AlphaMasked {
+    base: Rc<dyn Material>,
+    alpha: Rc<dyn Texture>,
+    cutoff: Option<f32>,
+}
+
+impl AlphaMasked {
+    pub fn new(base: Rc<dyn Material>, alpha: Rc<dyn Texture>) -> AlphaMasked {
+        AlphaMasked {
+            base,
+            alpha,
+            cutoff: None,
+        }
+    }
+
+    /// Makes the mask binary: alpha below `cutoff` is fully transparent and
+    /// anything else fully opaque, so no rays pass through stochastically.
+    pub fn with_cutoff(self, cutoff: f32) -> AlphaMasked {
+        AlphaMasked {
+            cutoff: Some(cutoff),
+            ..self
+        }
+    }
+}
+
+impl Material for AlphaMasked {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        self.base.scatter(ray_in, record, rng)
+    }
+
+    fn eval(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> Vec3 {
+        self.base.eval(ray_in, record, wi)
+    }
+
+    fn pdf(&self, ray_in: &Ray, record: &HitRecord, wi: &Vec3) -> f32 {
+        self.base.pdf(ray_in, record, wi)
+    }
+
+    fn emitted(&self, ray_in: &Ray, record: &HitRecord) -> Vec3 {
+        self.base.emitted(ray_in, record)
+    }
+
+    fn dispersive(&self) -> bool {
+        self.base.dispersive()
+    }
+
+    fn is_specular(&self) -> bool {
+        self.base.is_specular()
+    }
+
+    fn opacity(&self, record: &HitRecord) -> f32 {
+        let alpha = self.alpha.scalar(record.u, record.v, &record.p).clamp(0., 1.);
+
+        match self.cutoff {
+            Some(cutoff) if alpha < cutoff => 0.,
+            Some(_) => self.base.opacity(record),
+            None => alpha * self.base.opacity(record),
+        }
+    }
+
+    fn albedo(&self, record: &HitRecord) -> Vec3 {
+        self.base.albedo(record)
+    }
+
+    fn shading_normal(&self, record: &HitRecord) -> Vec3 {
+        self.base.shading_normal(record)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::light_sampler::UniformLightSampler;
+    use crate::objects::{Hittable, HittableList, Quad, Sphere};
+    use crate::scene::Scene;
+    use rand::rngs::ThreadRng;
+
+    #[test]
+    fn test_principled_sampling_matches_pdf() {
+        let mut rng = ThreadRng::default();
+        let material = Principled {
+            metallic: 0.3,
+            roughness: 0.5,
+            sheen: 0.5,
+            clearcoat: 0.5,
+            transmission: 0.3,
+            ..Principled::new(vec3!(0.8, 0.4, 0.2))
+        };
+        let wo = vec3!(0.3, 0.1, 0.8).unit_vector();
+        let n = 200_000;
+
+        // Importance sampled estimate of the directional albedo.
+        let mut sampled = 0f64;
+        for _ in 0..n {
+            if let Some(wi) = material.sample_local(&wo, &mut rng) {
+                let pdf = material.pdf_local(&wo, &wi);
+                if pdf > 0. {
+                    sampled += f64::from(material.eval_local(&wo, &wi).x / pdf);
+                }
+            }
+        }
+
+        // The same integral estimated with uniform sphere sampling.
+        let mut uniform = 0f64;
+        for _ in 0..n {
+            let z = 1. - 2. * rng.gen::<f32>();
+            let r = (1. - z * z).sqrt();
+            let phi = 2. * PI * rng.gen::<f32>();
+            let wi = vec3!(r * phi.cos(), r * phi.sin(), z);
+            uniform += f64::from(material.eval_local(&wo, &wi).x * 4. * PI);
+        }
+
+        let sampled = sampled / f64::from(n);
+        let uniform = uniform / f64::from(n);
+        assert!((sampled - uniform).abs() < 0.03 * uniform);
+    }
+
+    /// A hit on an upward facing quad made of `material`, by a ray coming
+    /// down at an angle.
+    fn hit_quad(material: Rc<dyn Material>) -> (Ray, HitRecord) {
+        let quad = Quad::new(vec3!(-1., 0., -1.), vec3!(0., 0., 2.), vec3!(2., 0., 0.), material);
+        let ray = Ray::new(vec3!(0.4, 1., 0.2), vec3!(-0.4, -1., -0.2));
+        let record = quad.hit(&ray, 0.001, f32::MAX).unwrap();
+        (ray, record)
+    }
+
+    /// Checks that the attenuation `scatter` returns off the non-specular
+    /// lobes, and `eval / pdf` at the directions it picks, both average to
+    /// the integral of `eval`.
+    fn check_scatter_matches_eval(material: Rc<dyn Material>) {
+        let mut rng = ThreadRng::default();
+        let (ray, record) = hit_quad(material);
+        let material = &record.material;
+        let n = 200_000;
+
+        let (mut attenuation, mut ratio) = (0f64, 0f64);
+        for _ in 0..n {
+            let scatter = match material.scatter(&ray, &record, &mut rng) {
+                Some(scatter) if !scatter.specular => scatter,
+                _ => continue,
+            };
+            let wi = scatter.ray.direction().unit_vector();
+            attenuation += f64::from(scatter.attenuation.x);
+            let pdf = material.pdf(&ray, &record, &wi);
+            if pdf > 0. {
+                ratio += f64::from(material.eval(&ray, &record, &wi).x / pdf);
+            }
+        }
+
+        let mut uniform = 0f64;
+        for _ in 0..n {
+            let wi = random_unit_vector(&mut rng);
+            uniform += f64::from(material.eval(&ray, &record, &wi).x * 4. * PI);
+        }
+
+        let (attenuation, ratio, uniform) = (attenuation / n as f64, ratio / n as f64, uniform / n as f64);
+        assert!((attenuation - uniform).abs() < 0.03 * uniform, "{} against {}", attenuation, uniform);
+        assert!((ratio - uniform).abs() < 0.03 * uniform, "{} against {}", ratio, uniform);
+    }
+
+    #[test]
+    fn test_mix_and_coated_sampling_matches_eval() {
+        let diffuse: Rc<dyn Material> = Rc::new(Lambertian::new(vec3!(0.8, 0.5, 0.3)));
+        let glossy: Rc<dyn Material> = Rc::new(Principled {
+            metallic: 0.7,
+            roughness: 0.4,
+            ..Principled::new(vec3!(0.9, 0.6, 0.2))
+        });
+
+        check_scatter_matches_eval(Rc::new(Mix::new(Rc::clone(&diffuse), Rc::clone(&glossy), 0.3)));
+        check_scatter_matches_eval(Rc::new(Coated::new(Rc::clone(&diffuse), 1.5)));
+        check_scatter_matches_eval(Rc::new(Coated::new(glossy, 1.5).with_tint(vec3!(0.9, 0.7, 0.5))));
+
+        // Both let the light of what they wrap through.
+        let light: Rc<dyn Material> = Rc::new(DiffuseLight::new(vec3!(4.)));
+        let (ray, record) = hit_quad(Rc::new(Mix::new(Rc::clone(&light), diffuse, 0.25)));
+        assert_eq!(record.material.emitted(&ray, &record), vec3!(3.));
+        let (ray, record) = hit_quad(Rc::new(Coated::new(light, 1.5)));
+        let cosine = -ray.direction().unit_vector().dot(&record.normal);
+        let expected = 4. * (1. - fresnel_dielectric(cosine, 1.5));
+        assert!((record.material.emitted(&ray, &record) - vec3!(expected)).length() < 1e-5);
+    }
+
+    #[test]
+    fn test_flat_normal_map_keeps_normal() {
+        let flat: Rc<dyn Texture> = Rc::new(SolidColor::new(vec3!(0.5, 0.5, 1.)));
+        let base: Rc<dyn Material> = Rc::new(Lambertian::new(vec3!(0.5)));
+        let (_, record) = hit_quad(Rc::new(NormalMapped::new(Rc::clone(&base), flat)));
+        assert!((record.material.shading_normal(&record) - record.normal).length() < 1e-5);
+
+        // A map leaning toward +u tilts the normal along `dpdu`.
+        let leaning: Rc<dyn Texture> = Rc::new(SolidColor::new(vec3!(1., 0.5, 1.)));
+        let (_, record) = hit_quad(Rc::new(NormalMapped::new(base, leaning)));
+        let normal = record.material.shading_normal(&record);
+        assert!(normal.dot(&record.dpdu) > 0.5 && normal.dot(&record.dpdv).abs() < 1e-5);
+
+        // Wrapping doesn't stop a surface glowing.
+        let light: Rc<dyn Material> = Rc::new(DiffuseLight::new(vec3!(2.)));
+        let flat: Rc<dyn Texture> = Rc::new(SolidColor::new(vec3!(0.5, 0.5, 1.)));
+        let (ray, record) = hit_quad(Rc::new(NormalMapped::new(Rc::clone(&light), Rc::clone(&flat))));
+        assert_eq!(record.material.emitted(&ray, &record), vec3!(2.));
+        let (ray, record) = hit_quad(Rc::new(BumpMapped::new(light, flat, 1.)));
+        assert_eq!(record.material.emitted(&ray, &record), vec3!(2.));
+    }
+
+    #[test]
+    fn test_wrapped_alpha_masks_stay_cut_out() {
+        let leaf: Rc<dyn Material> = Rc::new(Lambertian::new(vec3!(0.2, 0.6, 0.1)));
+        let flat: Rc<dyn Texture> = Rc::new(SolidColor::new(vec3!(0.5, 0.5, 1.)));
+        let masked = |alpha: f32| -> Rc<dyn Material> {
+            Rc::new(AlphaMasked::new(Rc::clone(&leaf), Rc::new(SolidColor::new(vec3!(alpha)))))
+        };
+        let wrappers: Vec<Rc<dyn Material>> = vec![
+            Rc::new(NormalMapped::new(masked(0.), Rc::clone(&flat))),
+            Rc::new(BumpMapped::new(masked(0.), Rc::clone(&flat), 1.)),
+            Rc::new(Coated::new(masked(0.), 1.5)),
+            Rc::new(Mix::new(masked(0.), masked(0.), 0.5)),
+        ];
+
+        // A card with holes all over in front of a solid quad.
+        let ray = Ray::new(vec3!(0.2, 1., 0.1), vec3!(0., -1., 0.));
+        let card = |material: Rc<dyn Material>| {
+            Box::new(Quad::new(vec3!(-1., 0., -1.), vec3!(0., 0., 2.), vec3!(2., 0., 0.), material))
+        };
+        for material in wrappers {
+            let mut list = HittableList::new();
+            list.add(card(material));
+            list.add(Box::new(Quad::new(vec3!(-1., -1., -1.), vec3!(0., 0., 2.), vec3!(2., 0., 0.), Rc::clone(&leaf))));
+            assert_eq!(list.hit(&ray, 0.001, f32::MAX).unwrap().p.y, -1.);
+        }
+
+        // Half opaque cards let half the light through, however wrapped.
+        let wrappers: Vec<Rc<dyn Material>> = vec![
+            Rc::new(NormalMapped::new(masked(0.5), Rc::clone(&flat))),
+            Rc::new(BumpMapped::new(masked(0.5), flat, 1.)),
+            Rc::new(Coated::new(masked(0.5), 1.5)),
+            Rc::new(Mix::new(masked(0.25), masked(0.75), 0.5)),
+        ];
+        for material in wrappers {
+            let mut list = HittableList::new();
+            list.add(card(material));
+            let scene = Scene::new(Box::new(list), vec![], Box::new(UniformLightSampler::new(&[])));
+            assert!((scene.transmittance(ray.origin(), ray.direction(), 2.) - 0.5).abs() < 1e-6);
+        }
+
+        // Masked cards still glow where they are.
+        let sign: Rc<dyn Material> = Rc::new(DiffuseLight::new(vec3!(2.)));
+        let (ray, record) = hit_quad(Rc::new(AlphaMasked::new(sign, Rc::new(SolidColor::new(vec3!(1.))))));
+        assert_eq!(record.material.emitted(&ray, &record), vec3!(2.));
+    }
+
+    #[test]
+    fn test_dielectric_absorbs_along_chord() {
+        let mut rng = ThreadRng::default();
+        let absorption = vec3!(0.5, 1., 2.);
+
+        // With an index of one the ray goes straight through, crossing a
+        // chord 1.6 long at 0.6 from the center of a unit sphere.
+        let sphere = Sphere::new(vec3!(0.), 1., Rc::new(Dielectric::new(1.).with_absorption(absorption)));
+        let ray = Ray::new(vec3!(-2., 0.6, 0.), vec3!(1., 0., 0.));
+        let entry = sphere.hit(&ray, 0.001, f32::MAX).unwrap();
+        let inside = sphere.material().unwrap().scatter(&ray, &entry, &mut rng).unwrap();
+        assert_eq!(inside.attenuation, vec3!(1.));
+
+        let exit = sphere.hit(&inside.ray, 0.001, f32::MAX).unwrap();
+        let out = sphere.material().unwrap().scatter(&inside.ray, &exit, &mut rng).unwrap();
+        let expected = absorption.map(|a| (-a * 1.6).exp());
+        assert!((out.attenuation - expected).length() < 1e-4, "{:?}", out.attenuation);
+
+        // A transmission color is what is left after its distance.
+        let tinted = Dielectric::new(1.5).with_transmission_color(vec3!(0.8, 0.5, 0.2), 2.);
+        let left = tinted.absorption.map(|a| (-a * 2.).exp());
+        assert!((left - vec3!(0.8, 0.5, 0.2)).length() < 1e-5);
     }
 }
@@ -0,0 +1,280 @@
+use rand::rngs::StdRng;
+use rand::{Error, Rng, RngCore, SeedableRng};
+use std::f32::consts::PI;
+
+use crate::camera::Camera;
+use crate::distribution::Distribution1D;
+use crate::film::{Film, Filter};
+use crate::integrator::Integrator;
+use crate::material::luminance;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// How often a mutation throws the whole path away for a fresh one.
+const LARGE_STEP_PROBABILITY: f32 = 0.3;
+
+/// The spread of a small step in each primary sample.
+const SIGMA: f32 = 0.01;
+
+/// Paths traced to estimate the brightness of the image.
+const BOOTSTRAP_SAMPLES: usize = 100_000;
+
+const CHAINS: usize = 1000;
+
+struct PrimarySample {
+    value: f32,
+    /// The iteration the value was last changed in.
+    modified: u64,
+    backup: f32,
+    backup_modified: u64,
+}
+
+/// The primary sample space of Kelemen et al. (2002): the vector of uniform
+/// numbers a path is made from, handed out in order as the path asks for
+/// them and mutated lazily, so that a path can be replayed and perturbed
+/// without knowing how many numbers it uses.
+pub struct PrimarySampler {
+    rng: StdRng,
+    samples: Vec<PrimarySample>,
+    index: usize,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+}
+
+impl PrimarySampler {
+    /// Samplers with the same seed replay the same first path.
+    pub fn new(seed: u64) -> PrimarySampler {
+        PrimarySampler {
+            rng: StdRng::seed_from_u64(seed),
+            samples: vec![],
+            index: 0,
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+        }
+    }
+
+    /// Starts mutating the path, with either a large or a small step.
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f32>() < LARGE_STEP_PROBABILITY;
+        self.index = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// Puts back the samples the rejected mutation changed.
+    fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.modified == self.iteration {
+                sample.value = sample.backup;
+                sample.modified = sample.backup_modified;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    /// The next number of the path, in `[0, 1)`.
+    fn next(&mut self) -> f32 {
+        // A number the path hasn't asked for before is as random as if the
+        // last large step had drawn it.
+        if self.index == self.samples.len() {
+            self.samples.push(PrimarySample {
+                value: self.rng.gen(),
+                modified: self.last_large_step,
+                backup: 0.,
+                backup_modified: 0,
+            });
+        }
+
+        let rng = &mut self.rng;
+        let sample = &mut self.samples[self.index];
+        self.index += 1;
+
+        // A sample unused since the last large step missed it, so catch up.
+        if sample.modified < self.last_large_step {
+            sample.value = rng.gen();
+            sample.modified = self.last_large_step;
+        }
+
+        sample.backup = sample.value;
+        sample.backup_modified = sample.modified;
+
+        if self.large_step {
+            sample.value = rng.gen();
+        } else {
+            // Small steps the sample missed add up to one wider step.
+            let steps = (self.iteration - sample.modified) as f32;
+            let (u1, u2): (f32, f32) = (rng.gen(), rng.gen());
+            let normal = (-2. * (1. - u1).ln()).sqrt() * (2. * PI * u2).cos();
+
+            sample.value += normal * SIGMA * steps.sqrt();
+            sample.value -= sample.value.floor();
+            sample.value = sample.value.min(1. - f32::EPSILON);
+        }
+
+        sample.modified = self.iteration;
+        sample.value
+    }
+}
+
+impl RngCore for PrimarySampler {
+    fn next_u32(&mut self) -> u32 {
+        (f64::from(self.next()) * 4_294_967_296.) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        u64::from(self.next_u32()) << 32 | u64::from(self.next_u32())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Primary sample space Metropolis light transport (Kelemen et al. 2002).
+///
+/// Markov chains wander over paths, mostly by nudging the numbers the last
+/// path was made from, and visit each in proportion to how bright it is.
+/// Once a chain finds a hard to reach light path it explores the paths near
+/// it, rather than losing it as independent samples would.
+pub struct Mlt {
+    integrator: Box<dyn Integrator>,
+    bootstrap: usize,
+    chains: usize,
+}
+
+impl Mlt {
+    /// Runs the chains over the paths `integrator` traces, which must draw
+    /// every random number from the `rng` it's given.
+    pub fn new(integrator: Box<dyn Integrator>) -> Mlt {
+        Mlt {
+            integrator,
+            bootstrap: BOOTSTRAP_SAMPLES,
+            chains: CHAINS,
+        }
+    }
+
+    /// Renders about `mutations` mutations per pixel, returning the pixels
+    /// a row at a time from the top.
+    pub fn render(
+        &self,
+        scene: &Scene,
+        camera: &dyn Camera,
+        width: usize,
+        height: usize,
+        mutations: usize,
+        rng: &mut dyn RngCore,
+    ) -> Vec<Vec3> {
+        let sample = |sampler: &mut PrimarySampler| {
+            let (u, v): (f32, f32) = (sampler.gen(), sampler.gen());
+            let color = match camera.get_ray(u, v, sampler) {
+                Some(ray) => self.integrator.color(ray, scene, sampler),
+                None => vec3!(0.),
+            };
+            (u, v, color)
+        };
+
+        // The mean brightness of independent paths normalizes the image,
+        // which the chains only know the relative brightness of.
+        let seed: u64 = rng.gen();
+        let weights: Vec<f32> = (0..self.bootstrap)
+            .map(|i| {
+                let (_, _, l) = sample(&mut PrimarySampler::new(seed.wrapping_add(i as u64)));
+                luminance(&l).max(0.)
+            })
+            .collect();
+        let bootstrap = Distribution1D::new(weights);
+        let brightness = bootstrap.integral();
+
+        let mut film = Film::new(width, height, Filter::Box(0.5));
+        if brightness <= 0. {
+            return film.pixels(0.);
+        }
+
+        let mut splat = |u: f32, v: f32, l: Vec3| {
+            film.add_splat(u * width as f32, (1. - v) * height as f32, l);
+        };
+
+        let total = width * height * mutations;
+        for chain in 0..self.chains {
+            let iterations = total / self.chains + usize::from(chain < total % self.chains);
+
+            // Start from a bootstrap path, so the chain begins in proportion
+            // to brightness and never needs a burn-in.
+            let (_, _, index) = bootstrap.sample(rng.gen());
+            let mut sampler = PrimarySampler::new(seed.wrapping_add(index as u64));
+            let mut current = sample(&mut sampler);
+            let mut current_luminance = luminance(&current.2);
+
+            for _ in 0..iterations {
+                sampler.start_iteration();
+                let proposed = sample(&mut sampler);
+                let proposed_luminance = luminance(&proposed.2);
+
+                let accept = if current_luminance > 0. {
+                    (proposed_luminance / current_luminance).clamp(0., 1.)
+                } else {
+                    1.
+                };
+
+                // Both paths get their expected share, which is less noisy
+                // than splatting only whichever one the chain ends up on.
+                if proposed_luminance > 0. {
+                    splat(proposed.0, proposed.1, proposed.2 * (accept / proposed_luminance));
+                }
+                if current_luminance > 0. {
+                    splat(current.0, current.1, current.2 * ((1. - accept) / current_luminance));
+                }
+
+                if rng.gen::<f32>() < accept {
+                    sampler.accept();
+                    current = proposed;
+                    current_luminance = proposed_luminance;
+                } else {
+                    sampler.reject();
+                }
+            }
+        }
+
+        film.pixels(brightness / mutations as f32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rejected_mutations_leave_path_unchanged() {
+        let mut sampler = PrimarySampler::new(3);
+        let path: Vec<f32> = (0..8).map(|_| sampler.next()).collect();
+
+        let mut replay = PrimarySampler::new(3);
+        assert_eq!((0..8).map(|_| replay.next()).collect::<Vec<f32>>(), path);
+
+        for _ in 0..10 {
+            sampler.start_iteration();
+            let mutated: Vec<f32> = (0..8).map(|_| sampler.next()).collect();
+            assert!(mutated.iter().all(|u| (0. ..1.).contains(u)));
+            assert_ne!(mutated, path);
+            sampler.reject();
+        }
+
+        let values: Vec<f32> = sampler.samples.iter().map(|sample| sample.value).collect();
+        assert_eq!(values, path);
+    }
+}
@@ -1,22 +1,96 @@
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use rand::{Rng, RngCore};
+use std::f32::consts::PI;
 use std::rc::Rc;
 
+use crate::aabb::Aabb;
 use crate::material::{Dielectric, Lambertian, Material, Metal};
+use crate::onb::Onb;
 use crate::ray::Ray;
 use crate::vec3::Vec3;
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub t: f32,
     pub p: Vec3,
     pub normal: Vec3,
+    pub u: f32,
+    pub v: f32,
+    /// The partial derivatives of `p` with respect to `u` and `v`.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub material: Rc<dyn Material>,
+    /// The index of the scene light this surface belongs to, if it is
+    /// emissive.
+    pub light: Option<usize>,
+    /// The position of the object hit in the list the scene's BVH was built
+    /// from.
+    pub object: usize,
+    /// The index of the object's material, numbered in the order materials
+    /// first appear in the list the scene's BVH was built from.
+    pub material_id: usize,
+}
+
+impl HitRecord {
+    /// The tangent frame at the hit, aligned with the texture coordinates.
+    pub fn frame(&self) -> Onb {
+        Onb::from_tangent(&self.normal, &self.dpdu, &self.dpdv)
+    }
 }
 
 pub trait Hittable {
     fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+
+    fn bounding_box(&self) -> Aabb;
+
+    /// How many bounding boxes and objects `hit` tests to find the hit.
+    fn hit_cost(&self, _ray: &Ray, _t_min: f32, _t_max: f32) -> usize {
+        1
+    }
+
+    /// The material the whole object is made of, if it is made of one.
+    fn material(&self) -> Option<&Rc<dyn Material>> {
+        None
+    }
 }
 
+/// A surface that area lights can be spread over.
+pub trait Shape: Hittable {
+    fn area(&self) -> f32;
+
+    /// Picks a point on the surface visible from `p`, returning it with its
+    /// normal and the solid angle density of the direction toward it.
+    fn sample(&self, p: &Vec3, rng: &mut dyn RngCore) -> Option<(Vec3, Vec3, f32)>;
+
+    /// Picks a point uniformly over the whole surface, returning it with its
+    /// normal.
+    fn sample_area(&self, rng: &mut dyn RngCore) -> (Vec3, Vec3);
+
+    /// The solid angle density with which `sample` picks the unit direction
+    /// `wi` from `p`.
+    fn pdf(&self, p: &Vec3, wi: &Vec3) -> f32 {
+        match self.hit(&Ray::new(*p, *wi), 0.001, f32::MAX) {
+            Some(record) => area_to_solid_angle(record.t, &record.normal, wi, self.area()),
+            None => 0.,
+        }
+    }
+
+    /// The normals of the surface, as a unit axis and the cosine of the
+    /// widest angle any normal makes with it.
+    fn normal_bounds(&self) -> (Vec3, f32);
+}
+
+/// Converts a uniform density over an area to one over the solid angle
+/// subtended from `distance` away along the unit direction `wi`.
+fn area_to_solid_angle(distance: f32, normal: &Vec3, wi: &Vec3, area: f32) -> f32 {
+    let cosine = normal.unit_vector().dot(wi).abs();
+    if cosine <= 0. {
+        return 0.;
+    }
+
+    distance * distance / (cosine * area)
+}
+
+#[derive(Clone)]
 pub struct Sphere {
     center: Vec3,
     radius: f32,
@@ -33,38 +107,399 @@ impl Sphere {
     }
 }
 
+impl Sphere {
+    fn record_at(&self, ray: &Ray, t: f32) -> HitRecord {
+        let p = ray.point_at_parameter(t);
+        let normal = (p - self.center) / self.radius;
+
+        let phi = (-normal.z).atan2(normal.x) + PI;
+        let theta = (-normal.y).clamp(-1., 1.).acos();
+
+        let sin_theta = theta.sin().max(1e-6);
+        let dpdu = 2. * PI * self.radius * vec3!(normal.z, 0., -normal.x);
+        let dpdv = PI
+            * self.radius
+            * vec3!(
+                -normal.x * normal.y / sin_theta,
+                sin_theta,
+                -normal.y * normal.z / sin_theta
+            );
+
+        HitRecord {
+            t,
+            p,
+            normal,
+            u: phi / (2. * PI),
+            v: theta / PI,
+            dpdu,
+            dpdv,
+            material: Rc::clone(&self.material),
+            light: None,
+            object: 0,
+            material_id: 0,
+        }
+    }
+}
+
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let oc = ray.origin() - self.center;
         let a = ray.direction().dot(ray.direction());
-        let b = (&oc).dot(ray.direction());
-        let c = (&oc).dot(&oc) - self.radius * self.radius;
+        let b = oc.dot(ray.direction());
+        let c = oc.dot(&oc) - self.radius * self.radius;
         let discriminant = b * b - a * c;
 
         if discriminant > 0. {
             let d_square = discriminant.sqrt();
-            let mut temp = (-b - d_square) / a;
 
-            if temp > t_max || temp < t_min {
-                temp = (-b + d_square) / a;
-                if temp > t_max || temp < t_min {
-                    return None;
+            for temp in [(-b - d_square) / a, (-b + d_square) / a].iter() {
+                if *temp > t_max || *temp < t_min {
+                    continue;
+                }
+
+                // Fully masked out hits let the ray carry on to the far side.
+                let record = self.record_at(ray, *temp);
+                if record.material.opacity(&record) > 0. {
+                    return Some(record);
                 }
             }
+        }
+
+        None
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.center - vec3!(self.radius), self.center + vec3!(self.radius))
+    }
+    fn material(&self) -> Option<&Rc<dyn Material>> {
+        Some(&self.material)
+    }
+}
 
-            let t = temp;
-            let p = ray.point_at_parameter(temp);
-            let normal = (p - &self.center) / self.radius;
+impl Shape for Sphere {
+    fn area(&self) -> f32 {
+        4. * PI * self.radius * self.radius
+    }
 
-            return Some(HitRecord {
-                t,
-                p,
-                normal,
-                material: Rc::clone(&self.material),
-            });
+    fn sample(&self, p: &Vec3, rng: &mut dyn RngCore) -> Option<(Vec3, Vec3, f32)> {
+        let oc = self.center - p;
+        let distance2 = oc.squared_length();
+        let radius2 = self.radius * self.radius;
+
+        if distance2 <= radius2 {
+            // From inside, any point could be visible.
+            let normal = random_unit_vector(rng);
+            let q = self.center + self.radius * normal;
+            let to_q = q - p;
+            let pdf = area_to_solid_angle(to_q.length(), &normal, &to_q.unit_vector(), self.area());
+            return Some((q, normal, pdf));
         }
 
-        None
+        // Sample the cone of directions the sphere subtends (Shirley et al.
+        // 1996), using `1 - cos = sin² / (1 + cos)` to keep precision for
+        // distant spheres.
+        let sin2_max = radius2 / distance2;
+        let cos_max = (1. - sin2_max).max(0.).sqrt();
+        let one_minus_cos_max = sin2_max / (1. + cos_max);
+
+        let cos_theta = 1. - rng.gen::<f32>() * one_minus_cos_max;
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * rng.gen::<f32>();
+        let wi = Onb::from_w(&oc).to_world(&vec3!(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+
+        let distance = distance2.sqrt();
+        let t = distance * cos_theta - (radius2 - distance2 * sin_theta * sin_theta).max(0.).sqrt();
+        let q = p + t * wi;
+
+        Some((q, (q - self.center) / self.radius, 1. / (2. * PI * one_minus_cos_max)))
+    }
+
+    fn sample_area(&self, rng: &mut dyn RngCore) -> (Vec3, Vec3) {
+        let normal = random_unit_vector(rng);
+        (self.center + self.radius * normal, normal)
+    }
+
+    fn pdf(&self, p: &Vec3, wi: &Vec3) -> f32 {
+        let oc = self.center - p;
+        let distance2 = oc.squared_length();
+        let radius2 = self.radius * self.radius;
+
+        if distance2 <= radius2 {
+            return match self.hit(&Ray::new(*p, *wi), 0.001, f32::MAX) {
+                Some(record) => area_to_solid_angle(record.t, &record.normal, wi, self.area()),
+                None => 0.,
+            };
+        }
+
+        let sin2_max = radius2 / distance2;
+        let cos_max = (1. - sin2_max).max(0.).sqrt();
+        let one_minus_cos_max = sin2_max / (1. + cos_max);
+
+        if 1. - wi.dot(&oc) / distance2.sqrt() <= one_minus_cos_max {
+            1. / (2. * PI * one_minus_cos_max)
+        } else {
+            0.
+        }
+    }
+
+    fn normal_bounds(&self) -> (Vec3, f32) {
+        (vec3!(0., 0., 1.), -1.)
+    }
+}
+
+pub fn random_unit_vector(rng: &mut dyn RngCore) -> Vec3 {
+    let z = 1. - 2. * rng.gen::<f32>();
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * rng.gen::<f32>();
+    vec3!(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// A single triangle with per-vertex texture coordinates. The normal faces
+/// the side from which the vertices appear counter-clockwise.
+#[derive(Clone)]
+pub struct Triangle {
+    vertices: [Vec3; 3],
+    uvs: [(f32, f32); 3],
+    material: Rc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(vertices: [Vec3; 3], uvs: [(f32, f32); 3], material: Rc<dyn Material>) -> Triangle {
+        Triangle {
+            vertices,
+            uvs,
+            material,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let [p0, p1, p2] = self.vertices;
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+
+        // Möller-Trumbore.
+        let pvec = ray.direction().cross(&e2);
+        let det = e1.dot(&pvec);
+        if det.abs() < 1e-9 {
+            return None;
+        }
+
+        let inv_det = 1. / det;
+        let tvec = ray.origin() - p0;
+        let b1 = tvec.dot(&pvec) * inv_det;
+        if !(0. ..=1.).contains(&b1) {
+            return None;
+        }
+
+        let qvec = tvec.cross(&e1);
+        let b2 = ray.direction().dot(&qvec) * inv_det;
+        if b2 < 0. || b1 + b2 > 1. {
+            return None;
+        }
+
+        let t = e2.dot(&qvec) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let b0 = 1. - b1 - b2;
+        let [(u0, v0), (u1, v1), (u2, v2)] = self.uvs;
+
+        // Solve for the derivatives of position along the texture axes.
+        let (du1, dv1) = (u1 - u0, v1 - v0);
+        let (du2, dv2) = (u2 - u0, v2 - v0);
+        let uv_det = du1 * dv2 - dv1 * du2;
+        let normal = e1.cross(&e2).unit_vector();
+
+        let (dpdu, dpdv) = if uv_det.abs() < 1e-9 {
+            let frame = Onb::from_w(&normal);
+            (frame.u, frame.v)
+        } else {
+            let inv = 1. / uv_det;
+            ((dv2 * e1 - dv1 * e2) * inv, (du1 * e2 - du2 * e1) * inv)
+        };
+
+        let record = HitRecord {
+            t,
+            p: ray.point_at_parameter(t),
+            normal,
+            u: b0 * u0 + b1 * u1 + b2 * u2,
+            v: b0 * v0 + b1 * v1 + b2 * v2,
+            dpdu,
+            dpdv,
+            material: Rc::clone(&self.material),
+            light: None,
+            object: 0,
+            material_id: 0,
+        };
+
+        if record.material.opacity(&record) > 0. {
+            Some(record)
+        } else {
+            None
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let [p0, p1, p2] = self.vertices;
+        Aabb::new(p0, p1).union(&Aabb::new(p2, p2))
+    }
+    fn material(&self) -> Option<&Rc<dyn Material>> {
+        Some(&self.material)
+    }
+}
+
+impl Shape for Triangle {
+    fn area(&self) -> f32 {
+        let [p0, p1, p2] = self.vertices;
+        0.5 * (p1 - p0).cross(&(p2 - p0)).length()
+    }
+
+    fn sample(&self, p: &Vec3, rng: &mut dyn RngCore) -> Option<(Vec3, Vec3, f32)> {
+        let (q, normal) = self.sample_area(rng);
+        let to_q = q - p;
+        let distance = to_q.length();
+        let pdf = area_to_solid_angle(distance, &normal, &(to_q / distance), self.area());
+
+        Some((q, normal, pdf))
+    }
+
+    fn sample_area(&self, rng: &mut dyn RngCore) -> (Vec3, Vec3) {
+        let [p0, p1, p2] = self.vertices;
+        let su = rng.gen::<f32>().sqrt();
+        let (b0, b1) = (1. - su, rng.gen::<f32>() * su);
+
+        let q = b0 * p0 + b1 * p1 + (1. - b0 - b1) * p2;
+        (q, (p1 - p0).cross(&(p2 - p0)).unit_vector())
+    }
+
+    fn normal_bounds(&self) -> (Vec3, f32) {
+        let [p0, p1, p2] = self.vertices;
+        ((p1 - p0).cross(&(p2 - p0)).unit_vector(), 1.)
+    }
+}
+
+/// A parallelogram with one corner at `q` and sides `u` and `v`, which are
+/// also its texture axes. The normal is `u × v`.
+#[derive(Clone)]
+pub struct Quad {
+    q: Vec3,
+    u: Vec3,
+    v: Vec3,
+    material: Rc<dyn Material>,
+}
+
+impl Quad {
+    pub fn new(q: Vec3, u: Vec3, v: Vec3, material: Rc<dyn Material>) -> Quad {
+        Quad { q, u, v, material }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let n = self.u.cross(&self.v);
+        let denom = n.dot(ray.direction());
+        if denom.abs() < 1e-9 {
+            return None;
+        }
+
+        let t = n.dot(&(self.q - ray.origin())) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let p = ray.point_at_parameter(t);
+        let w = n / n.dot(&n);
+        let offset = p - self.q;
+        let alpha = w.dot(&offset.cross(&self.v));
+        let beta = w.dot(&self.u.cross(&offset));
+
+        if !(0. ..=1.).contains(&alpha) || !(0. ..=1.).contains(&beta) {
+            return None;
+        }
+
+        let record = HitRecord {
+            t,
+            p,
+            normal: n.unit_vector(),
+            u: alpha,
+            v: beta,
+            dpdu: self.u,
+            dpdv: self.v,
+            material: Rc::clone(&self.material),
+            light: None,
+            object: 0,
+            material_id: 0,
+        };
+
+        if record.material.opacity(&record) > 0. {
+            Some(record)
+        } else {
+            None
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let far = self.q + self.u + self.v;
+        Aabb::new(self.q, far).union(&Aabb::new(self.q + self.u, self.q + self.v))
+    }
+    fn material(&self) -> Option<&Rc<dyn Material>> {
+        Some(&self.material)
+    }
+}
+
+impl Shape for Quad {
+    fn area(&self) -> f32 {
+        self.u.cross(&self.v).length()
+    }
+
+    fn sample(&self, p: &Vec3, rng: &mut dyn RngCore) -> Option<(Vec3, Vec3, f32)> {
+        let (q, normal) = self.sample_area(rng);
+        let to_q = q - p;
+        let distance = to_q.length();
+        let pdf = area_to_solid_angle(distance, &normal, &(to_q / distance), self.area());
+
+        Some((q, normal, pdf))
+    }
+
+    fn sample_area(&self, rng: &mut dyn RngCore) -> (Vec3, Vec3) {
+        let q = self.q + rng.gen::<f32>() * self.u + rng.gen::<f32>() * self.v;
+        (q, self.u.cross(&self.v).unit_vector())
+    }
+
+    fn normal_bounds(&self) -> (Vec3, f32) {
+        (self.u.cross(&self.v).unit_vector(), 1.)
+    }
+}
+
+/// A shape that is also one of the scene's lights, so that paths hitting it
+/// by chance can be weighted against sampling the light directly.
+pub struct Emitter<S: Shape> {
+    shape: S,
+    light: usize,
+}
+
+impl<S: Shape> Emitter<S> {
+    pub fn new(shape: S, light: usize) -> Emitter<S> {
+        Emitter { shape, light }
+    }
+}
+
+impl<S: Shape> Hittable for Emitter<S> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut record = self.shape.hit(ray, t_min, t_max)?;
+        record.light = Some(self.light);
+        Some(record)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.shape.bounding_box()
+    }
+
+    fn material(&self) -> Option<&Rc<dyn Material>> {
+        self.shape.material()
     }
 }
 
@@ -83,7 +518,11 @@ impl HittableList {
         self.list.push(object);
     }
 
-    pub fn random(rng: &mut ThreadRng) -> HittableList {
+    pub fn into_objects(self) -> Vec<Box<dyn Hittable>> {
+        self.list
+    }
+
+    pub fn random(rng: &mut dyn RngCore) -> HittableList {
         let mut list = HittableList::new();
 
         list.add(Box::new(Sphere::new(
@@ -107,7 +546,7 @@ impl HittableList {
                     b_fl + 0.9 * rng.gen::<f32>()
                 );
 
-                if (&center - &CENTER).length() > 0.9 {
+                if (center - CENTER).length() > 0.9 {
                     if choose_mat < 0.8 {
                         list.add(Box::new(Sphere::new(
                             center,
@@ -174,6 +613,12 @@ impl Hittable for HittableList {
             }
         }
 
-        return record;
+        record
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.list
+            .iter()
+            .fold(Aabb::empty(), |bounds, item| bounds.union(&item.bounding_box()))
     }
 }
@@ -0,0 +1,78 @@
+use crate::vec3::Vec3;
+
+/// An orthonormal basis, used to move directions between world space and a
+/// local shading frame where `w` is the surface normal.
+#[derive(Clone, Debug)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    /// Builds an arbitrary frame around `n` (Duff et al. 2017).
+    pub fn from_w(n: &Vec3) -> Onb {
+        let w = n.unit_vector();
+        let sign = if w.z >= 0. { 1. } else { -1. };
+        let a = -1. / (sign + w.z);
+        let b = w.x * w.y * a;
+
+        let u = vec3!(1. + sign * w.x * w.x * a, sign * b, -sign * w.x);
+        let v = vec3!(b, sign + w.y * w.y * a, -w.y);
+
+        Onb { u, v, w }
+    }
+
+    /// Builds a frame around `n` with `u` following the tangent `dpdu`, and
+    /// `v` on the same side as `dpdv` so mirrored texture layouts keep their
+    /// handedness. Falls back to `from_w` if the tangent is degenerate.
+    pub fn from_tangent(n: &Vec3, dpdu: &Vec3, dpdv: &Vec3) -> Onb {
+        let w = n.unit_vector();
+        let t = dpdu - w * w.dot(dpdu);
+
+        if t.squared_length() < 1e-12 {
+            return Onb::from_w(&w);
+        }
+
+        let u = t.unit_vector();
+        let mut v = w.cross(&u);
+        if v.dot(dpdv) < 0. {
+            v = -v;
+        }
+
+        Onb { u, v, w }
+    }
+
+    /// Converts a direction expressed in this frame to world space.
+    pub fn to_world(&self, a: &Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    /// Converts a world space direction into this frame.
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        vec3!(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tangent_frame_follows_uvs() {
+        let n = vec3!(0., 1., 0.);
+        let frame = Onb::from_tangent(&n, &vec3!(2., 0.5, 0.), &vec3!(0., 0., 3.));
+        assert!((frame.u - vec3!(1., 0., 0.)).length() < 1e-6);
+        assert!((frame.v - vec3!(0., 0., 1.)).length() < 1e-6);
+        assert_eq!(frame.w, n);
+
+        // Mirrored texture coordinates flip `v` to keep following `dpdv`.
+        let mirrored = Onb::from_tangent(&n, &vec3!(2., 0.5, 0.), &vec3!(0., 0., -3.));
+        assert!((mirrored.u - frame.u).length() < 1e-6);
+        assert!((mirrored.v + frame.v).length() < 1e-6);
+
+        // Without a tangent, any frame around the normal will do.
+        let fallback = Onb::from_tangent(&n, &vec3!(0., 2., 0.), &vec3!(0., 0., 1.));
+        assert!(fallback.u.dot(&n).abs() < 1e-6 && fallback.v.dot(&n).abs() < 1e-6);
+    }
+}
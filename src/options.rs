@@ -0,0 +1,287 @@
+use std::env;
+
+/// Command line options for a render.
+pub struct Options {
+    pub scene: String,
+    pub spectral: bool,
+    /// The light transport algorithm, such as `path` or `bdpt`, or a debug
+    /// view such as `normals`.
+    pub integrator: String,
+    /// Photons traced for the photon map, or per pass of `sppm`.
+    pub photons: usize,
+    /// The radius photons are gathered from, or where it starts for `sppm`.
+    pub photon_radius: f32,
+    /// How far ambient occlusion looks for occluders.
+    pub ao_distance: f32,
+    /// Where to write AOVs, as `<prefix>_<layer>.exr`.
+    pub aovs: Option<String>,
+    /// Whether to denoise the image, guided by the AOVs.
+    pub denoise: bool,
+    /// Samples per pixel, unless sampling adaptively. Without a time budget
+    /// or target noise, 10.
+    pub samples: Option<u32>,
+    /// Where to save the render as it goes, and to resume it from if it
+    /// exists.
+    pub checkpoint: Option<String>,
+    /// Seconds between checkpoints.
+    pub checkpoint_interval: f32,
+    /// Minutes to render for at most.
+    pub time_budget: Option<f32>,
+    /// The relative noise of the image, in percent, to stop at.
+    pub target_noise: Option<f32>,
+    /// Seconds between progress reports.
+    pub progress_interval: f32,
+    /// Whether to stop sampling pixels once they are no longer noisy,
+    /// between `min_samples` and `max_samples`.
+    pub adaptive: bool,
+    pub min_samples: u32,
+    pub max_samples: u32,
+    /// The relative error of a pixel's luminance that adaptive sampling
+    /// stops at.
+    pub noise_threshold: f32,
+    /// Where to write a picture of how many samples each pixel took.
+    pub sample_heatmap: Option<String>,
+    /// The size of the image in pixels. Given one, the other keeps the
+    /// camera's own shape.
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    /// How the camera projects the scene: `perspective`, `orthographic`,
+    /// `fisheye`, `equirectangular` or `cube-map`.
+    pub camera: String,
+    /// The field of view in degrees, vertically for `perspective` and across
+    /// the image circle for `fisheye`.
+    pub fov: Option<f32>,
+    /// How many units high an `orthographic` camera's view is.
+    pub view_height: Option<f32>,
+    /// Where camera samples come from: `independent`, `stratified`,
+    /// `halton`, `sobol` or `blue-noise`.
+    pub sampler: String,
+    /// How samples are weighted into pixels: `box`, `tent`, `gaussian`,
+    /// `mitchell` or `lanczos`.
+    pub filter: String,
+    /// The radius of the filter in pixels, if not the filter's own default.
+    pub filter_radius: Option<f32>,
+    /// The part of the image to render, as fractions `x0,x1,y0,y1` of its
+    /// width and height from the top left.
+    pub crop_window: Option<[f32; 4]>,
+    /// The part of the image to render, as pixels `x0,x1,y0,y1` from the top
+    /// left, not including the ends.
+    pub pixel_bounds: Option<[usize; 4]>,
+    /// Whether to write only the cropped part, rather than the whole image
+    /// with the rest black.
+    pub cropped: bool,
+    /// The order tiles are rendered in: `scanline`, `spiral` or `hilbert`.
+    pub tile_order: String,
+    pub tile_size: usize,
+    /// Seeds every random number of the render, scenes included.
+    pub seed: u64,
+    pub normal_map: Option<String>,
+    pub environment: Option<String>,
+    /// Degrees to turn the environment about the vertical axis.
+    pub environment_rotation: f32,
+    pub environment_intensity: f32,
+    /// A file listing point, spot and directional lights.
+    pub lights: Option<String>,
+    /// How to pick lights to sample: `uniform`, `power` or `bvh`.
+    pub light_sampler: String,
+    /// Whether to light the scene with a physical sky and sun.
+    pub sky: bool,
+    /// The sun position in degrees above the horizon and clockwise from
+    /// `-z`.
+    pub sun_elevation: f32,
+    pub sun_azimuth: f32,
+    /// The angular diameter of the sun in degrees.
+    pub sun_size: f32,
+    pub turbidity: f32,
+    pub ground_albedo: f32,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            scene: String::from("random"),
+            spectral: false,
+            integrator: String::from("path"),
+            photons: 200_000,
+            photon_radius: 0.1,
+            ao_distance: 1.,
+            aovs: None,
+            denoise: false,
+            samples: None,
+            checkpoint: None,
+            checkpoint_interval: 60.,
+            time_budget: None,
+            target_noise: None,
+            progress_interval: 10.,
+            adaptive: false,
+            min_samples: 4,
+            max_samples: 64,
+            noise_threshold: 0.05,
+            sample_heatmap: None,
+            width: None,
+            height: None,
+            camera: String::from("perspective"),
+            fov: None,
+            view_height: None,
+            sampler: String::from("independent"),
+            filter: String::from("box"),
+            filter_radius: None,
+            crop_window: None,
+            pixel_bounds: None,
+            cropped: false,
+            tile_order: String::from("scanline"),
+            tile_size: 16,
+            seed: 0,
+            normal_map: None,
+            environment: None,
+            environment_rotation: 0.,
+            environment_intensity: 1.,
+            lights: None,
+            light_sampler: String::from("bvh"),
+            sky: false,
+            sun_elevation: 45.,
+            sun_azimuth: 0.,
+            sun_size: 0.53,
+            turbidity: 3.,
+            ground_albedo: 0.3,
+        }
+    }
+}
+
+fn value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("missing value for `{}`", flag))
+}
+
+fn number<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<f32, String> {
+    let text = value(args, flag)?;
+    text.parse()
+        .map_err(|_| format!("invalid number `{}` for `{}`", text, flag))
+}
+
+/// Four numbers separated by commas, like `0.25,0.75,0,0.5`.
+fn four_numbers<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<[f32; 4], String> {
+    let text = value(args, flag)?;
+    let numbers: Vec<f32> = text
+        .split(',')
+        .map(|number| number.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid numbers `{}` for `{}`", text, flag))?;
+
+    match numbers[..] {
+        [a, b, c, d] => Ok([a, b, c, d]),
+        _ => Err(format!("`{}` needs four numbers, not `{}`", flag, text)),
+    }
+}
+
+impl Options {
+    pub fn from_args() -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut args = env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--scene" => options.scene = value(&mut args, &arg)?,
+                "--spectral" => options.spectral = true,
+                "--integrator" => options.integrator = value(&mut args, &arg)?,
+                "--photons" => options.photons = number(&mut args, &arg)? as usize,
+                "--photon-radius" => options.photon_radius = number(&mut args, &arg)?,
+                "--ao-distance" => options.ao_distance = number(&mut args, &arg)?,
+                "--aovs" => options.aovs = Some(value(&mut args, &arg)?),
+                "--denoise" => options.denoise = true,
+                "--samples" => options.samples = Some(number(&mut args, &arg)? as u32),
+                "--checkpoint" => options.checkpoint = Some(value(&mut args, &arg)?),
+                "--checkpoint-interval" => options.checkpoint_interval = number(&mut args, &arg)?,
+                "--time-budget" => options.time_budget = Some(number(&mut args, &arg)?),
+                "--target-noise" => options.target_noise = Some(number(&mut args, &arg)?),
+                "--progress-interval" => options.progress_interval = number(&mut args, &arg)?,
+                "--adaptive" => options.adaptive = true,
+                "--min-samples" => options.min_samples = number(&mut args, &arg)? as u32,
+                "--max-samples" => options.max_samples = number(&mut args, &arg)? as u32,
+                "--noise-threshold" => options.noise_threshold = number(&mut args, &arg)?,
+                "--sample-heatmap" => options.sample_heatmap = Some(value(&mut args, &arg)?),
+                "--width" => options.width = Some(number(&mut args, &arg)? as usize),
+                "--height" => options.height = Some(number(&mut args, &arg)? as usize),
+                "--camera" => options.camera = value(&mut args, &arg)?,
+                "--fov" => options.fov = Some(number(&mut args, &arg)?),
+                "--view-height" => options.view_height = Some(number(&mut args, &arg)?),
+                "--sampler" => options.sampler = value(&mut args, &arg)?,
+                "--filter" => options.filter = value(&mut args, &arg)?,
+                "--filter-radius" => options.filter_radius = Some(number(&mut args, &arg)?),
+                "--crop-window" => options.crop_window = Some(four_numbers(&mut args, &arg)?),
+                "--pixel-bounds" => {
+                    let bounds = four_numbers(&mut args, &arg)?;
+                    options.pixel_bounds = Some(bounds.map(|bound| bound.max(0.) as usize));
+                }
+                "--cropped" => options.cropped = true,
+                "--tile-order" => options.tile_order = value(&mut args, &arg)?,
+                "--tile-size" => options.tile_size = number(&mut args, &arg)? as usize,
+                "--seed" => {
+                    let text = value(&mut args, &arg)?;
+                    options.seed = text
+                        .parse()
+                        .map_err(|_| format!("invalid seed `{}` for `{}`", text, arg))?;
+                }
+                "--normal-map" => options.normal_map = Some(value(&mut args, &arg)?),
+                "--environment" => options.environment = Some(value(&mut args, &arg)?),
+                "--environment-rotation" => {
+                    options.environment_rotation = number(&mut args, &arg)?
+                }
+                "--environment-intensity" => {
+                    options.environment_intensity = number(&mut args, &arg)?
+                }
+                "--lights" => options.lights = Some(value(&mut args, &arg)?),
+                "--light-sampler" => options.light_sampler = value(&mut args, &arg)?,
+                "--sky" => options.sky = true,
+                "--sun-elevation" => options.sun_elevation = number(&mut args, &arg)?,
+                "--sun-azimuth" => options.sun_azimuth = number(&mut args, &arg)?,
+                "--sun-size" => options.sun_size = number(&mut args, &arg)?,
+                "--turbidity" => options.turbidity = number(&mut args, &arg)?,
+                "--ground-albedo" => options.ground_albedo = number(&mut args, &arg)?,
+                _ => return Err(format!("unknown option `{}`", arg)),
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// The options that change what the samples of a render come out as,
+    /// for telling whether a checkpoint belongs to it. Those that only say
+    /// when to stop or what else to write are left out, so that a render can
+    /// be resumed to take more samples.
+    pub fn fingerprint(&self) -> String {
+        let fields = [
+            format!("scene={:?}", self.scene),
+            format!("seed={}", self.seed),
+            format!("spectral={}", self.spectral),
+            format!("integrator={:?}", self.integrator),
+            format!("photons={}", self.photons),
+            format!("photon_radius={}", self.photon_radius),
+            format!("ao_distance={}", self.ao_distance),
+            format!("width={:?}", self.width),
+            format!("height={:?}", self.height),
+            format!("camera={:?}", self.camera),
+            format!("fov={:?}", self.fov),
+            format!("view_height={:?}", self.view_height),
+            format!("sampler={:?}", self.sampler),
+            format!("filter={:?}", self.filter),
+            format!("filter_radius={:?}", self.filter_radius),
+            format!("crop_window={:?}", self.crop_window),
+            format!("pixel_bounds={:?}", self.pixel_bounds),
+            format!("normal_map={:?}", self.normal_map),
+            format!("environment={:?}", self.environment),
+            format!("environment_rotation={}", self.environment_rotation),
+            format!("environment_intensity={}", self.environment_intensity),
+            format!("lights={:?}", self.lights),
+            format!("light_sampler={:?}", self.light_sampler),
+            format!("sky={}", self.sky),
+            format!("sun_elevation={}", self.sun_elevation),
+            format!("sun_azimuth={}", self.sun_azimuth),
+            format!("sun_size={}", self.sun_size),
+            format!("turbidity={}", self.turbidity),
+            format!("ground_albedo={}", self.ground_albedo),
+        ];
+
+        fields.join("\n")
+    }
+}
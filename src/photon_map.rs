@@ -0,0 +1,116 @@
+use crate::aabb::Aabb;
+use crate::vec3::Vec3;
+
+/// Light arriving at a surface, left behind by a photon traced from a light.
+#[derive(Copy, Clone, Debug)]
+pub struct Photon {
+    pub p: Vec3,
+    /// The unit direction back toward where the photon came from.
+    pub wi: Vec3,
+    pub power: Vec3,
+}
+
+/// A kd-tree over photons, for finding those near a point (Jensen 2001).
+///
+/// The tree is stored implicitly: the photon in the middle of each range
+/// splits it, along the axis recorded next to it.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        PhotonMap { photons, axes }
+    }
+
+    /// Calls `f` with every photon within `radius` of `p`.
+    pub fn for_each_within<F: FnMut(&Photon)>(&self, p: &Vec3, radius: f32, mut f: F) {
+        self.visit(0, self.photons.len(), p, radius * radius, &mut f);
+    }
+
+    fn visit<F: FnMut(&Photon)>(&self, start: usize, end: usize, p: &Vec3, radius2: f32, f: &mut F) {
+        if start >= end {
+            return;
+        }
+
+        let mid = (start + end) / 2;
+        let photon = &self.photons[mid];
+        if (photon.p - p).squared_length() <= radius2 {
+            f(photon);
+        }
+
+        let offset = p.axis(self.axes[mid]) - photon.p.axis(self.axes[mid]);
+        let (near, far) = if offset < 0. {
+            ((start, mid), (mid + 1, end))
+        } else {
+            ((mid + 1, end), (start, mid))
+        };
+
+        self.visit(near.0, near.1, p, radius2, f);
+        if offset * offset <= radius2 {
+            self.visit(far.0, far.1, p, radius2, f);
+        }
+    }
+}
+
+/// Puts the median photon along the widest axis in the middle, with those
+/// below it before and those above after, and recurses into each half.
+fn build(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.len() <= 1 {
+        return;
+    }
+
+    let bounds = photons
+        .iter()
+        .fold(Aabb::empty(), |bounds, photon| bounds.union(&Aabb::new(photon.p, photon.p)));
+    let axis = bounds.longest_axis();
+
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| a.p.axis(axis).total_cmp(&b.p.axis(axis)));
+    axes[mid] = axis;
+
+    let (below, above) = photons.split_at_mut(mid);
+    let (axes_below, axes_above) = axes.split_at_mut(mid);
+    build(below, axes_below);
+    build(&mut above[1..], &mut axes_above[1..]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_finds_same_photons_as_brute_force() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let photons: Vec<Photon> = (0..2000)
+            .map(|i| Photon {
+                p: vec3!(rng.gen::<f32>(), 0.2 * rng.gen::<f32>(), rng.gen::<f32>()),
+                wi: vec3!(0., 1., 0.),
+                power: vec3!(i as f32),
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone());
+
+        for _ in 0..50 {
+            let p = vec3!(rng.gen::<f32>(), 0.1, rng.gen::<f32>());
+            let radius = 0.1 * rng.gen::<f32>();
+
+            let mut found = vec![];
+            map.for_each_within(&p, radius, |photon| found.push(photon.power.x));
+            found.sort_by(f32::total_cmp);
+
+            let expected: Vec<f32> = photons
+                .iter()
+                .filter(|photon| (photon.p - p).length() <= radius)
+                .map(|photon| photon.power.x)
+                .collect();
+
+            assert_eq!(found, expected);
+        }
+    }
+}
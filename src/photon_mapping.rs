@@ -0,0 +1,387 @@
+use rand::{Rng, RngCore};
+use std::f32::consts::PI;
+
+use crate::aabb::Aabb;
+use crate::camera::Camera;
+use crate::distribution::power_heuristic;
+use crate::integrator::Integrator;
+use crate::light_sampler::{LightSampler, PowerLightSampler};
+use crate::material::luminance;
+use crate::objects::HitRecord;
+use crate::photon_map::{Photon, PhotonMap};
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+const MAX_DEPTH: i32 = 50;
+
+/// The fraction of new photons that progressive photon mapping keeps as it
+/// shrinks each pixel's radius (Hachisuka et al. 2008).
+const ALPHA: f32 = 2. / 3.;
+
+/// Where a camera path first reaches a surface that isn't purely specular,
+/// and so where light arriving from photons is gathered.
+struct VisiblePoint {
+    record: HitRecord,
+    ray_in: Ray,
+    /// The throughput of the camera path up to the point.
+    beta: Vec3,
+}
+
+impl VisiblePoint {
+    /// The radiance reflected toward the camera from the photons within
+    /// `radius`, times the number of photons traced per pass, and how many
+    /// photons there were.
+    fn gather(&self, map: &PhotonMap, radius: f32) -> (Vec3, usize) {
+        let mut sum = vec3!(0.);
+        let mut count = 0;
+
+        map.for_each_within(&self.record.p, radius, |photon| {
+            let cosine = self.record.normal.dot(&photon.wi).abs();
+            if cosine > 1e-4 {
+                let f = self.record.material.eval(&self.ray_in, &self.record, &photon.wi) / cosine;
+                sum += f * photon.power;
+                count += 1;
+            }
+        });
+
+        (sum, count)
+    }
+}
+
+/// Follows `ray` from the camera through specular surfaces to its visible
+/// point, returning the light it picks up on the way: what it sees emitted
+/// and the direct light at the visible point.
+fn trace_camera(ray: Ray, scene: &Scene, rng: &mut dyn RngCore) -> (Vec3, Option<VisiblePoint>) {
+    let mut ray = ray;
+    let mut beta = vec3!(1.);
+    let mut radiance = vec3!(0.);
+    let mut depth = 0;
+
+    while depth <= MAX_DEPTH {
+        let record = match scene.hit(&ray, 0.001, f32::MAX) {
+            Some(record) => record,
+            None => {
+                radiance += beta * scene.environment(&ray.direction().unit_vector());
+                break;
+            }
+        };
+
+        let opacity = record.material.opacity(&record);
+        if opacity < 1. && rng.gen::<f32>() >= opacity {
+            ray = Ray::new(record.p, *ray.direction());
+            continue;
+        }
+
+        radiance += beta * record.material.emitted(&ray, &record);
+
+        if !record.material.is_specular() {
+            radiance += beta * direct_light(&ray, &record, scene, rng);
+            return (radiance, Some(VisiblePoint { record, ray_in: ray, beta }));
+        }
+
+        let scatter = match record.material.scatter(&ray, &record, rng) {
+            Some(scatter) => scatter,
+            None => break,
+        };
+
+        beta *= scatter.attenuation;
+        ray = scatter.ray;
+        depth += 1;
+    }
+
+    (radiance, None)
+}
+
+/// Light arriving straight from a light, found both by sampling a light and
+/// by sampling the material, weighted with multiple importance sampling.
+/// Light that reaches the hit any other way is left to the photons.
+fn direct_light(ray: &Ray, record: &HitRecord, scene: &Scene, rng: &mut dyn RngCore) -> Vec3 {
+    let mut radiance = vec3!(0.);
+
+    if let Some((_, light, pick_pdf)) = scene.pick_light(&record.p, rng) {
+        if let Some(sample) = light.sample(&record.p, rng) {
+            let f = record.material.eval(ray, record, &sample.wi);
+            let light_pdf = pick_pdf * sample.pdf;
+
+            if f != vec3!(0.) && light_pdf > 0. {
+                let transmittance = scene.transmittance(&record.p, &sample.wi, sample.distance);
+                let weight = if light.is_delta() {
+                    1.
+                } else {
+                    power_heuristic(light_pdf, record.material.pdf(ray, record, &sample.wi))
+                };
+                radiance += f * sample.radiance * (transmittance * weight / light_pdf);
+            }
+        }
+    }
+
+    let scatter = match record.material.scatter(ray, record, rng) {
+        Some(scatter) => scatter,
+        None => return radiance,
+    };
+    let direction = scatter.ray.direction().unit_vector();
+    let scatter_pdf = if scatter.specular {
+        0.
+    } else {
+        record.material.pdf(ray, record, &direction)
+    };
+
+    let mut next = scatter.ray;
+    loop {
+        let hit = match scene.hit(&next, 0.001, f32::MAX) {
+            Some(hit) => hit,
+            None => {
+                let weight = if scatter_pdf > 0. {
+                    power_heuristic(scatter_pdf, scene.environment_pdf(&record.p, &direction))
+                } else {
+                    1.
+                };
+                return radiance + scatter.attenuation * scene.environment(&direction) * weight;
+            }
+        };
+
+        let opacity = hit.material.opacity(&hit);
+        if opacity < 1. && rng.gen::<f32>() >= opacity {
+            next = Ray::new(hit.p, direction);
+            continue;
+        }
+
+        let weight = match hit.light {
+            Some(index) if scatter_pdf > 0. => {
+                power_heuristic(scatter_pdf, scene.light_pdf(&record.p, &direction, index))
+            }
+            _ => 1.,
+        };
+        return radiance + scatter.attenuation * hit.material.emitted(&next, &hit) * weight;
+    }
+}
+
+/// Traces `count` photons from the lights, picked by power, and keeps those
+/// that arrive at surfaces that aren't purely specular after at least one
+/// bounce. Photons from lights at infinity are aimed at `bounds`.
+///
+/// Each photon's power is divided by `count`, so that the photons' power
+/// adds up to the light in the scene.
+fn trace_photons(scene: &Scene, bounds: &Aabb, count: usize, rng: &mut dyn RngCore) -> Vec<Photon> {
+    let sampler = PowerLightSampler::new(&scene.lights);
+    let mut photons = vec![];
+
+    for _ in 0..count {
+        let (index, pmf) = match sampler.pick(&bounds.center(), rng.gen()) {
+            Some(picked) => picked,
+            None => break,
+        };
+        let sample = match scene.lights[index].sample_emission(bounds, rng) {
+            Some(sample) if sample.pdf_position > 0. && sample.pdf_direction > 0. => sample,
+            _ => continue,
+        };
+
+        let cosine = sample.normal.dot(&sample.ray.direction().unit_vector()).abs();
+        let mut power = sample.radiance * cosine / (pmf * sample.pdf_position * sample.pdf_direction * count as f32);
+        let mut ray = sample.ray;
+        let mut depth = 0;
+
+        while depth <= MAX_DEPTH {
+            let record = match scene.hit(&ray, 0.001, f32::MAX) {
+                Some(record) => record,
+                None => break,
+            };
+
+            let opacity = record.material.opacity(&record);
+            if opacity < 1. && rng.gen::<f32>() >= opacity {
+                ray = Ray::new(record.p, *ray.direction());
+                continue;
+            }
+
+            if depth > 0 && !record.material.is_specular() {
+                photons.push(Photon {
+                    p: record.p,
+                    wi: -ray.direction().unit_vector(),
+                    power,
+                });
+            }
+
+            let scatter = match record.material.scatter(&ray, &record, rng) {
+                Some(scatter) => scatter,
+                None => break,
+            };
+
+            // Russian roulette, keeping photons in proportion to how much
+            // the bounce lets through.
+            let mut attenuation = scatter.attenuation;
+            if depth >= 3 {
+                let survival = luminance(&attenuation).clamp(0.05, 0.95);
+                if rng.gen::<f32>() >= survival {
+                    break;
+                }
+                attenuation /= survival;
+            }
+
+            power *= attenuation;
+            ray = scatter.ray;
+            depth += 1;
+        }
+    }
+
+    photons
+}
+
+/// The bounds of the visible points nearest the camera, leaving out the
+/// farthest tenth so that a distant horizon doesn't spread the photons from
+/// lights at infinity too thinly.
+fn visible_bounds(points: &[&VisiblePoint], origin: &Vec3) -> Aabb {
+    let mut distances: Vec<f32> = points.iter().map(|point| (point.record.p - origin).length()).collect();
+    if distances.is_empty() {
+        return Aabb::new(vec3!(-1.), vec3!(1.));
+    }
+
+    let nth = distances.len() * 9 / 10;
+    let (_, cutoff, _) = distances.select_nth_unstable_by(nth, f32::total_cmp);
+    let cutoff = *cutoff;
+
+    points
+        .iter()
+        .filter(|point| (point.record.p - origin).length() <= cutoff)
+        .fold(Aabb::empty(), |bounds, point| bounds.union(&Aabb::new(point.record.p, point.record.p)))
+}
+
+/// Photon mapping (Jensen 1996): a photon map is traced once up front, and
+/// every camera path estimates the light reflected at its visible point from
+/// the density of photons within a fixed radius. Caustics and indirect light
+/// come out smooth, but blurred by the radius.
+pub struct PhotonMapping {
+    map: PhotonMap,
+    radius: f32,
+}
+
+impl PhotonMapping {
+    pub fn new(scene: &Scene, camera: &dyn Camera, photons: usize, radius: f32, rng: &mut dyn RngCore) -> PhotonMapping {
+        // Find where the camera looks, to aim the photons from lights at
+        // infinity.
+        let (size, origin) = (64, camera.origin());
+        let points: Vec<VisiblePoint> = (0..size * size)
+            .filter_map(|i| {
+                let (u, v) = ((i % size) as f32 + 0.5, (i / size) as f32 + 0.5);
+                let ray = camera.get_ray(u / size as f32, v / size as f32, rng)?;
+                trace_camera(ray, scene, rng).1
+            })
+            .collect();
+        let bounds = visible_bounds(&points.iter().collect::<Vec<_>>(), &origin);
+
+        PhotonMapping {
+            map: PhotonMap::new(trace_photons(scene, &bounds, photons, rng)),
+            radius,
+        }
+    }
+}
+
+impl Integrator for PhotonMapping {
+    fn color(&self, ray: Ray, scene: &Scene, rng: &mut dyn RngCore) -> Vec3 {
+        let (mut radiance, point) = trace_camera(ray, scene, rng);
+
+        if let Some(point) = point {
+            let (sum, _) = point.gather(&self.map, self.radius);
+            radiance += point.beta * sum / (PI * self.radius * self.radius);
+        }
+
+        radiance
+    }
+}
+
+/// The statistics stochastic progressive photon mapping keeps per pixel.
+struct PixelStatistics {
+    radius: f32,
+    /// The number of photons kept so far.
+    photons: f32,
+    /// The reflected photon power gathered within `radius`.
+    tau: Vec3,
+    /// The sum of the light picked up directly by every pass.
+    direct: Vec3,
+}
+
+/// Stochastic progressive photon mapping (Hachisuka and Jensen 2009). Each
+/// pass traces a new camera ray through every pixel and a new photon map,
+/// and shrinks each pixel's gather radius as photons accumulate, so that the
+/// blur of plain photon mapping vanishes as passes are added.
+pub struct Sppm {
+    photons: usize,
+    radius: f32,
+}
+
+impl Sppm {
+    /// `photons` are traced per pass, and `radius` is where every pixel's
+    /// gather radius starts.
+    pub fn new(photons: usize, radius: f32) -> Sppm {
+        Sppm { photons, radius }
+    }
+
+    /// Renders `passes` passes, returning the pixels a row at a time from
+    /// the top.
+    pub fn render(
+        &self,
+        scene: &Scene,
+        camera: &dyn Camera,
+        width: usize,
+        height: usize,
+        passes: usize,
+        rng: &mut dyn RngCore,
+    ) -> Vec<Vec3> {
+        let mut pixels: Vec<PixelStatistics> = (0..width * height)
+            .map(|_| PixelStatistics {
+                radius: self.radius,
+                photons: 0.,
+                tau: vec3!(0.),
+                direct: vec3!(0.),
+            })
+            .collect();
+
+        for _ in 0..passes {
+            let mut points = Vec::with_capacity(pixels.len());
+            for (index, pixel) in pixels.iter_mut().enumerate() {
+                let (i, j) = (index % width, height - 1 - index / width);
+                let u = (i as f32 + rng.gen::<f32>()) / width as f32;
+                let v = (j as f32 + rng.gen::<f32>()) / height as f32;
+
+                let (direct, point) = match camera.get_ray(u, v, rng) {
+                    Some(ray) => trace_camera(ray, scene, rng),
+                    None => (vec3!(0.), None),
+                };
+                pixel.direct += direct;
+                points.push(point);
+            }
+
+            let bounds = visible_bounds(&points.iter().flatten().collect::<Vec<_>>(), &camera.origin());
+            let map = PhotonMap::new(trace_photons(scene, &bounds, self.photons, rng));
+
+            for (pixel, point) in pixels.iter_mut().zip(points.iter()) {
+                let point = match point {
+                    Some(point) => point,
+                    None => continue,
+                };
+
+                let (sum, count) = point.gather(&map, pixel.radius);
+                if count == 0 {
+                    continue;
+                }
+
+                let photons = pixel.photons + ALPHA * count as f32;
+                let radius = pixel.radius * (photons / (pixel.photons + count as f32)).sqrt();
+                let shrink = (radius / pixel.radius) * (radius / pixel.radius);
+
+                pixel.tau = (pixel.tau + point.beta * sum) * shrink;
+                pixel.photons = photons;
+                pixel.radius = radius;
+            }
+        }
+
+        let passes = passes.max(1) as f32;
+        pixels
+            .iter()
+            .map(|pixel| {
+                let indirect = pixel.tau / (passes * PI * pixel.radius * pixel.radius);
+                pixel.direct / passes + indirect
+            })
+            .collect()
+    }
+}
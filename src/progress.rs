@@ -0,0 +1,136 @@
+use std::time::{Duration, Instant};
+
+use crate::adaptive::Welford;
+
+/// How far a render has got: samples per pixel, and how noisy the image
+/// is, as the mean of the relative standard error of its pixels.
+fn measure(pixels: &[Welford]) -> (f32, f32) {
+    let samples: u64 = pixels.iter().map(|pixel| u64::from(pixel.count())).sum();
+    let noise: f32 = pixels.iter().map(Welford::relative_noise).sum();
+    let n = pixels.len().max(1) as f32;
+    (samples as f32 / n, noise / n)
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, s) => format!("{}h {:02}m {:02}s", h, m, s),
+    }
+}
+
+/// Decides when a progressive render stops, which is when it reaches the
+/// first of its targets, and logs how it is getting on.
+pub struct Progress {
+    start: Instant,
+    last_log: Instant,
+    log_interval: Duration,
+    samples: Option<u32>,
+    time_budget: Option<Duration>,
+    /// The relative noise to stop at.
+    target_noise: Option<f32>,
+}
+
+impl Progress {
+    /// Starts timing a render that stops after `samples` samples per pixel,
+    /// `minutes` minutes or once its noise is below `target_noise`, logging
+    /// every `log_interval` seconds.
+    pub fn new(samples: Option<u32>, minutes: Option<f32>, target_noise: Option<f32>, log_interval: f32) -> Progress {
+        let start = Instant::now();
+        Progress {
+            start,
+            last_log: start,
+            log_interval: Duration::from_secs_f32(log_interval),
+            samples,
+            time_budget: minutes.map(|minutes| Duration::from_secs_f32(60. * minutes)),
+            target_noise,
+        }
+    }
+
+    pub fn out_of_time(&self) -> bool {
+        self.time_budget.is_some_and(|budget| self.start.elapsed() >= budget)
+    }
+
+    /// Whether the render is done, other than by reaching its samples per
+    /// pixel, which the pixels are sampled up to anyway.
+    pub fn is_done(&self, pixels: &[Welford]) -> bool {
+        self.out_of_time() || self.target_noise.is_some_and(|target| measure(pixels).1 <= target)
+    }
+
+    /// How long until the first target is reached, guessing that the noise
+    /// falls with the square root of the samples.
+    fn remaining(&self, spp: f32, noise: f32) -> Option<Duration> {
+        let elapsed = self.start.elapsed().as_secs_f32();
+        if spp <= 0. {
+            return None;
+        }
+
+        let per_sample = elapsed / spp;
+        let estimates = [
+            self.samples.map(|samples| (samples as f32 - spp) * per_sample),
+            self.time_budget.map(|budget| budget.as_secs_f32() - elapsed),
+            self.target_noise
+                .filter(|_| noise.is_finite())
+                .map(|target| (spp * (noise / target).powi(2) - spp) * per_sample),
+        ];
+
+        let least = estimates.iter().flatten().copied().fold(f32::INFINITY, f32::min);
+        if least.is_finite() {
+            Some(Duration::from_secs_f32(least.max(0.)))
+        } else {
+            None
+        }
+    }
+
+    /// Logs progress to stderr if it has been long enough since last time.
+    pub fn log_if_due(&mut self, pixels: &[Welford]) {
+        if self.last_log.elapsed() >= self.log_interval {
+            self.log(pixels);
+        }
+    }
+
+    pub fn log(&mut self, pixels: &[Welford]) {
+        let (spp, noise) = measure(pixels);
+        let remaining = match self.remaining(spp, noise) {
+            Some(remaining) => format!(", about {} left", format_duration(remaining)),
+            None => String::new(),
+        };
+        let noise = if noise.is_finite() {
+            format!("{:.2}%", 100. * noise)
+        } else {
+            String::from("unknown")
+        };
+
+        eprintln!(
+            "{} elapsed, {:.1} spp, noise {}{}",
+            format_duration(self.start.elapsed()),
+            spp,
+            noise,
+            remaining
+        );
+        self.last_log = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn test_stops_at_target_noise() {
+        let mut pixels = vec![Welford::default(); 4];
+        let progress = Progress::new(None, None, Some(0.105), 10.);
+        assert!(!progress.is_done(&pixels));
+
+        // Samples alternating between 0.5 and 1.5 have a relative standard
+        // deviation of about 0.5, so their mean's falls below 0.105 at 24.
+        for n in 1..=26 {
+            pixels.iter_mut().for_each(|pixel| pixel.add(vec3!(if n % 2 == 1 { 0.5 } else { 1.5 })));
+            assert_eq!(progress.is_done(&pixels), n >= 24, "after {} samples", n);
+        }
+
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1h 02m 05s");
+    }
+}
@@ -1,8 +1,10 @@
 use crate::vec3::Vec3;
 
+#[derive(Copy, Clone)]
 pub struct Ray {
     a: Vec3,
-    b: Vec3
+    b: Vec3,
+    wavelength: Option<f32>,
 }
 
 impl Ray {
@@ -14,14 +16,24 @@ impl Ray {
         &self.b
     }
 
+    /// The hero wavelength in nanometres when rendering spectrally.
+    pub fn wavelength(&self) -> Option<f32> {
+        self.wavelength
+    }
+
     pub fn point_at_parameter(&self, t: f32) -> Vec3 {
-        &self.a + t * &self.b
+        self.a + t * self.b
     }
 
     pub fn new(a: Vec3, b: Vec3) -> Ray { 
         Ray {
             a,
-            b
+            b,
+            wavelength: None,
         }
     }
+
+    pub fn with_wavelength(self, wavelength: Option<f32>) -> Ray {
+        Ray { wavelength, ..self }
+    }
 }
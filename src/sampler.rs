@@ -0,0 +1,480 @@
+use rand::{Error, Rng, RngCore};
+use rand_pcg::Pcg32;
+
+/// Dimensions of a Halton sample past this many are independent random
+/// numbers, as the sequence's later bases are too large to help.
+const HALTON_DIMENSIONS: usize = 64;
+
+/// The width and height of the blue noise tile.
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// How widely each point of the blue noise tile pushes the next away.
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+
+/// Hands out the random numbers of each sample through a pixel, one
+/// dimension at a time. Dimensions `2k` and `2k + 1` are a pair, which
+/// samplers that can stratify together.
+pub trait Sampler {
+    /// Starts the `index`th sample through the pixel `x` across and `y` up.
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u32);
+
+    /// The next dimension of the sample, in `[0, 1)`.
+    fn get_1d(&mut self) -> f32;
+
+    /// The next two dimensions of the sample.
+    fn get_2d(&mut self) -> (f32, f32) {
+        let u = self.get_1d();
+        (u, self.get_1d())
+    }
+}
+
+/// Lets code that wants random numbers draw them from a sampler, one
+/// dimension for every 32 bits.
+pub struct SamplerRng<'a>(pub &'a mut dyn Sampler);
+
+impl RngCore for SamplerRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        (f64::from(self.0.get_1d()) * 4_294_967_296.) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        u64::from(self.next_u32()) << 32 | u64::from(self.next_u32())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// The SplitMix64 finalizer, which scatters nearby values across the whole
+/// range.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0, |h, value| mix(h ^ value.wrapping_add(0x9e37_79b9_7f4a_7c15)))
+}
+
+/// The top 24 bits of `bits` as a number in `[0, 1)`.
+fn to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Where a sampler is up to: the pixel sample, the next dimension, and
+/// random numbers of its own for whatever the sampler doesn't cover. Every
+/// pixel sample has a PCG stream of its own, so its numbers don't depend on
+/// which samples were taken before it, or on which thread takes it.
+struct PixelSample {
+    seed: u64,
+    x: usize,
+    y: usize,
+    index: u32,
+    dimension: u32,
+    rng: Pcg32,
+}
+
+impl PixelSample {
+    fn new(seed: u64) -> PixelSample {
+        PixelSample {
+            seed,
+            x: 0,
+            y: 0,
+            index: 0,
+            dimension: 0,
+            rng: Pcg32::new(seed, 0),
+        }
+    }
+
+    fn start(&mut self, x: usize, y: usize, index: u32) {
+        self.x = x;
+        self.y = y;
+        self.index = index;
+        self.dimension = 0;
+        self.rng = Pcg32::new(self.hash(&[u64::from(index)]), u64::from(index));
+    }
+
+    fn next_dimension(&mut self) -> u32 {
+        self.dimension += 1;
+        self.dimension - 1
+    }
+
+    /// A hash of the seed, the pixel and `values`, the same every time.
+    fn hash(&self, values: &[u64]) -> u64 {
+        hash(&[self.seed, self.x as u64, self.y as u64, hash(values)])
+    }
+}
+
+/// Independent uniform random numbers for every dimension.
+pub struct Independent {
+    sample: PixelSample,
+}
+
+impl Independent {
+    pub fn new(seed: u64) -> Independent {
+        Independent {
+            sample: PixelSample::new(seed),
+        }
+    }
+}
+
+impl Sampler for Independent {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u32) {
+        self.sample.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.sample.rng.gen()
+    }
+}
+
+/// Splits each pair of dimensions into a grid of strata, one for each of
+/// the pixel's samples, and jitters a point within each. The strata are
+/// visited in a different order for each pair, so that pairs aren't
+/// correlated with each other.
+pub struct Stratified {
+    x_strata: u32,
+    y_strata: u32,
+    sample: PixelSample,
+}
+
+impl Stratified {
+    /// Stratifies `samples` samples per pixel. Samples past that many are
+    /// spread over the strata again.
+    pub fn new(samples: u32, seed: u64) -> Stratified {
+        // Keeps the number of strata within a `u32` for open ended renders.
+        let samples = samples.clamp(1, 1 << 30);
+        let x_strata = (samples as f32).sqrt().ceil() as u32;
+        let y_strata = samples.div_ceil(x_strata);
+
+        Stratified {
+            x_strata,
+            y_strata,
+            sample: PixelSample::new(seed),
+        }
+    }
+}
+
+impl Sampler for Stratified {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u32) {
+        self.sample.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.sample.next_dimension();
+        let strata = self.x_strata * self.y_strata;
+        let order = self.sample.hash(&[u64::from(dimension / 2)]) as u32;
+        let stratum = permutation_element(self.sample.index % strata, strata, order);
+
+        let jitter = to_unit(self.sample.hash(&[u64::from(self.sample.index), u64::from(dimension)]) as u32);
+        if dimension.is_multiple_of(2) {
+            ((stratum % self.x_strata) as f32 + jitter) / self.x_strata as f32
+        } else {
+            ((stratum / self.x_strata) as f32 + jitter) / self.y_strata as f32
+        }
+    }
+}
+
+/// The element at `index` of a random permutation of `0..length` picked by
+/// `seed` (Kensler 2013).
+fn permutation_element(mut index: u32, length: u32, seed: u32) -> u32 {
+    let mut mask = length - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170_893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929_eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | seed >> 27);
+        index = index.wrapping_mul(0x6935_fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dc_b303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e50_1cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860_a3df);
+        index &= mask;
+        index ^= index >> 5;
+
+        if index < length {
+            return (index.wrapping_add(seed)) % length;
+        }
+    }
+}
+
+/// The Halton sequence, counting in a different prime base for each
+/// dimension, with the digits of every pixel scrambled differently so that
+/// neighbouring pixels don't share their patterns.
+pub struct Halton {
+    primes: Vec<u32>,
+    sample: PixelSample,
+}
+
+impl Halton {
+    pub fn new(seed: u64) -> Halton {
+        let mut primes = Vec::with_capacity(HALTON_DIMENSIONS);
+        let mut candidate = 2;
+        while primes.len() < HALTON_DIMENSIONS {
+            if primes.iter().all(|prime| candidate % prime != 0) {
+                primes.push(candidate);
+            }
+            candidate += 1;
+        }
+
+        Halton {
+            primes,
+            sample: PixelSample::new(seed),
+        }
+    }
+}
+
+impl Sampler for Halton {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u32) {
+        self.sample.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.sample.next_dimension();
+        let base = match self.primes.get(dimension as usize) {
+            Some(base) => *base,
+            None => return self.sample.rng.gen(),
+        };
+
+        // Shifts every digit, including the zeros past the last, by an
+        // amount of its own.
+        let (mut index, mut value, mut scale) = (self.sample.index, 0., 1.);
+        let mut digit = 0;
+        while scale > 1e-7 {
+            scale /= base as f64;
+            let shift = self.sample.hash(&[u64::from(dimension), digit]) % u64::from(base);
+            value += ((u64::from(index % base) + shift) % u64::from(base)) as f64 * scale;
+            index /= base;
+            digit += 1;
+        }
+
+        (value as f32).min(1. - f32::EPSILON)
+    }
+}
+
+/// The first two dimensions of the Sobol sequence.
+fn sobol(index: u32, dimension: u32) -> u32 {
+    if dimension == 0 {
+        return index.reverse_bits();
+    }
+
+    let (mut value, mut direction, mut index) = (0, 1 << 31, index);
+    while index != 0 {
+        if index & 1 != 0 {
+            value ^= direction;
+        }
+        direction ^= direction >> 1;
+        index >>= 1;
+    }
+
+    value
+}
+
+/// Owen scrambles the bits of `x`, from the top down (Burley 2020).
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+/// The Sobol sequence with Owen scrambling. Only its first two dimensions
+/// are used, for every pair of dimensions, with the order of the points
+/// shuffled differently for each pair so that pairs aren't correlated
+/// (Burley 2020).
+pub struct Sobol {
+    sample: PixelSample,
+}
+
+impl Sobol {
+    pub fn new(seed: u64) -> Sobol {
+        Sobol {
+            sample: PixelSample::new(seed),
+        }
+    }
+}
+
+/// The `index`th point of `dimension` of the shuffled, scrambled Sobol
+/// sequence seeded by `hash`.
+fn padded_sobol(index: u32, dimension: u32, hash: impl Fn(&[u64]) -> u64) -> f32 {
+    let shuffled = nested_uniform_scramble(index, hash(&[u64::from(dimension / 2)]) as u32);
+    let bits = sobol(shuffled, dimension % 2);
+    to_unit(nested_uniform_scramble(bits, hash(&[u64::from(dimension), 1]) as u32))
+}
+
+impl Sampler for Sobol {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u32) {
+        self.sample.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.sample.next_dimension();
+        let sample = &self.sample;
+        padded_sobol(sample.index, dimension, |values| sample.hash(values))
+    }
+}
+
+/// Every pixel takes the same scrambled Sobol points, each shifted by a
+/// blue noise tile laid over the image (Heitz and Belcour 2019). The error
+/// left in neighbouring pixels differs as much as it can, so the noise is
+/// fine grained and less visible for its amount.
+pub struct BlueNoise {
+    seed: u64,
+    tile: Vec<f32>,
+    sample: PixelSample,
+}
+
+impl BlueNoise {
+    pub fn new(seed: u64) -> BlueNoise {
+        BlueNoise {
+            seed,
+            tile: blue_noise(BLUE_NOISE_SIZE, &mut Pcg32::new(seed, 0)),
+            sample: PixelSample::new(seed),
+        }
+    }
+}
+
+impl Sampler for BlueNoise {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u32) {
+        self.sample.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.sample.next_dimension();
+        let seed = self.seed;
+        let value = padded_sobol(self.sample.index, dimension, |values| hash(&[seed, hash(values)]));
+
+        // Each dimension is shifted by the tile moved somewhere else.
+        let offset = hash(&[seed, u64::from(dimension), 2]);
+        let size = BLUE_NOISE_SIZE as u64;
+        let x = (self.sample.x + ((offset & 0xffff_ffff) % size) as usize) % BLUE_NOISE_SIZE;
+        let y = (self.sample.y + ((offset >> 32) % size) as usize) % BLUE_NOISE_SIZE;
+        let shifted = value + self.tile[y * BLUE_NOISE_SIZE + x];
+
+        if shifted >= 1. {
+            (shifted - 1.).min(1. - f32::EPSILON)
+        } else {
+            shifted
+        }
+    }
+}
+
+/// A `size` by `size` tile of blue noise that wraps around, with every
+/// value in `[0, 1)` once. Each value goes in the emptiest place left, as
+/// in the last phase of void and cluster (Ulichney 1993).
+fn blue_noise(size: usize, rng: &mut dyn RngCore) -> Vec<f32> {
+    let wrap = |d: usize| d.min(size - d) as f32;
+    let kernel: Vec<f32> = (0..size * size)
+        .map(|i| {
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2. * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp()
+        })
+        .collect();
+
+    // A little noise to break ties, which would otherwise fill in rows.
+    let mut energy: Vec<f32> = (0..size * size).map(|_| 1e-3 * rng.gen::<f32>()).collect();
+    let mut tile = vec![-1.; size * size];
+
+    for rank in 0..size * size {
+        let void = (0..size * size)
+            .filter(|i| tile[*i] < 0.)
+            .min_by(|a, b| energy[*a].partial_cmp(&energy[*b]).unwrap())
+            .unwrap();
+        tile[void] = (rank as f32 + 0.5) / (size * size) as f32;
+
+        let (vx, vy) = (void % size, void / size);
+        for y in 0..size {
+            for x in 0..size {
+                let d = (y + size - vy) % size * size + (x + size - vx) % size;
+                energy[y * size + x] += kernel[d];
+            }
+        }
+    }
+
+    tile
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_samplers_converge_faster_than_independent() {
+        let samples = 64;
+
+        // The fraction of each pixel a disc covers, like an edge in an image.
+        let disc = |(u, v): (f32, f32)| if (u - 0.5).powi(2) + (v - 0.5).powi(2) < 0.16 { 1. } else { 0. };
+        let area = std::f32::consts::PI * 0.16;
+
+        let error = |sampler: &mut dyn Sampler| {
+            let mut squares = 0.;
+            for pixel in 0..64 {
+                let mut sum = 0.;
+                for index in 0..samples {
+                    sampler.start_pixel_sample(pixel % 8, pixel / 8, index);
+                    // Skips a pair, so the sequences' later dimensions are tested.
+                    sampler.get_2d();
+                    sum += disc(sampler.get_2d());
+                }
+                squares += (sum / samples as f32 - area).powi(2);
+            }
+            (squares / 64.).sqrt()
+        };
+
+        let independent = error(&mut Independent::new(1));
+        let samplers: Vec<(&str, Box<dyn Sampler>)> = vec![
+            ("stratified", Box::new(Stratified::new(samples, 1))),
+            ("halton", Box::new(Halton::new(1))),
+            ("sobol", Box::new(Sobol::new(1))),
+            ("blue noise", Box::new(BlueNoise::new(1))),
+        ];
+
+        for (name, mut sampler) in samplers {
+            let error = error(sampler.as_mut());
+            assert!(error < 0.5 * independent, "{}: {} vs {}", name, error, independent);
+        }
+    }
+
+    #[test]
+    fn test_samples_ignore_render_order() {
+        let mut sampler = Sobol::new(7);
+        let mut take = |x: usize, y: usize, index: u32| {
+            sampler.start_pixel_sample(x, y, index);
+            (0..200).map(|_| sampler.get_1d()).collect::<Vec<f32>>()
+        };
+
+        let first = take(3, 5, 2);
+        take(0, 0, 0);
+        take(3, 5, 1);
+        assert_eq!(first, take(3, 5, 2));
+        assert_ne!(first, take(3, 5, 3));
+    }
+}
@@ -0,0 +1,104 @@
+use rand::{Rng, RngCore};
+use std::rc::Rc;
+
+use crate::light::{AreaLight, Light};
+use crate::light_sampler::LightSampler;
+use crate::material::{DiffuseLight, Material};
+use crate::objects::{Emitter, HitRecord, Hittable, HittableList, Shape};
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// The geometry to render along with the lights that can be sampled
+/// directly.
+pub struct Scene {
+    pub world: Box<dyn Hittable>,
+    pub lights: Vec<Box<dyn Light>>,
+    sampler: Box<dyn LightSampler>,
+}
+
+/// Adds a shape that glows with `emission` to `objects`, along with the
+/// light that samples it. `shape` builds the shape from its material.
+pub fn add_area_light<S, F>(objects: &mut HittableList, lights: &mut Vec<Box<dyn Light>>, emission: Vec3, shape: F)
+where
+    S: Shape + Clone + 'static,
+    F: FnOnce(Rc<dyn Material>) -> S,
+{
+    let shape = shape(Rc::new(DiffuseLight::new(emission)));
+
+    objects.add(Box::new(Emitter::new(shape.clone(), lights.len())));
+    lights.push(Box::new(AreaLight::new(Box::new(shape), emission)));
+}
+
+impl Scene {
+    /// `sampler` picks between `lights` for next event estimation.
+    pub fn new(
+        world: Box<dyn Hittable>,
+        lights: Vec<Box<dyn Light>>,
+        sampler: Box<dyn LightSampler>,
+    ) -> Scene {
+        Scene {
+            world,
+            lights,
+            sampler,
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.world.hit(ray, t_min, t_max)
+    }
+
+    /// How many bounding boxes and objects finding the hit along `ray`
+    /// takes.
+    pub fn hit_cost(&self, ray: &Ray, t_min: f32, t_max: f32) -> usize {
+        self.world.hit_cost(ray, t_min, t_max)
+    }
+
+    /// The radiance arriving along rays that leave the scene.
+    pub fn environment(&self, direction: &Vec3) -> Vec3 {
+        self.lights
+            .iter()
+            .fold(vec3!(0.), |sum, light| sum + light.environment(direction))
+    }
+
+    /// Picks one of the lights to sample from `p`, returning its index and
+    /// the light with the probability of picking it.
+    pub fn pick_light(&self, p: &Vec3, rng: &mut dyn RngCore) -> Option<(usize, &dyn Light, f32)> {
+        let (index, pmf) = self.sampler.pick(p, rng.gen())?;
+        Some((index, self.lights[index].as_ref(), pmf))
+    }
+
+    /// The solid angle density with which picking and then sampling a light
+    /// from `p` would choose the light at `index` in the unit direction `wi`.
+    pub fn light_pdf(&self, p: &Vec3, wi: &Vec3, index: usize) -> f32 {
+        self.sampler.pmf(p, index) * self.lights[index].pdf(p, wi)
+    }
+
+    /// Like `light_pdf`, but for escaping the scene in the unit direction
+    /// `wi`, summed over the lights at infinity.
+    pub fn environment_pdf(&self, p: &Vec3, wi: &Vec3) -> f32 {
+        (0..self.lights.len())
+            .filter(|&i| self.lights[i].is_infinite())
+            .map(|i| self.light_pdf(p, wi, i))
+            .sum()
+    }
+
+    /// The fraction of light that makes it `distance` along `direction` from
+    /// `origin`, letting it through partially opaque surfaces.
+    pub fn transmittance(&self, origin: &Vec3, direction: &Vec3, distance: f32) -> f32 {
+        let mut ray = Ray::new(*origin, *direction);
+        let mut remaining = distance * (1. - 1e-3);
+        let mut transmittance = 1.;
+
+        while let Some(record) = self.hit(&ray, 0.001, remaining) {
+            transmittance *= 1. - record.material.opacity(&record);
+            if transmittance <= 0. {
+                return 0.;
+            }
+
+            remaining -= record.t;
+            ray = Ray::new(record.p, *direction);
+        }
+
+        transmittance
+    }
+}
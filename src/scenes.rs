@@ -0,0 +1,493 @@
+use rand::{Rng, RngCore};
+use std::rc::Rc;
+
+use crate::image::Image;
+use crate::light::{EnvironmentLight, Light};
+use crate::material::{
+    AlphaMasked, BumpMapped, Coated, Dielectric, Lambertian, Material, Metal, Mix, NormalMapped,
+    Principled,
+};
+use crate::objects::{HittableList, Quad, Sphere, Triangle};
+use crate::scene::add_area_light;
+use crate::texture::{Checker, ImageTexture, SolidColor};
+use crate::vec3::Vec3;
+
+/// A row of spheres showing off the lobes of the `Principled` material.
+pub fn principled() -> HittableList {
+    let mut list = HittableList::new();
+
+    list.add(Box::new(Sphere::new(
+        vec3!(0., -1000., 0.),
+        1000.,
+        Rc::new(Lambertian::new(vec3!(0.5, 0.5, 0.5))),
+    )));
+
+    let materials = vec![
+        Principled {
+            roughness: 0.8,
+            sheen: 1.,
+            ..Principled::new(vec3!(0.8, 0.1, 0.1))
+        },
+        Principled {
+            metallic: 1.,
+            roughness: 0.3,
+            anisotropic: 0.8,
+            ..Principled::new(vec3!(0.9, 0.6, 0.3))
+        },
+        Principled {
+            roughness: 0.6,
+            clearcoat: 1.,
+            ..Principled::new(vec3!(0.1, 0.2, 0.6))
+        },
+        Principled {
+            roughness: 0.05,
+            transmission: 1.,
+            ..Principled::new(vec3!(0.9, 1., 0.9))
+        },
+        Principled {
+            roughness: 0.4,
+            subsurface: 1.,
+            specular_tint: 0.5,
+            ..Principled::new(vec3!(0.9, 0.8, 0.6))
+        },
+    ];
+
+    for (i, material) in materials.into_iter().enumerate() {
+        let z = 1.5 * (i as f32 - 2.);
+        list.add(Box::new(Sphere::new(vec3!(0., 0.6, z), 0.6, Rc::new(material))));
+    }
+
+    list
+}
+
+/// Dispersive glass in front of a row of diffuse spheres. Render with
+/// `--spectral` to see the colour fringes.
+pub fn dispersion() -> HittableList {
+    let mut list = HittableList::new();
+
+    list.add(Box::new(Sphere::new(
+        vec3!(0., -1000., 0.),
+        1000.,
+        Rc::new(Lambertian::new(vec3!(0.5, 0.5, 0.5))),
+    )));
+
+    // Schott SF11 dense flint glass.
+    list.add(Box::new(Sphere::new(
+        vec3!(3., 1., 0.),
+        1.,
+        Rc::new(Dielectric::sellmeier(
+            [1.737_597, 0.313_747, 1.898_781],
+            [0.013_188_7, 0.062_306_8, 155.236_3],
+        )),
+    )));
+
+    // A crown glass described by its Cauchy coefficients.
+    list.add(Box::new(Sphere::new(
+        vec3!(3., 0.5, -2.2),
+        0.5,
+        Rc::new(Dielectric::cauchy(1.5046, 0.004_20)),
+    )));
+
+    for i in 0..6 {
+        let z = 1.2 * (i as f32 - 2.5);
+        let albedo = vec3!(
+            0.5 + 0.5 * (i as f32).cos(),
+            0.5 + 0.5 * (i as f32 + 2.).cos(),
+            0.5 + 0.5 * (i as f32 + 4.).cos()
+        );
+        list.add(Box::new(Sphere::new(vec3!(-2., 0.5, z), 0.5, Rc::new(Lambertian::new(albedo)))));
+    }
+
+    list
+}
+
+/// Tinted glass of increasing thickness, showing the colour deepen with the
+/// distance travelled through the medium.
+pub fn absorption() -> HittableList {
+    let mut list = HittableList::new();
+
+    list.add(Box::new(Sphere::new(
+        vec3!(0., -1000., 0.),
+        1000.,
+        Rc::new(Lambertian::new(vec3!(0.5, 0.5, 0.5))),
+    )));
+
+    for (i, z) in [-3., -1.95, -0.4, 1.65].iter().enumerate() {
+        let radius = 0.25 * (i + 1) as f32;
+        list.add(Box::new(Sphere::new(
+            vec3!(0., radius, *z),
+            radius,
+            Rc::new(Dielectric::new(1.5).with_transmission_color(vec3!(0.2, 0.7, 0.4), 1.)),
+        )));
+    }
+
+    list
+}
+
+/// Composite materials: car paint, varnished wood and a textured blend of
+/// metal and plastic.
+pub fn layered() -> HittableList {
+    let mut list = HittableList::new();
+
+    list.add(Box::new(Sphere::new(
+        vec3!(0., -1000., 0.),
+        1000.,
+        Rc::new(Lambertian::new(vec3!(0.5, 0.5, 0.5))),
+    )));
+
+    let flakes = Principled {
+        metallic: 0.7,
+        roughness: 0.45,
+        ..Principled::new(vec3!(0.6, 0.05, 0.05))
+    };
+    let car_paint = Coated::new(Rc::new(flakes), 1.5);
+
+    let wood = Lambertian::textured(Rc::new(Checker::new(
+        vec3!(0.45, 0.25, 0.1),
+        vec3!(0.3, 0.15, 0.05),
+        0.1,
+    )));
+    let varnish = Coated::new(Rc::new(wood), 1.55).with_tint(vec3!(0.9, 0.75, 0.5));
+
+    let blend = Mix::textured(
+        Rc::new(Lambertian::new(vec3!(0.1, 0.3, 0.6))),
+        Rc::new(Metal::new(vec3!(0.9, 0.9, 0.9), 0.05)),
+        Rc::new(Checker::new(vec3!(0.), vec3!(1.), 0.25)),
+    );
+    let half = Mix::new(
+        Rc::new(Lambertian::new(vec3!(0.8, 0.8, 0.2))),
+        Rc::new(Dielectric::new(1.5)),
+        0.5,
+    );
+
+    list.add(Box::new(Sphere::new(vec3!(0., 1., -3.), 1., Rc::new(car_paint))));
+    list.add(Box::new(Sphere::new(vec3!(0., 1., -0.9), 1., Rc::new(varnish))));
+    list.add(Box::new(Sphere::new(vec3!(0., 1., 1.2), 1., Rc::new(blend))));
+    list.add(Box::new(Sphere::new(vec3!(0., 0.5, 2.9), 0.5, Rc::new(half))));
+
+    list
+}
+
+/// A tangent space normal map of a grid of hemispherical dimples.
+fn dimples(size: usize, cells: usize) -> Image {
+    let mut image = Image::new(size, size);
+    let cell = size as f32 / cells as f32;
+
+    for y in 0..size {
+        for x in 0..size {
+            let dx = 2. * ((x as f32 + 0.5) % cell) / cell - 1.;
+            let dy = 2. * ((y as f32 + 0.5) % cell) / cell - 1.;
+            let r2 = (dx * dx + dy * dy) / 0.64;
+
+            // Image rows run top to bottom while `v` runs bottom to top.
+            let normal = if r2 < 1. {
+                vec3!(dx / 0.8, -dy / 0.8, (1. - r2).sqrt())
+            } else {
+                vec3!(0., 0., 1.)
+            };
+            image.set(x, y, 0.5 * (normal + vec3!(1.)));
+        }
+    }
+
+    image
+}
+
+/// A height map of concentric ripples.
+fn ripples(size: usize, waves: f32) -> Image {
+    let mut image = Image::new(size, size);
+
+    for y in 0..size {
+        for x in 0..size {
+            let dx = x as f32 / size as f32 - 0.5;
+            let dy = y as f32 / size as f32 - 0.5;
+            let r = (dx * dx + dy * dy).sqrt();
+            image.set(x, y, vec3!(0.5 + 0.5 * (2. * std::f32::consts::PI * waves * r).cos()));
+        }
+    }
+
+    image
+}
+
+/// Normal and bump mapped surfaces. `normal_map` replaces the generated
+/// dimple map on the metal sphere.
+pub fn surface_detail(normal_map: Option<Image>) -> HittableList {
+    let mut list = HittableList::new();
+
+    let tiles = Rc::new(Lambertian::textured(Rc::new(Checker::new(
+        vec3!(0.6, 0.6, 0.6),
+        vec3!(0.4, 0.4, 0.4),
+        1.,
+    ))));
+    let rippled_floor: Rc<dyn Material> = Rc::new(BumpMapped::new(
+        tiles,
+        Rc::new(ImageTexture::new(Rc::new(ripples(1024, 24.)))),
+        0.1,
+    ));
+
+    let corners = [
+        vec3!(-8., 0., -8.),
+        vec3!(8., 0., -8.),
+        vec3!(8., 0., 8.),
+        vec3!(-8., 0., 8.),
+    ];
+    list.add(Box::new(Triangle::new(
+        [corners[0], corners[3], corners[2]],
+        [(0., 0.), (0., 1.), (1., 1.)],
+        Rc::clone(&rippled_floor),
+    )));
+    list.add(Box::new(Triangle::new(
+        [corners[0], corners[2], corners[1]],
+        [(0., 0.), (1., 1.), (1., 0.)],
+        rippled_floor,
+    )));
+
+    let map = normal_map.unwrap_or_else(|| dimples(512, 16));
+    let hammered = Principled {
+        metallic: 1.,
+        roughness: 0.25,
+        ..Principled::new(vec3!(0.9, 0.7, 0.5))
+    };
+    list.add(Box::new(Sphere::new(
+        vec3!(0., 1., -1.2),
+        1.,
+        Rc::new(NormalMapped::new(
+            Rc::new(hammered),
+            Rc::new(ImageTexture::new(Rc::new(map))),
+        )
+        .with_strength(0.8)),
+    )));
+
+    let stucco = BumpMapped::new(
+        Rc::new(Lambertian::new(vec3!(0.7, 0.3, 0.2))),
+        Rc::new(ImageTexture::new(Rc::new(ripples(256, 40.)))),
+        0.005,
+    );
+    list.add(Box::new(Sphere::new(vec3!(0., 1., 1.2), 1., Rc::new(stucco))));
+
+    list
+}
+
+/// An opacity mask of a diamond lattice, like a garden trellis.
+fn lattice(size: usize, cells: f32, width: f32) -> Image {
+    let mut image = Image::new(size, size);
+
+    for y in 0..size {
+        for x in 0..size {
+            let u = x as f32 / size as f32 * cells;
+            let v = y as f32 / size as f32 * cells;
+            let a = (u + v).fract();
+            let b = (u - v).rem_euclid(1.);
+            let bar = a < width || b < width;
+            image.set(x, y, vec3!(if bar { 1. } else { 0. }));
+        }
+    }
+
+    image
+}
+
+/// An opacity mask of a single leaf.
+fn leaf(size: usize) -> Image {
+    let mut image = Image::new(size, size);
+
+    for y in 0..size {
+        for x in 0..size {
+            let u = 2. * x as f32 / size as f32 - 1.;
+            let v = 2. * y as f32 / size as f32 - 1.;
+            let half_width = 0.45 * (1. - v * v);
+            let inside = u.abs() < half_width || (u.abs() < 0.03 && v > 0.);
+            image.set(x, y, vec3!(if inside { 1. } else { 0. }));
+        }
+    }
+
+    image
+}
+
+/// Alpha masked cards: a trellis and leaves cut out with a binary mask,
+/// and a partially transparent screen.
+pub fn cutout() -> HittableList {
+    let mut list = HittableList::new();
+
+    list.add(Box::new(Sphere::new(
+        vec3!(0., -1000., 0.),
+        1000.,
+        Rc::new(Lambertian::new(vec3!(0.5, 0.5, 0.5))),
+    )));
+    list.add(Box::new(Sphere::new(
+        vec3!(-3., 1., 0.),
+        1.,
+        Rc::new(Lambertian::new(vec3!(0.8, 0.3, 0.1))),
+    )));
+
+    let trellis = AlphaMasked::new(
+        Rc::new(Lambertian::new(vec3!(0.9, 0.9, 0.85))),
+        Rc::new(ImageTexture::new(Rc::new(lattice(512, 6., 0.12)))),
+    )
+    .with_cutoff(0.5);
+    list.add(Box::new(Quad::new(
+        vec3!(0., 0., -3.),
+        vec3!(0., 0., 3.),
+        vec3!(0., 2.5, 0.),
+        Rc::new(trellis),
+    )));
+
+    let leaf: Rc<dyn Material> = Rc::new(
+        AlphaMasked::new(
+            Rc::new(Lambertian::new(vec3!(0.2, 0.5, 0.1))),
+            Rc::new(ImageTexture::new(Rc::new(leaf(256)))),
+        )
+        .with_cutoff(0.5),
+    );
+    for i in 0..5 {
+        let z = -2.6 + 0.55 * i as f32;
+        let y = 0.4 + 0.35 * (i % 2) as f32;
+        list.add(Box::new(Quad::new(
+            vec3!(0.5, y, z),
+            vec3!(0.1, 0., 0.5),
+            vec3!(0.3, 0.5, 0.),
+            Rc::clone(&leaf),
+        )));
+    }
+
+    let screen = AlphaMasked::new(
+        Rc::new(Lambertian::new(vec3!(0.1, 0.1, 0.1))),
+        Rc::new(SolidColor::new(vec3!(0.35))),
+    );
+    list.add(Box::new(Quad::new(
+        vec3!(0., 0., 0.5),
+        vec3!(0., 0., 2.5),
+        vec3!(0., 2., 0.),
+        Rc::new(screen),
+    )));
+
+    list
+}
+
+/// A city block at night, lit by a few thousand windows and street lamps
+/// under a dim sky. Picking lights uniformly for a scene like this leaves
+/// most shadow rays aimed at windows on the far side of town.
+pub fn city(rng: &mut dyn RngCore) -> (HittableList, Vec<Box<dyn Light>>) {
+    let mut list = HittableList::new();
+    let mut lights: Vec<Box<dyn Light>> = vec![];
+
+    let mut night = Image::new(1, 1);
+    night.set(0, 0, vec3!(0.004, 0.006, 0.015));
+    lights.push(Box::new(EnvironmentLight::new(night)));
+
+    list.add(Box::new(Quad::new(
+        vec3!(-50., 0., -50.),
+        vec3!(0., 0., 100.),
+        vec3!(100., 0., 0.),
+        Rc::new(Lambertian::new(vec3!(0.15))),
+    )));
+
+    let up = vec3!(0., 1., 0.);
+    let faces = [
+        vec3!(1., 0., 0.),
+        vec3!(-1., 0., 0.),
+        vec3!(0., 0., 1.),
+        vec3!(0., 0., -1.),
+    ];
+    let (spacing, half) = (0.8, 0.25);
+
+    for i in -7..5 {
+        for k in -6..7 {
+            let center = vec3!(i as f32 * spacing, 0., k as f32 * spacing);
+            let height = 0.3 + 1.5 * rng.gen::<f32>() * rng.gen::<f32>();
+            let wall: Rc<dyn Material> = Rc::new(Lambertian::new(vec3!(0.2 + 0.2 * rng.gen::<f32>())));
+
+            list.add(Box::new(Quad::new(
+                center + vec3!(-half, height, -half),
+                vec3!(0., 0., 2. * half),
+                vec3!(2. * half, 0., 0.),
+                Rc::clone(&wall),
+            )));
+
+            for n in faces.iter() {
+                let across = up.cross(n);
+                let face = center + half * n;
+
+                list.add(Box::new(Quad::new(
+                    face - half * across,
+                    2. * half * across,
+                    vec3!(0., height, 0.),
+                    Rc::clone(&wall),
+                )));
+
+                let mut floor = 0.05;
+                while floor + 0.1 < height {
+                    for column in 0..3 {
+                        if rng.gen::<f32>() > 0.35 {
+                            continue;
+                        }
+
+                        let offset = -half + (column as f32 + 0.5) * (2. * half / 3.) - 0.04;
+                        let glow = vec3!(1., 0.75 + 0.2 * rng.gen::<f32>(), 0.4 + 0.4 * rng.gen::<f32>());
+                        let corner = face + 0.003 * n + offset * across + vec3!(0., floor, 0.);
+
+                        add_area_light(&mut list, &mut lights, 3. * glow, |material| {
+                            Quad::new(corner, 0.08 * across, vec3!(0., 0.06, 0.), material)
+                        });
+                    }
+                    floor += 0.12;
+                }
+            }
+
+            // A street lamp at each crossing.
+            let lamp = center + vec3!(spacing / 2., 0.25, spacing / 2.);
+            add_area_light(&mut list, &mut lights, vec3!(60., 35., 12.), |material| {
+                Sphere::new(lamp, 0.02, material)
+            });
+        }
+    }
+
+    (list, lights)
+}
+
+/// A closed room lit only through a small hole in the ceiling, with a glass
+/// ball beneath it that focuses the light into a caustic on the floor.
+pub fn interior() -> (HittableList, Vec<Box<dyn Light>>) {
+    let mut list = HittableList::new();
+    let mut lights: Vec<Box<dyn Light>> = vec![];
+
+    let white: Rc<dyn Material> = Rc::new(Lambertian::new(vec3!(0.7)));
+    let red: Rc<dyn Material> = Rc::new(Lambertian::new(vec3!(0.6, 0.1, 0.1)));
+    let green: Rc<dyn Material> = Rc::new(Lambertian::new(vec3!(0.15, 0.5, 0.15)));
+
+    let (x0, x1, y0, y1, z0, z1) = (-4., 16., -1., 3., -5., 5.);
+    let walls = [
+        (vec3!(x0, y0, z0), vec3!(x1 - x0, 0., 0.), vec3!(0., 0., z1 - z0), &white),
+        (vec3!(x0, y0, z0), vec3!(0., y1 - y0, 0.), vec3!(0., 0., z1 - z0), &white),
+        (vec3!(x1, y0, z0), vec3!(0., y1 - y0, 0.), vec3!(0., 0., z1 - z0), &white),
+        (vec3!(x0, y0, z0), vec3!(x1 - x0, 0., 0.), vec3!(0., y1 - y0, 0.), &red),
+        (vec3!(x0, y0, z1), vec3!(x1 - x0, 0., 0.), vec3!(0., y1 - y0, 0.), &green),
+    ];
+    for (q, u, v, material) in walls.iter() {
+        list.add(Box::new(Quad::new(*q, *u, *v, Rc::clone(material))));
+    }
+
+    // The ceiling, around a hole from `hole.0` to `hole.1` in x and z.
+    let hole = (vec3!(-2.6, y1, -0.3), vec3!(-2., y1, 0.3));
+    let ceiling = [
+        (vec3!(x0, y1, z0), vec3!(hole.0.x - x0, 0., 0.), vec3!(0., 0., z1 - z0)),
+        (vec3!(hole.1.x, y1, z0), vec3!(x1 - hole.1.x, 0., 0.), vec3!(0., 0., z1 - z0)),
+        (vec3!(hole.0.x, y1, z0), vec3!(hole.1.x - hole.0.x, 0., 0.), vec3!(0., 0., hole.0.z - z0)),
+        (vec3!(hole.0.x, y1, hole.1.z), vec3!(hole.1.x - hole.0.x, 0., 0.), vec3!(0., 0., z1 - hole.1.z)),
+    ];
+    for (q, u, v) in ceiling.iter() {
+        list.add(Box::new(Quad::new(*q, *u, *v, Rc::clone(&white))));
+    }
+
+    add_area_light(&mut list, &mut lights, vec3!(40., 36., 30.), |material| {
+        Quad::new(vec3!(-2.8, y1 + 0.3, -0.5), vec3!(1., 0., 0.), vec3!(0., 0., 1.), material)
+    });
+
+    list.add(Box::new(Sphere::new(vec3!(-1.5, -0.2, 0.), 0.8, Rc::new(Dielectric::new(1.5)))));
+    list.add(Box::new(Sphere::new(
+        vec3!(0.5, -0.4, 1.6),
+        0.6,
+        Rc::new(Metal::new(vec3!(0.8, 0.7, 0.5), 0.05)),
+    )));
+    list.add(Box::new(Sphere::new(vec3!(0.8, -0.5, -1.5), 0.5, Rc::clone(&red))));
+
+    (list, lights)
+}
@@ -0,0 +1,330 @@
+use rand::{Rng, RngCore};
+use std::f32::consts::PI;
+
+use crate::light::{Light, LightSample};
+use crate::objects::random_unit_vector;
+use crate::onb::Onb;
+use crate::spectrum::xyz_to_rgb;
+use crate::vec3::Vec3;
+
+/// Scene units per kcd/m², chosen so that a white surface facing the noon
+/// sun comes out a little below full brightness.
+const LUMINANCE_SCALE: f32 = 0.025;
+
+/// The illuminance of sunlight above the atmosphere, in klx.
+const SOLAR_ILLUMINANCE: f32 = 128.;
+
+/// The direction toward a sun at `elevation` degrees above the horizon and
+/// `azimuth` degrees clockwise from `-z` when seen from above.
+pub fn sun_direction(elevation: f32, azimuth: f32) -> Vec3 {
+    let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+    vec3!(
+        elevation.cos() * azimuth.sin(),
+        elevation.sin(),
+        -elevation.cos() * azimuth.cos()
+    )
+}
+
+/// The white to blue gradient that lights scenes without any other light at
+/// infinity.
+pub struct GradientSky;
+
+impl Light for GradientSky {
+    fn sample(&self, p: &Vec3, rng: &mut dyn RngCore) -> Option<LightSample> {
+        let wi = random_unit_vector(rng);
+
+        Some(LightSample {
+            wi,
+            distance: f32::INFINITY,
+            normal: vec3!(0.),
+            radiance: self.environment(&wi),
+            pdf: self.pdf(p, &wi),
+        })
+    }
+
+    fn pdf(&self, _p: &Vec3, _wi: &Vec3) -> f32 {
+        1. / (4. * PI)
+    }
+
+    fn environment(&self, direction: &Vec3) -> Vec3 {
+        let t = 0.5 * (direction.y() + 1.);
+        (1. - t) * vec3!(1.) + t * vec3!(0.5, 0.7, 1.0)
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+}
+
+/// The coefficients of the Perez et al. (1993) sky luminance distribution.
+struct Perez([f32; 5]);
+
+impl Perez {
+    fn eval(&self, cos_theta: f32, gamma: f32) -> f32 {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+        (1. + a * (b / cos_theta.max(0.01)).exp()) * (1. + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+/// The analytic daylight sky of Preetham, Shirley and Smits (1999), with a
+/// flat diffuse ground below the horizon.
+pub struct PreethamSky {
+    sun: Vec3,
+    perez: [Perez; 3],
+    /// Luminance and chromaticity at the zenith, divided by the Perez
+    /// function there so they can be scaled directly.
+    zenith: [f32; 3],
+    ground: Vec3,
+}
+
+impl PreethamSky {
+    /// `turbidity` ranges from about 2 for a clear sky to 10 for haze, and
+    /// `ground_albedo` is the color of the ground below the horizon.
+    pub fn new(sun: Vec3, turbidity: f32, ground_albedo: Vec3) -> PreethamSky {
+        let t = turbidity;
+        let sun = sun.unit_vector();
+        // The model only covers suns at or above the horizon.
+        let theta_s = sun.y.clamp(0., 1.).acos();
+
+        let perez = [
+            Perez([
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ]),
+            Perez([
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ]),
+            Perez([
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ]),
+        ];
+
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_s);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let cubic = |c: [f32; 4]| c[0] * theta_s.powi(3) + c[1] * theta_s.powi(2) + c[2] * theta_s + c[3];
+        let x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let zenith = [
+            luminance / perez[0].eval(1., theta_s),
+            x / perez[1].eval(1., theta_s),
+            y / perez[2].eval(1., theta_s),
+        ];
+
+        let mut sky = PreethamSky {
+            sun,
+            perez,
+            zenith,
+            ground: vec3!(0.),
+        };
+
+        // Light the ground with the sun and the sky above it.
+        let irradiance = sun_irradiance(&sun, turbidity) * sun.y.max(0.) + sky.irradiance();
+        sky.ground = ground_albedo * irradiance / PI;
+        sky
+    }
+
+    fn sky(&self, direction: &Vec3) -> Vec3 {
+        let cos_theta = direction.y;
+        let gamma = direction.dot(&self.sun).clamp(-1., 1.).acos();
+
+        let luminance = self.zenith[0] * self.perez[0].eval(cos_theta, gamma);
+        let x = self.zenith[1] * self.perez[1].eval(cos_theta, gamma);
+        let y = self.zenith[2] * self.perez[2].eval(cos_theta, gamma);
+
+        let xyz = vec3!(x / y, 1., (1. - x - y) / y) * luminance * LUMINANCE_SCALE;
+        xyz_to_rgb(&xyz).map(|c| c.max(0.))
+    }
+
+    /// The irradiance from the sky onto an upward facing surface.
+    fn irradiance(&self) -> Vec3 {
+        let (rings, segments) = (32, 64);
+        let mut sum = vec3!(0.);
+
+        for i in 0..rings {
+            let cos_theta = (i as f32 + 0.5) / rings as f32;
+            let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+
+            for j in 0..segments {
+                let phi = 2. * PI * (j as f32 + 0.5) / segments as f32;
+                let direction = vec3!(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                sum += self.sky(&direction) * cos_theta;
+            }
+        }
+
+        sum * (2. * PI / (rings * segments) as f32)
+    }
+}
+
+impl Light for PreethamSky {
+    fn sample(&self, p: &Vec3, rng: &mut dyn RngCore) -> Option<LightSample> {
+        let wi = random_unit_vector(rng);
+
+        Some(LightSample {
+            wi,
+            distance: f32::INFINITY,
+            normal: vec3!(0.),
+            radiance: self.environment(&wi),
+            pdf: self.pdf(p, &wi),
+        })
+    }
+
+    fn pdf(&self, _p: &Vec3, _wi: &Vec3) -> f32 {
+        1. / (4. * PI)
+    }
+
+    fn environment(&self, direction: &Vec3) -> Vec3 {
+        if direction.y > 0. {
+            self.sky(direction)
+        } else {
+            self.ground
+        }
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+}
+
+/// The irradiance from the sun onto a surface facing it, attenuated by
+/// Rayleigh and aerosol scattering along its way through the atmosphere.
+fn sun_irradiance(sun: &Vec3, turbidity: f32) -> Vec3 {
+    if sun.y <= 0. {
+        return vec3!(0.);
+    }
+
+    // Relative optical air mass (Kasten and Young 1989).
+    let zenith = sun.y.acos().to_degrees();
+    let air_mass = 1. / (sun.y + 0.50572 * (96.07995 - zenith).powf(-1.6364));
+    let beta = 0.04608 * turbidity - 0.04586;
+
+    // Red, green and blue wavelengths in micrometres.
+    let transmittance = vec3!(0.68, 0.55, 0.44).map(|lambda| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let aerosol = beta * lambda.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp()
+    });
+
+    transmittance * SOLAR_ILLUMINANCE * LUMINANCE_SCALE
+}
+
+/// A distant sun of a given angular diameter, which casts soft shadows with
+/// the width of its penumbra growing with the distance to the occluder.
+pub struct SunLight {
+    frame: Onb,
+    cos_max: f32,
+    radiance: Vec3,
+}
+
+impl SunLight {
+    /// Sunlight of the strength and color the Preetham sky would give it.
+    pub fn new(direction: Vec3, turbidity: f32, angular_diameter: f32) -> SunLight {
+        let direction = direction.unit_vector();
+        let cos_max = (angular_diameter.to_radians() / 2.).cos();
+        let solid_angle = 2. * PI * (1. - cos_max);
+
+        SunLight {
+            frame: Onb::from_w(&direction),
+            cos_max,
+            radiance: sun_irradiance(&direction, turbidity) / solid_angle,
+        }
+    }
+}
+
+impl Light for SunLight {
+    fn sample(&self, _p: &Vec3, rng: &mut dyn RngCore) -> Option<LightSample> {
+        let cos_theta = 1. - rng.gen::<f32>() * (1. - self.cos_max);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * rng.gen::<f32>();
+        let wi = self.frame.to_world(&vec3!(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+
+        Some(LightSample {
+            wi,
+            distance: f32::INFINITY,
+            normal: vec3!(0.),
+            radiance: self.radiance,
+            pdf: 1. / (2. * PI * (1. - self.cos_max)),
+        })
+    }
+
+    fn pdf(&self, _p: &Vec3, wi: &Vec3) -> f32 {
+        if wi.dot(&self.frame.w) >= self.cos_max {
+            1. / (2. * PI * (1. - self.cos_max))
+        } else {
+            0.
+        }
+    }
+
+    fn environment(&self, direction: &Vec3) -> Vec3 {
+        if direction.dot(&self.frame.w) >= self.cos_max {
+            self.radiance
+        } else {
+            vec3!(0.)
+        }
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_sun_and_sky_radiance() {
+        let noon = sun_direction(90., 0.);
+        let sky = PreethamSky::new(noon, 3., vec3!(0.3));
+        let sun = SunLight::new(noon, 3., 0.53);
+
+        // A white surface facing the noon sun comes out a little below full
+        // brightness, and the sun's disk adds up to its irradiance.
+        let white = sun_irradiance(&noon, 3.) / PI;
+        assert!(white.x < 1. && white.z > 0.5, "{:?}", white);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let n = 1000;
+        let mut irradiance = vec3!(0.);
+        for _ in 0..n {
+            let sample = sun.sample(&vec3!(0.), &mut rng).unwrap();
+            assert!((sample.pdf - sun.pdf(&vec3!(0.), &sample.wi)).abs() < 1e-3 * sample.pdf);
+            irradiance += sample.radiance * sample.wi.dot(&noon) / sample.pdf / n as f32;
+        }
+        assert!((irradiance - sun_irradiance(&noon, 3.)).length() < 1e-3, "{:?}", irradiance);
+
+        // The clear sky is blue, and lights the ground less than the sun
+        // does; a hazier one is brighter and paler.
+        let side = vec3!(1., 0.5, 0.).unit_vector();
+        let clear = sky.environment(&side);
+        let hazy = PreethamSky::new(noon, 8., vec3!(0.3)).environment(&side);
+        assert!(clear.z > 2. * clear.x, "{:?}", clear);
+        assert!(hazy.y > clear.y && hazy.z / hazy.x < clear.z / clear.x, "{:?}", hazy);
+        assert!(sky.irradiance().y < 0.5 * sun_irradiance(&noon, 3.).y);
+
+        // Low suns are dim and red, and those below the horizon give no
+        // light.
+        let low = sun_irradiance(&sun_direction(5., 0.), 3.);
+        assert!(low.x > low.y && low.y > low.z && low.y < 0.1 * sun_irradiance(&noon, 3.).y, "{:?}", low);
+        assert_eq!(sun_irradiance(&sun_direction(-5., 0.), 3.), vec3!(0.));
+    }
+}
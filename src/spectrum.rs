@@ -0,0 +1,287 @@
+use std::ops;
+use std::sync::OnceLock;
+
+use crate::vec3::Vec3;
+
+/// The range of wavelengths, in nanometres, sampled in spectral mode.
+pub const LAMBDA_MIN: f32 = 380.;
+pub const LAMBDA_MAX: f32 = 720.;
+
+/// The number of wavelengths carried along each path.
+pub const N_WAVELENGTHS: usize = 4;
+
+/// The spectra used by Smits' (1999) RGB to spectrum conversion, tabulated
+/// over ten evenly spaced bins between `LAMBDA_MIN` and `LAMBDA_MAX`.
+const SMITS_WHITE: [f32; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f32; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f32; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f32; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f32; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f32; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f32; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+fn smits(table: &[f32; 10], lambda: f32) -> f32 {
+    let x = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10. - 0.5;
+    let x = x.clamp(0., 9.);
+    let i = (x as usize).min(8);
+    let t = x - i as f32;
+
+    table[i] * (1. - t) + table[i + 1] * t
+}
+
+/// Evaluates the smooth spectrum matching `rgb` at `lambda`.
+pub fn rgb_to_spectrum(rgb: &Vec3, lambda: f32) -> f32 {
+    let (r, g, b) = (rgb.r(), rgb.g(), rgb.b());
+    let s = |table| smits(table, lambda);
+
+    if r <= g && r <= b {
+        if g <= b {
+            r * s(&SMITS_WHITE) + (g - r) * s(&SMITS_CYAN) + (b - g) * s(&SMITS_BLUE)
+        } else {
+            r * s(&SMITS_WHITE) + (b - r) * s(&SMITS_CYAN) + (g - b) * s(&SMITS_GREEN)
+        }
+    } else if g <= r && g <= b {
+        if r <= b {
+            g * s(&SMITS_WHITE) + (r - g) * s(&SMITS_MAGENTA) + (b - r) * s(&SMITS_BLUE)
+        } else {
+            g * s(&SMITS_WHITE) + (b - g) * s(&SMITS_MAGENTA) + (r - b) * s(&SMITS_RED)
+        }
+    } else if r <= g {
+        b * s(&SMITS_WHITE) + (r - b) * s(&SMITS_YELLOW) + (g - r) * s(&SMITS_GREEN)
+    } else {
+        b * s(&SMITS_WHITE) + (g - b) * s(&SMITS_YELLOW) + (r - g) * s(&SMITS_RED)
+    }
+}
+
+fn lobe(x: f32, mu: f32, sigma_low: f32, sigma_high: f32) -> f32 {
+    let t = (x - mu) / if x < mu { sigma_low } else { sigma_high };
+    (-0.5 * t * t).exp()
+}
+
+/// The CIE 1931 colour matching functions, using the multi-lobe fit of
+/// Wyman, Sloan and Shirley (2013).
+pub fn cie_xyz(lambda: f32) -> Vec3 {
+    vec3!(
+        1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8)
+    )
+}
+
+/// Converts CIE XYZ to linear sRGB.
+pub fn xyz_to_rgb(xyz: &Vec3) -> Vec3 {
+    vec3!(
+        3.240_454 * xyz.x - 1.537_138 * xyz.y - 0.498_531 * xyz.z,
+        -0.969_266 * xyz.x + 1.876_011 * xyz.y + 0.041_556 * xyz.z,
+        0.055_643 * xyz.x - 0.204_026 * xyz.y + 1.057_225 * xyz.z
+    )
+}
+
+/// Per channel scale that maps a constant unit spectrum to RGB white, so
+/// that spectral and RGB renders of the same scene agree.
+fn white_balance() -> Vec3 {
+    static WHITE: OnceLock<Vec3> = OnceLock::new();
+
+    *WHITE.get_or_init(|| {
+        let steps = 1000;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f32;
+        let mut xyz = vec3!(0.);
+
+        for i in 0..steps {
+            let lambda = LAMBDA_MIN + (i as f32 + 0.5) * step;
+            xyz += cie_xyz(lambda) * step;
+        }
+
+        1. / xyz_to_rgb(&xyz)
+    })
+}
+
+/// The wavelengths carried by a single path. The first is the hero
+/// wavelength; the rest are evenly rotated from it across the range.
+#[derive(Copy, Clone, Debug)]
+pub struct SampledWavelengths {
+    pub lambda: [f32; N_WAVELENGTHS],
+    pub pdf: [f32; N_WAVELENGTHS],
+}
+
+impl SampledWavelengths {
+    pub fn sample_uniform(u: f32) -> SampledWavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.; N_WAVELENGTHS];
+
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f32 / N_WAVELENGTHS as f32).fract();
+            *l = LAMBDA_MIN + offset * range;
+        }
+
+        SampledWavelengths {
+            lambda,
+            pdf: [1. / range; N_WAVELENGTHS],
+        }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    /// Drops every wavelength but the hero, for when the path has taken a
+    /// wavelength dependent direction such as refraction through a
+    /// dispersive medium.
+    pub fn terminate_secondary(&mut self) {
+        if self.pdf[1..].iter().all(|p| *p == 0.) {
+            return;
+        }
+
+        for pdf in self.pdf[1..].iter_mut() {
+            *pdf = 0.;
+        }
+        self.pdf[0] /= N_WAVELENGTHS as f32;
+    }
+
+    /// Upsamples an RGB value at each of the wavelengths.
+    pub fn spectrum_of(&self, rgb: &Vec3) -> Spectrum {
+        let mut values = [0.; N_WAVELENGTHS];
+        for (v, l) in values.iter_mut().zip(self.lambda.iter()) {
+            *v = rgb_to_spectrum(rgb, *l);
+        }
+        Spectrum(values)
+    }
+
+    /// Converts radiance sampled at these wavelengths back to linear sRGB.
+    pub fn to_rgb(self, values: &Spectrum) -> Vec3 {
+        let mut xyz = vec3!(0.);
+
+        for ((lambda, pdf), value) in self.lambda.iter().zip(self.pdf.iter()).zip(values.0.iter()) {
+            if *pdf > 0. {
+                xyz += cie_xyz(*lambda) * (value / pdf);
+            }
+        }
+
+        xyz_to_rgb(&(xyz / N_WAVELENGTHS as f32)) * white_balance()
+    }
+}
+
+/// Radiance or throughput carried along a path, either at the sampled
+/// wavelengths or, when rendering in RGB, in the first three entries.
+#[derive(Copy, Clone, Debug)]
+pub struct Spectrum(pub [f32; N_WAVELENGTHS]);
+
+impl Spectrum {
+    pub fn splat(value: f32) -> Spectrum {
+        Spectrum([value; N_WAVELENGTHS])
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.0.iter().all(|&value| value == 0.)
+    }
+}
+
+impl ops::Add for Spectrum {
+    type Output = Spectrum;
+
+    fn add(mut self, other: Spectrum) -> Spectrum {
+        self += other;
+        self
+    }
+}
+
+impl ops::AddAssign for Spectrum {
+    fn add_assign(&mut self, other: Spectrum) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+    }
+}
+
+impl ops::Mul for Spectrum {
+    type Output = Spectrum;
+
+    fn mul(mut self, other: Spectrum) -> Spectrum {
+        self *= other;
+        self
+    }
+}
+
+impl ops::MulAssign for Spectrum {
+    fn mul_assign(&mut self, other: Spectrum) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a *= b;
+        }
+    }
+}
+
+/// How a path carries color: as plain RGB, or at a handful of sampled
+/// wavelengths so that dispersive materials can split light.
+#[derive(Copy, Clone, Debug)]
+pub enum Channels {
+    Rgb,
+    Spectral(SampledWavelengths),
+}
+
+impl Channels {
+    /// The wavelength that wavelength dependent materials should follow.
+    pub fn hero(&self) -> Option<f32> {
+        match self {
+            Channels::Rgb => None,
+            Channels::Spectral(wavelengths) => Some(wavelengths.hero()),
+        }
+    }
+
+    pub fn terminate_secondary(&mut self) {
+        if let Channels::Spectral(wavelengths) = self {
+            wavelengths.terminate_secondary();
+        }
+    }
+
+    pub fn spectrum_of(&self, rgb: &Vec3) -> Spectrum {
+        match self {
+            Channels::Rgb => {
+                let mut values = [0.; N_WAVELENGTHS];
+                values[..3].copy_from_slice(&[rgb.r(), rgb.g(), rgb.b()]);
+                Spectrum(values)
+            }
+            Channels::Spectral(wavelengths) => wavelengths.spectrum_of(rgb),
+        }
+    }
+
+    pub fn to_rgb(self, values: &Spectrum) -> Vec3 {
+        match self {
+            Channels::Rgb => vec3!(values.0[0], values.0[1], values.0[2]),
+            Channels::Spectral(wavelengths) => wavelengths.to_rgb(values),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_white_round_trip() {
+        let mut sum = vec3!(0.);
+        let n = 1000;
+
+        for i in 0..n {
+            let wavelengths = SampledWavelengths::sample_uniform((i as f32 + 0.5) / n as f32);
+            sum += wavelengths.to_rgb(&wavelengths.spectrum_of(&vec3!(1.)));
+        }
+
+        let white = sum / n as f32;
+        assert!((white - vec3!(1.)).length() < 0.01);
+    }
+}
@@ -0,0 +1,90 @@
+use std::rc::Rc;
+
+use crate::image::Image;
+use crate::vec3::Vec3;
+
+pub trait Texture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3;
+
+    /// The texture read as a single channel, for weights and masks.
+    fn scalar(&self, u: f32, v: f32, p: &Vec3) -> f32 {
+        let c = self.value(u, v, p);
+        (c.r() + c.g() + c.b()) / 3.
+    }
+}
+
+pub struct SolidColor {
+    color: Vec3,
+}
+
+impl SolidColor {
+    pub fn new(color: Vec3) -> SolidColor {
+        SolidColor { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+/// A 3D checkerboard alternating between two colors every `scale` units.
+pub struct Checker {
+    even: Vec3,
+    odd: Vec3,
+    scale: f32,
+}
+
+impl Checker {
+    pub fn new(even: Vec3, odd: Vec3, scale: f32) -> Checker {
+        Checker { even, odd, scale }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, _u: f32, _v: f32, p: &Vec3) -> Vec3 {
+        let cell = p.map(|x| (x / self.scale).floor());
+        if (cell.x + cell.y + cell.z) as i32 % 2 == 0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+/// An image wrapped over the texture coordinates, with `v` running from the
+/// bottom of the image to the top, and bilinear filtering.
+pub struct ImageTexture {
+    image: Rc<Image>,
+}
+
+impl ImageTexture {
+    pub fn new(image: Rc<Image>) -> ImageTexture {
+        ImageTexture { image }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _p: &Vec3) -> Vec3 {
+        let width = self.image.width();
+        let height = self.image.height();
+        if width == 0 || height == 0 {
+            return vec3!(0.);
+        }
+
+        let x = u.rem_euclid(1.) * width as f32 - 0.5;
+        let y = (1. - v.rem_euclid(1.)) * height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+
+        let texel = |i: f32, j: f32| {
+            let i = (i as i64).rem_euclid(width as i64) as usize;
+            let j = (j as i64).rem_euclid(height as i64) as usize;
+            self.image.get(i, j)
+        };
+
+        (1. - ty) * ((1. - tx) * texel(x0, y0) + tx * texel(x0 + 1., y0))
+            + ty * ((1. - tx) * texel(x0, y0 + 1.) + tx * texel(x0 + 1., y0 + 1.))
+    }
+}
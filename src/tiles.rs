@@ -0,0 +1,231 @@
+use crate::image::Image;
+
+/// A rectangle of pixels, measured from the top left.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    /// The pixels from `x0` to `x1` across and `y0` to `y1` down, not
+    /// including the ends, within a `width` by `height` image.
+    pub fn from_bounds(bounds: [usize; 4], width: usize, height: usize) -> Option<Rect> {
+        let [x0, x1, y0, y1] = bounds;
+        let (x1, y1) = (x1.min(width), y1.min(height));
+        if x0 < x1 && y0 < y1 {
+            Some(Rect::new(x0, y0, x1 - x0, y1 - y0))
+        } else {
+            None
+        }
+    }
+
+    /// The pixels within fractions `x0` to `x1` of the way across and `y0`
+    /// to `y1` of the way down a `width` by `height` image.
+    pub fn from_window(window: [f32; 4], width: usize, height: usize) -> Option<Rect> {
+        let [x0, x1, y0, y1] = window;
+        let across = |f: f32| (f.clamp(0., 1.) * width as f32).ceil() as usize;
+        let down = |f: f32| (f.clamp(0., 1.) * height as f32).ceil() as usize;
+        Rect::from_bounds([across(x0), across(x1), down(y0), down(y1)], width, height)
+    }
+
+    pub fn area(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+
+    /// This rectangle grown by `margin` pixels on every side, within a
+    /// `width` by `height` image.
+    pub fn grow(&self, margin: usize, width: usize, height: usize) -> Rect {
+        let (x, y) = (self.x.saturating_sub(margin), self.y.saturating_sub(margin));
+        let x1 = (self.x + self.width + margin).min(width);
+        let y1 = (self.y + self.height + margin).min(height);
+        Rect::new(x, y, x1 - x, y1 - y)
+    }
+
+    /// The values of this rectangle's pixels, out of `values` over `outer`,
+    /// which contains it. Both are a row at a time from the top.
+    pub fn trim<T: Copy>(&self, outer: &Rect, values: &[T]) -> Vec<T> {
+        (self.y..self.y + self.height)
+            .flat_map(|y| {
+                let start = (y - outer.y) * outer.width + self.x - outer.x;
+                values[start..start + self.width].iter().copied()
+            })
+            .collect()
+    }
+
+    /// Puts `image`, which covers this rectangle, in a black image `width`
+    /// by `height`.
+    pub fn uncrop(&self, image: &Image, width: usize, height: usize) -> Image {
+        let mut full = Image::new(width, height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                full.set(self.x + x, self.y + y, image.get(x, y));
+            }
+        }
+
+        full
+    }
+}
+
+/// The order tiles are rendered in within each pass.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TileOrder {
+    /// A row at a time from the top.
+    Scanline,
+    /// Spiralling out from the middle.
+    Spiral,
+    /// Along a Hilbert curve, which keeps each tile next to the last.
+    Hilbert,
+}
+
+/// Splits `bounds` into tiles `size` pixels square, or smaller at the right
+/// and bottom edges, in `order`.
+pub fn tiles(bounds: &Rect, size: usize, order: TileOrder) -> Vec<Rect> {
+    let size = size.max(1);
+    let (columns, rows) = (bounds.width.div_ceil(size), bounds.height.div_ceil(size));
+    let cells: Vec<(usize, usize)> = match order {
+        TileOrder::Scanline => (0..rows).flat_map(|row| (0..columns).map(move |column| (column, row))).collect(),
+        TileOrder::Spiral => spiral(columns, rows),
+        TileOrder::Hilbert => hilbert(columns, rows),
+    };
+
+    cells
+        .into_iter()
+        .map(|(column, row)| {
+            let (x, y) = (column * size, row * size);
+            Rect::new(
+                bounds.x + x,
+                bounds.y + y,
+                size.min(bounds.width - x),
+                size.min(bounds.height - y),
+            )
+        })
+        .collect()
+}
+
+/// Walks right, down, left and up in ever longer legs from the middle cell,
+/// keeping the cells that are in the grid.
+fn spiral(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let mut cells = Vec::with_capacity(columns * rows);
+    let (mut x, mut y) = (((columns as isize) - 1) / 2, ((rows as isize) - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg = 1;
+    let mut direction = 0;
+
+    while cells.len() < columns * rows {
+        // Each leg length is walked twice, turning after each.
+        for _ in 0..2 {
+            for _ in 0..leg {
+                if x >= 0 && y >= 0 && (x as usize) < columns && (y as usize) < rows {
+                    cells.push((x as usize, y as usize));
+                }
+                let (dx, dy) = directions[direction];
+                x += dx;
+                y += dy;
+            }
+            direction = (direction + 1) % 4;
+        }
+        leg += 1;
+    }
+
+    cells
+}
+
+/// The cells of the Hilbert curve over the smallest power of two square
+/// that covers the grid, keeping those that are in it.
+fn hilbert(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let side = columns.max(rows).next_power_of_two();
+    (0..side * side)
+        .map(|d| hilbert_cell(side, d))
+        .filter(|&(x, y)| x < columns && y < rows)
+        .collect()
+}
+
+/// The cell `d` steps along the Hilbert curve over a `side` by `side` grid.
+fn hilbert_cell(side: usize, mut d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut s = 1;
+    while s < side {
+        let rx = 1 & (d / 2);
+        let ry = 1 & (d ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        d /= 4;
+        s *= 2;
+    }
+
+    (x, y)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tiles_cover_bounds_once() {
+        let bounds = Rect::new(3, 5, 70, 45);
+        let size = 16;
+
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert].iter() {
+            let tiles = tiles(&bounds, size, *order);
+            let mut covered = vec![0; 100 * 100];
+            for tile in tiles.iter() {
+                for y in tile.y..tile.y + tile.height {
+                    for x in tile.x..tile.x + tile.width {
+                        covered[y * 100 + x] += 1;
+                    }
+                }
+            }
+
+            for y in 0..100 {
+                for x in 0..100 {
+                    let inside = (3..73).contains(&x) && (5..50).contains(&y);
+                    assert_eq!(covered[y * 100 + x], usize::from(inside), "{:?} at {}, {}", order, x, y);
+                }
+            }
+
+            match order {
+                TileOrder::Scanline => assert_eq!(tiles[1], Rect::new(19, 5, 16, 16)),
+                TileOrder::Spiral => assert_eq!(tiles[0], Rect::new(3 + 32, 5 + 16, 16, 16)),
+                TileOrder::Hilbert => assert_eq!(tiles[0], Rect::new(3, 5, 16, 16)),
+            }
+        }
+
+        // On a power of two grid, each cell of the Hilbert curve is next to
+        // the last.
+        for pair in hilbert(8, 8).windows(2) {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            assert_eq!((x0 as isize - x1 as isize).abs() + (y0 as isize - y1 as isize).abs(), 1);
+        }
+
+        assert_eq!(Rect::from_window([0.25, 0.5, 0., 1.], 100, 50), Some(Rect::new(25, 0, 25, 50)));
+        assert_eq!(Rect::from_bounds([10, 5, 0, 10], 100, 50), None);
+
+        // A margin stops at the edges of the image, and trimming it takes
+        // back the pixels inside.
+        let padded = bounds.grow(4, 75, 100);
+        assert_eq!(padded, Rect::new(0, 1, 75, 53));
+        let indices: Vec<usize> = (0..padded.area()).collect();
+        let trimmed = bounds.trim(&padded, &indices);
+        assert_eq!(trimmed.len(), bounds.area());
+        assert_eq!((trimmed[0], trimmed[70]), (4 * 75 + 3, 5 * 75 + 3));
+        assert!(padded.contains(74, 53) && !padded.contains(75, 53) && !padded.contains(0, 54));
+    }
+}
@@ -94,6 +94,23 @@ impl Vec3 {
             self.x * other.y - self.y * other.x,
         )
     }
+
+    pub fn min(&self, other: &Vec3) -> Vec3 {
+        vec3!(self.x.min(other.x), self.y.min(other.y), self.z.min(other.z))
+    }
+
+    pub fn max(&self, other: &Vec3) -> Vec3 {
+        vec3!(self.x.max(other.x), self.y.max(other.y), self.z.max(other.z))
+    }
+
+    /// The `x`, `y` or `z` component for an `axis` of 0, 1 or 2.
+    pub fn axis(&self, axis: usize) -> f32 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
 }
 
 impl fmt::Display for Vec3 {